@@ -0,0 +1,85 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+/// Error returned while generating the extension manifest.
+#[derive(Debug)]
+pub enum Error {
+    /// A variable normally set by cargo for build scripts is missing.
+    Env(&'static str),
+    /// Reading a source file or writing the manifest failed.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// `Cargo.toml` or its `[package.metadata.moosync]` table is malformed.
+    Metadata {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// A source file or its `#[moosync_extension]` attribute could not be parsed.
+    Source { path: PathBuf, source: syn::Error },
+    /// More than one type is annotated with `#[moosync_extension]`.
+    DuplicateExtension { first: PathBuf, second: PathBuf },
+    /// The generated manifest doesn't satisfy `schema.json`.
+    Invalid(Vec<String>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Env(var) => write!(
+                f,
+                "{} is not set, manifest generation must run from a build script",
+                var
+            ),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Metadata { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Source { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::DuplicateExtension { first, second } => write!(
+                f,
+                "#[moosync_extension] is used more than once ({} and {})",
+                first.display(),
+                second.display()
+            ),
+            Error::Invalid(problems) => {
+                writeln!(f, "Invalid extension manifest:")?;
+                for problem in problems {
+                    writeln!(f, "  - {}", problem)?;
+                }
+                write!(
+                    f,
+                    "Set the missing fields in [package.metadata.moosync] or in #[moosync_extension(manifest(...))]"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Metadata { source, .. } => Some(source),
+            Error::Source { source, .. } => Some(source),
+            Error::Env(_) | Error::DuplicateExtension { .. } | Error::Invalid(_) => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
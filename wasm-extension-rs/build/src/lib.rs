@@ -0,0 +1,252 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generates the `package.json` manifest of a Rust extension at build time.
+//!
+//! Add this crate as a build dependency and call [`generate`] from `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     if let Err(e) = moosync_edk_build::generate() {
+//!         panic!("{}", e);
+//!     }
+//! }
+//! ```
+//!
+//! `version` is taken from `Cargo.toml`. The other fields are read from the
+//! `[package.metadata.moosync]` table and from the `manifest(...)` argument of
+//! `#[moosync_extension]`, the attribute taking precedence:
+//!
+//! ```toml
+//! [package.metadata.moosync]
+//! name = "moosync.sample.extension"
+//! display-name = "Sample extension"
+//! icon = "assets/icon.svg"
+//! extension-entry = "ext.wasm"
+//! hosts = ["soundcloud.com", "*.soundcloud.com"]
+//!
+//! [package.metadata.moosync.paths]
+//! "{HOME}/Music" = "/music"
+//! ```
+//!
+//! `name` defaults to the package name and `extensionEntry` to the file name of
+//! the compiled library. The result is checked against the manifest schema and the
+//! build fails if a required field, like `icon` or `displayName`, is missing.
+//!
+//! The declared hosts are also passed to the extension, so that
+//! `moosync_edk::http` can reject requests to hosts the manifest doesn't permit.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+mod error;
+mod schema;
+mod source;
+
+pub use error::{Error, Result};
+
+/// Contents of the generated `package.json`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension_entry: Option<String>,
+    pub moosync_extension: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub permissions: Permissions,
+}
+
+/// The `permissions` field of the manifest.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Permissions {
+    pub hosts: Vec<String>,
+    pub paths: BTreeMap<String, String>,
+}
+
+/// Manifest fields set by the extension author, either in `Cargo.toml` or
+/// in `#[moosync_extension(manifest(...))]`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ManifestFields {
+    name: Option<String>,
+    display_name: Option<String>,
+    icon: Option<String>,
+    extension_entry: Option<String>,
+    hosts: Option<Vec<String>>,
+    paths: Option<BTreeMap<String, String>>,
+}
+
+impl From<moosync_edk_manifest::ManifestFields> for ManifestFields {
+    fn from(fields: moosync_edk_manifest::ManifestFields) -> Self {
+        ManifestFields {
+            name: fields.name,
+            display_name: fields.display_name,
+            icon: fields.icon,
+            extension_entry: fields.extension_entry,
+            hosts: fields.hosts,
+            paths: fields.paths,
+        }
+    }
+}
+
+impl ManifestFields {
+    /// Fills the fields not set on `self` from `other`.
+    fn or(self, other: ManifestFields) -> ManifestFields {
+        ManifestFields {
+            name: self.name.or(other.name),
+            display_name: self.display_name.or(other.display_name),
+            icon: self.icon.or(other.icon),
+            extension_entry: self.extension_entry.or(other.extension_entry),
+            hosts: self.hosts.or(other.hosts),
+            paths: self.paths.or(other.paths),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoToml {
+    package: Option<CargoPackage>,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage {
+    metadata: Option<CargoMetadata>,
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    moosync: Option<ManifestFields>,
+}
+
+impl Manifest {
+    /// Builds the manifest of the package being compiled, using the variables
+    /// cargo passes to build scripts.
+    pub fn from_env() -> Result<Manifest> {
+        let manifest_dir = env_var("CARGO_MANIFEST_DIR")?;
+        let package_name = env_var("CARGO_PKG_NAME")?;
+        let version = env_var("CARGO_PKG_VERSION")?;
+        Manifest::from_package(Path::new(&manifest_dir), &package_name, &version)
+    }
+
+    /// Builds the manifest of the package at `manifest_dir`.
+    pub fn from_package(
+        manifest_dir: &Path,
+        package_name: &str,
+        version: &str,
+    ) -> Result<Manifest> {
+        let from_attr = source::find_manifest_fields(&manifest_dir.join("src"))?;
+        let from_cargo = read_metadata(&manifest_dir.join("Cargo.toml"))?;
+        let fields = from_attr.unwrap_or_default().or(from_cargo);
+
+        Ok(Manifest {
+            name: Some(fields.name.unwrap_or_else(|| package_name.to_string())),
+            version: Some(version.to_string()),
+            icon: fields.icon,
+            extension_entry: Some(
+                fields
+                    .extension_entry
+                    .unwrap_or_else(|| format!("{}.wasm", package_name.replace('-', "_"))),
+            ),
+            moosync_extension: true,
+            display_name: fields.display_name,
+            permissions: Permissions {
+                hosts: fields.hosts.unwrap_or_default(),
+                paths: fields.paths.unwrap_or_default(),
+            },
+        })
+    }
+
+    /// Checks the manifest against the manifest schema.
+    pub fn validate(&self) -> Result<()> {
+        let value = serde_json::to_value(self).expect("manifest is serializable");
+        let problems = schema::validate(&value);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(problems))
+        }
+    }
+
+    /// Writes the manifest to `path`, leaving the file untouched if it is
+    /// already up to date.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut contents = serde_json::to_string_pretty(self).expect("manifest is serializable");
+        contents.push('\n');
+
+        if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+            return Ok(());
+        }
+        fs::write(path, contents).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Generates, validates and writes `package.json` next to `Cargo.toml`.
+///
+/// Meant to be called from a build script. Returns the path of the written manifest.
+pub fn generate() -> Result<PathBuf> {
+    let manifest_dir = PathBuf::from(env_var("CARGO_MANIFEST_DIR")?);
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=src");
+
+    let manifest = Manifest::from_env()?;
+    manifest.validate()?;
+
+    let path = manifest_dir.join("package.json");
+    manifest.write(&path)?;
+
+    // Read by the `init` generated by #[moosync_extension] to check requests
+    // against the declared hosts.
+    println!(
+        "cargo:rustc-env=MOOSYNC_EDK_ALLOWED_HOSTS={}",
+        manifest.permissions.hosts.join(" ")
+    );
+    Ok(path)
+}
+
+fn env_var(name: &'static str) -> Result<String> {
+    env::var(name).map_err(|_| Error::Env(name))
+}
+
+fn read_metadata(path: &Path) -> Result<ManifestFields> {
+    let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let cargo: CargoToml = toml::from_str(&contents).map_err(|source| Error::Metadata {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(cargo
+        .package
+        .and_then(|package| package.metadata)
+        .and_then(|metadata| metadata.moosync)
+        .unwrap_or_default())
+}
@@ -0,0 +1,194 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validation against the manifest `schema.json` shared by all SDKs.
+//!
+//! The crate ships its own copy of the schema so that it can be packaged on its
+//! own. It must be kept identical to the one at the root of the repository.
+//!
+//! Only the keywords used by that schema are understood: `type`, `minLength`,
+//! `properties`, `required` and `items`.
+
+use serde_json::Value;
+
+const SCHEMA: &str = include_str!("../schema.json");
+
+/// Returns every way in which `manifest` violates the schema.
+pub(crate) fn validate(manifest: &Value) -> Vec<String> {
+    let schema: Value = serde_json::from_str(SCHEMA).expect("schema.json is valid JSON");
+    let mut problems = vec![];
+    check(&schema, manifest, "", &mut problems);
+    problems
+}
+
+fn check(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let display_path = if path.is_empty() { "manifest" } else { path };
+
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if !has_type(value, expected) {
+            problems.push(format!(
+                "`{}` must be of type {}, found {}",
+                display_path, expected, value
+            ));
+            return;
+        }
+    }
+
+    if let (Some(min), Some(value)) = (
+        schema.get("minLength").and_then(Value::as_u64),
+        value.as_str(),
+    ) {
+        if (value.chars().count() as u64) < min {
+            problems.push(format!(
+                "`{}` must be at least {} characters long",
+                display_path, min
+            ));
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    problems.push(format!("missing required field `{}`", join(path, key)));
+                }
+            }
+        }
+
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, property) in properties {
+                if let Some(value) = object.get(key) {
+                    check(property, value, &join(path, key), problems);
+                }
+            }
+        }
+    }
+
+    if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+        for (i, value) in values.iter().enumerate() {
+            check(items, value, &format!("{}[{}]", display_path, i), problems);
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn valid() -> Value {
+        json!({
+            "name": "moosync.sample",
+            "version": "0.1.0",
+            "icon": "icon.svg",
+            "extensionEntry": "ext.wasm",
+            "moosyncExtension": true,
+            "displayName": "Sample",
+            "permissions": { "hosts": ["example.com"], "paths": {} },
+        })
+    }
+
+    #[test]
+    fn bundled_schema_matches_the_shared_one() {
+        let shared = concat!(env!("CARGO_MANIFEST_DIR"), "/../../schema.json");
+        // Only available when building from a checkout of the repository.
+        if let Ok(shared) = std::fs::read_to_string(shared) {
+            assert_eq!(SCHEMA, shared, "build/schema.json is out of date");
+        }
+    }
+
+    #[test]
+    fn accepts_a_complete_manifest() {
+        assert_eq!(validate(&valid()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_missing_fields() {
+        let mut manifest = valid();
+        let object = manifest.as_object_mut().unwrap();
+        object.remove("icon");
+        object.remove("displayName");
+        object["permissions"]
+            .as_object_mut()
+            .unwrap()
+            .remove("paths");
+
+        assert_eq!(
+            validate(&manifest),
+            vec![
+                "missing required field `icon`",
+                "missing required field `displayName`",
+                "missing required field `permissions.paths`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_wrong_types() {
+        let mut manifest = valid();
+        manifest["moosyncExtension"] = json!("yes");
+        manifest["permissions"]["hosts"] = json!({});
+
+        assert_eq!(
+            validate(&manifest),
+            vec![
+                "`moosyncExtension` must be of type boolean, found \"yes\"",
+                "`permissions.hosts` must be of type array, found {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_empty_strings() {
+        let mut manifest = valid();
+        manifest["name"] = json!("");
+
+        assert_eq!(
+            validate(&manifest),
+            vec!["`name` must be at least 1 characters long"]
+        );
+    }
+
+    #[test]
+    fn reports_a_non_object_manifest() {
+        assert_eq!(
+            validate(&json!([])),
+            vec!["`manifest` must be of type object, found []"]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reads the `manifest(...)` argument of `#[moosync_extension]` from the crate sources.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use syn::{Attribute, Item};
+
+use crate::error::{Error, Result};
+use crate::ManifestFields;
+
+/// Looks for `#[moosync_extension]` in every `.rs` file under `dir`.
+pub(crate) fn find_manifest_fields(dir: &Path) -> Result<Option<ManifestFields>> {
+    let mut found: Option<(PathBuf, ManifestFields)> = None;
+
+    for path in rust_files(dir)? {
+        let source = fs::read_to_string(&path).map_err(|source| Error::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let file = syn::parse_file(&source).map_err(|source| Error::Source {
+            path: path.clone(),
+            source,
+        })?;
+
+        let mut attrs = vec![];
+        collect_extension_attrs(&file.items, &mut attrs);
+
+        for attr in attrs {
+            let fields = parse_attr(attr).map_err(|source| Error::Source {
+                path: path.clone(),
+                source,
+            })?;
+            if let Some((first, _)) = &found {
+                return Err(Error::DuplicateExtension {
+                    first: first.clone(),
+                    second: path,
+                });
+            }
+            found = Some((path.clone(), fields));
+        }
+    }
+
+    Ok(found.map(|(_, fields)| fields))
+}
+
+fn rust_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).map_err(|source| Error::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry
+            .map_err(|source| Error::Io {
+                path: dir.to_path_buf(),
+                source,
+            })?
+            .path();
+        if path.is_dir() {
+            files.extend(rust_files(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_extension_attrs<'a>(items: &'a [Item], attrs: &mut Vec<&'a Attribute>) {
+    for item in items {
+        let item_attrs = match item {
+            Item::Struct(item) => &item.attrs,
+            Item::Enum(item) => &item.attrs,
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    collect_extension_attrs(items, attrs);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        attrs.extend(item_attrs.iter().filter(|attr| {
+            attr.path()
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "moosync_extension")
+        }));
+    }
+}
+
+fn parse_attr(attr: &Attribute) -> syn::Result<ManifestFields> {
+    let mut fields = ManifestFields::default();
+    if matches!(attr.meta, syn::Meta::Path(_)) {
+        return Ok(fields);
+    }
+
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("constructor") {
+            meta.value()?.parse::<syn::Path>()?;
+        } else if meta.path.is_ident("manifest") {
+            fields = moosync_edk_manifest::parse_manifest(&meta)?.into();
+        }
+        // Anything else is validated by the attribute macro itself.
+        Ok(())
+    })?;
+    Ok(fields)
+}
@@ -0,0 +1,146 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fixture inputs and expected response shapes for every exported wrapper.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use types::entities::{QueryableAlbum, QueryableArtist, QueryablePlaylist};
+use types::songs::{QueryableSong, Song};
+use types::ui::extensions::{
+    AccountLoginArgs, ContextMenuReturnType, CustomRequestReturnType, ExtensionAccountDetail,
+    ExtensionProviderScope, PlaybackDetailsReturnType, PlaylistAndSongsReturnType,
+    PlaylistReturnType, PreferenceArgs, RecommendationsReturnType, SearchReturnType,
+    SongReturnType, SongsWithPageTokenReturnType,
+};
+use types::ui::player_details::PlayerState;
+
+pub struct Case {
+    /// Name of the exported function.
+    pub export: &'static str,
+    /// JSON passed as the call input.
+    pub input: Value,
+    /// Validates the raw response against the type the main app expects.
+    pub check: fn(&[u8]) -> anyhow::Result<()>,
+}
+
+fn check<T: DeserializeOwned>(output: &[u8]) -> anyhow::Result<()> {
+    serde_json::from_slice::<T>(output).map_err(|e| {
+        anyhow::anyhow!(
+            "response does not match {}: {} (got {})",
+            std::any::type_name::<T>(),
+            e,
+            String::from_utf8_lossy(output)
+        )
+    })?;
+    Ok(())
+}
+
+macro_rules! case {
+    ($export:literal => $ret:ty) => {
+        case!($export, Value::Null => $ret)
+    };
+    ($export:literal, $input:expr => $ret:ty) => {
+        Case {
+            export: $export,
+            input: serde_json::to_value($input).unwrap(),
+            check: check::<$ret>,
+        }
+    };
+}
+
+fn song() -> Song {
+    Song {
+        song: QueryableSong {
+            _id: Some("conformance:song".into()),
+            title: Some("Conformance".into()),
+            duration: Some(180.0),
+            url: Some("https://example.com/song".into()),
+            ..Default::default()
+        },
+        album: Some(album()),
+        artists: Some(vec![artist()]),
+        genre: None,
+    }
+}
+
+fn album() -> QueryableAlbum {
+    QueryableAlbum {
+        album_id: Some("conformance:album".into()),
+        album_name: Some("Conformance".into()),
+        ..Default::default()
+    }
+}
+
+fn artist() -> QueryableArtist {
+    QueryableArtist {
+        artist_id: Some("conformance:artist".into()),
+        artist_name: Some("Conformance".into()),
+        ..Default::default()
+    }
+}
+
+fn playlist() -> QueryablePlaylist {
+    QueryablePlaylist {
+        playlist_id: Some("conformance:playlist".into()),
+        playlist_name: "Conformance".into(),
+        ..Default::default()
+    }
+}
+
+/// Every `*_wrapper` exported by `moosync_edk`, in the order they're declared.
+pub fn all() -> Vec<Case> {
+    let token: Option<String> = None;
+    vec![
+        case!("get_provider_scopes_wrapper" => Vec<ExtensionProviderScope>),
+        case!("get_playlists_wrapper" => PlaylistReturnType),
+        case!("get_playlist_content_wrapper", ("conformance:playlist", &token) => SongsWithPageTokenReturnType),
+        case!("get_playlist_from_url_wrapper", "https://example.com/playlist" => PlaylistAndSongsReturnType),
+        case!("get_playback_details_wrapper", song() => PlaybackDetailsReturnType),
+        case!("search_wrapper", "conformance" => SearchReturnType),
+        case!("get_recommendations_wrapper" => RecommendationsReturnType),
+        case!("get_song_from_url_wrapper", "https://example.com/song" => SongReturnType),
+        case!("handle_custom_request_wrapper", "extension://conformance" => CustomRequestReturnType),
+        case!("get_artist_songs_wrapper", (artist(), &token) => SongsWithPageTokenReturnType),
+        case!("get_album_songs_wrapper", (album(), &token) => SongsWithPageTokenReturnType),
+        case!("get_song_from_id_wrapper", "conformance:song" => SongReturnType),
+        case!("on_queue_changed_wrapper", json!({ "songs": [song()], "index": 0, "change": "added" }) => ()),
+        case!("on_volume_changed_wrapper" => ()),
+        case!("on_player_state_changed_wrapper" => ()),
+        case!("on_song_changed_wrapper" => ()),
+        case!("on_volume_changed_wrapper", json!({ "volume": 50.0, "previous": 40.0 }) => ()),
+        case!("on_player_state_changed_wrapper", json!({ "state": PlayerState::Playing }) => ()),
+        case!("on_song_changed_wrapper", json!({ "song": song() }) => ()),
+        case!("on_seeked_wrapper", 42.0 => ()),
+        case!("on_preferences_changed_wrapper", PreferenceArgs { key: "conformance".into(), value: json!(true) } => ()),
+        case!("on_song_added_wrapper", song() => ()),
+        case!("on_song_removed_wrapper", song() => ()),
+        case!("on_playlist_added_wrapper", playlist() => ()),
+        case!("on_playlist_removed_wrapper", playlist() => ()),
+        case!("get_accounts_wrapper" => Vec<ExtensionAccountDetail>),
+        case!("perform_account_login_wrapper", AccountLoginArgs {
+            package_name: "conformance".into(),
+            account_id: "conformance:account".into(),
+            login_status: true,
+        } => String),
+        case!("scrobble_wrapper", song() => ()),
+        case!("oauth_callback_wrapper", "conformance://callback?code=0" => ()),
+        case!("get_song_context_menu_wrapper", vec![song()] => Vec<ContextMenuReturnType>),
+        case!("get_playlist_context_menu_wrapper", playlist() => Vec<ContextMenuReturnType>),
+        case!("on_context_menu_action_wrapper", "conformance" => ()),
+        case!("get_lyrics_wrapper", song() => String),
+    ]
+}
@@ -0,0 +1,92 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Stub implementations of the `ExtismHost` imports declared in
+//! `moosync_edk::api`.
+//!
+//! The signatures here must stay in sync with the `extern "ExtismHost"` block on
+//! the SDK side; a mismatch makes the plugin fail to instantiate, which is
+//! exactly the drift the runner is meant to catch.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use extism::convert::Json;
+use extism::{host_fn, Manifest, Plugin, PluginBuilder, UserData, Wasm, PTR};
+use serde_json::Value;
+use types::extensions::MainCommand;
+
+/// State shared by the stubbed host functions.
+#[derive(Default)]
+pub struct HostState {
+    /// Every command the extension sent to the main app, in order.
+    pub commands: Vec<Value>,
+}
+
+host_fn!(send_main_command(user_data: HostState; command: Json<Value>) -> Json<Option<Value>> {
+    let Json(command) = command;
+    // Make sure the SDK still serializes commands the way the main app parses them.
+    serde_json::from_value::<MainCommand>(command.clone())?;
+
+    let state = user_data.get()?;
+    state.lock().unwrap().commands.push(command);
+    Ok(Json(None))
+});
+
+host_fn!(system_time(_user_data: HostState;) -> u64 {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+});
+
+host_fn!(open_clientfd(_user_data: HostState; _path: String) -> i64 {
+    Ok(-1)
+});
+
+host_fn!(write_sock(_user_data: HostState; _sock_id: i64, _buf: Vec<u8>) -> i64 {
+    Ok(-1)
+});
+
+host_fn!(read_sock(_user_data: HostState; _sock_id: i64, _read_len: u64) -> Vec<u8> {
+    Ok(vec![])
+});
+
+host_fn!(close_sock(_user_data: HostState; _sock_id: i64) -> i64 {
+    Ok(0)
+});
+
+host_fn!(hash(_user_data: HostState; hash_type: String, _data: Vec<u8>) -> Vec<u8> {
+    Err(anyhow::anyhow!("hash ({}) is not available in the conformance runner", hash_type))
+});
+
+/// Loads the extension at `path` with every host function stubbed.
+pub fn load_plugin(path: &Path, state: &UserData<HostState>) -> anyhow::Result<Plugin> {
+    let manifest = Manifest::new([Wasm::file(path)]);
+    PluginBuilder::new(manifest)
+        .with_wasi(true)
+        .with_function(
+            "send_main_command",
+            [PTR],
+            [PTR],
+            state.clone(),
+            send_main_command,
+        )
+        .with_function("system_time", [], [PTR], state.clone(), system_time)
+        .with_function("open_clientfd", [PTR], [PTR], state.clone(), open_clientfd)
+        .with_function("write_sock", [PTR, PTR], [PTR], state.clone(), write_sock)
+        .with_function("read_sock", [PTR, PTR], [PTR], state.clone(), read_sock)
+        .with_function("close_sock", [PTR], [PTR], state.clone(), close_sock)
+        .with_function("hash", [PTR, PTR], [PTR], state.clone(), hash)
+        .build()
+}
@@ -0,0 +1,113 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Loads a compiled extension with the Extism runtime and checks that every
+//! exported wrapper accepts the main app's inputs and answers with a response
+//! the main app can parse.
+//!
+//! ```sh
+//! cargo build --release --target wasm32-wasip1
+//! cargo run -p moosync-edk-conformance -- target/wasm32-wasip1/release/ext.wasm
+//! ```
+//!
+//! Methods the extension doesn't implement are reported as skipped. A missing
+//! export, a trap or a response that doesn't deserialize into the matching
+//! `types` struct fails the run.
+
+mod cases;
+mod host;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use extism::{Plugin, UserData};
+use host::HostState;
+
+/// Return code of a wrapper whose trait method isn't implemented, see
+/// `moosync_edk::api::NOT_IMPLEMENTED_CODE`.
+const NOT_IMPLEMENTED_CODE: i32 = 501;
+
+enum Outcome {
+    Passed,
+    Skipped,
+    Failed(String),
+}
+
+fn run_case(plugin: &mut Plugin, case: &cases::Case) -> Outcome {
+    if !plugin.function_exists(case.export) {
+        return Outcome::Failed("not exported".into());
+    }
+
+    let input = case.input.to_string();
+    match plugin.call_get_error_code::<&str, &[u8]>(case.export, &input) {
+        Ok(output) => match (case.check)(output) {
+            Ok(()) => Outcome::Passed,
+            Err(e) => Outcome::Failed(e.to_string()),
+        },
+        Err((_, NOT_IMPLEMENTED_CODE)) => Outcome::Skipped,
+        Err((e, code)) => Outcome::Failed(format!("returned {}: {:#}", code, e)),
+    }
+}
+
+fn run(path: PathBuf) -> anyhow::Result<bool> {
+    let state = UserData::new(HostState::default());
+    let mut plugin = host::load_plugin(&path, &state)?;
+
+    plugin
+        .call::<&str, &[u8]>("entry", "")
+        .map_err(|e| anyhow::anyhow!("entry failed: {:#}", e))?;
+
+    let (mut passed, mut skipped, mut failed) = (0, 0, 0);
+    for case in cases::all() {
+        match run_case(&mut plugin, &case) {
+            Outcome::Passed => {
+                passed += 1;
+                println!("ok       {}", case.export);
+            }
+            Outcome::Skipped => {
+                skipped += 1;
+                println!("skipped  {}", case.export);
+            }
+            Outcome::Failed(reason) => {
+                failed += 1;
+                println!("FAILED   {}: {}", case.export, reason);
+            }
+        }
+    }
+
+    let commands = state.get()?.lock().unwrap().commands.len();
+    println!(
+        "\n{} passed, {} skipped, {} failed ({} main app commands sent)",
+        passed, skipped, failed, commands
+    );
+    Ok(failed == 0)
+}
+
+fn main() -> ExitCode {
+    let Some(path) = std::env::args_os().nth(1) else {
+        eprintln!("usage: moosync-edk-conformance <extension.wasm>");
+        return ExitCode::from(2);
+    };
+
+    match run(PathBuf::from(path)) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
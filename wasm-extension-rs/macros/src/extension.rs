@@ -0,0 +1,157 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::{meta::ParseNestedMeta, spanned::Spanned, Item, Path};
+
+#[derive(Default)]
+pub(crate) struct ExtensionArgs {
+    constructor: Option<Path>,
+    is_async: bool,
+}
+
+impl ExtensionArgs {
+    pub(crate) fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("constructor") {
+            self.constructor = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("async") {
+            self.is_async = true;
+            Ok(())
+        } else if meta.path.is_ident("manifest") {
+            // The values are only read by `moosync-edk-build` when generating
+            // `package.json`, so nothing is emitted for them here.
+            moosync_edk_manifest::parse_manifest(&meta).map(drop)
+        } else {
+            Err(meta.error(
+                "unsupported moosync_extension argument, expected `constructor`, `async` or `manifest`",
+            ))
+        }
+    }
+}
+
+pub(crate) fn expand(args: ExtensionArgs, item: Item) -> syn::Result<TokenStream> {
+    let (ident, generics) = match &item {
+        Item::Struct(item) => (&item.ident, &item.generics),
+        Item::Enum(item) => (&item.ident, &item.generics),
+        _ => {
+            return Err(syn::Error::new(
+                item.span(),
+                "#[moosync_extension] can only be applied to a struct or an enum",
+            ));
+        }
+    };
+
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new(
+            generics.span(),
+            "#[moosync_extension] cannot be applied to a generic type",
+        ));
+    }
+
+    let construct = match &args.constructor {
+        Some(constructor) => quote_spanned!(constructor.span()=> #constructor()),
+        None => quote_spanned!(ident.span()=> <#ident as ::core::default::Default>::default()),
+    };
+
+    let register = if args.is_async {
+        quote_spanned! {ident.span()=>
+            let extension: #ident = #construct;
+            let result = ::moosync_edk::async_api::register_async_extension(extension);
+        }
+    } else {
+        quote_spanned! {ident.span()=>
+            let extension: ::std::boxed::Box<dyn ::moosync_edk::api::Extension> =
+                ::std::boxed::Box::new(#construct);
+            let result = ::moosync_edk::handler::register_extension(extension);
+        }
+    };
+
+    Ok(quote! {
+        #item
+
+        const _: () = {
+            #[unsafe(no_mangle)]
+            pub extern "C" fn init() {
+                // Set by moosync-edk-build when it generates the manifest.
+                if let ::core::option::Option::Some(hosts) =
+                    ::core::option_env!("MOOSYNC_EDK_ALLOWED_HOSTS")
+                {
+                    ::moosync_edk::permissions::set_allowed_hosts(hosts.split_whitespace());
+                }
+
+                #register
+                if let ::core::result::Result::Err(e) = result {
+                    ::moosync_edk::error!("Failed to register extension: {}", e.0);
+                }
+            }
+        };
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse::Parser;
+
+    use super::*;
+
+    fn expand_str(attr: &str, item: &str) -> syn::Result<String> {
+        let mut args = ExtensionArgs::default();
+        syn::meta::parser(|meta| args.parse(meta)).parse_str(attr)?;
+        expand(args, syn::parse_str(item)?).map(|tokens| tokens.to_string())
+    }
+
+    #[test]
+    fn registers_a_blocking_extension_by_default() {
+        let expanded = expand_str("", "struct Sample {}").unwrap();
+        assert!(expanded.contains(":: moosync_edk :: handler :: register_extension"));
+        assert!(!expanded.contains("register_async_extension"));
+        assert!(expanded.contains("< Sample as :: core :: default :: Default > :: default ()"));
+    }
+
+    #[test]
+    fn registers_an_async_extension() {
+        let expanded = expand_str("async", "struct Sample {}").unwrap();
+        assert!(expanded
+            .contains(":: moosync_edk :: async_api :: register_async_extension (extension)"));
+        assert!(!expanded.contains("handler :: register_extension"));
+    }
+
+    #[test]
+    fn uses_the_constructor() {
+        let expanded = expand_str("async, constructor = Sample::new", "struct Sample {}").unwrap();
+        assert!(expanded.contains("let extension : Sample = Sample :: new ()"));
+    }
+
+    #[test]
+    fn rejects_unsupported_items_and_arguments() {
+        let err = expand_str("", "fn sample() {}").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("can only be applied to a struct or an enum"));
+
+        let err = expand_str("", "struct Sample<T>(T);").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot be applied to a generic type"));
+
+        let err = expand_str("blocking", "struct Sample {}").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unsupported moosync_extension argument"));
+    }
+}
@@ -0,0 +1,124 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Procedural macros for the Moosync extension development kit.
+//!
+//! These are re-exported from `moosync_edk` and should not be depended upon directly.
+
+use proc_macro::TokenStream;
+use syn::{parse::Nothing, parse_macro_input, DeriveInput, Item, ItemImpl};
+
+mod extension;
+mod preferences;
+mod scopes;
+
+/// Registers the annotated type as the extension of this plugin.
+///
+/// Generates the `init` symbol called by the SDK's `entry` export, constructs the
+/// extension and passes it to `moosync_edk::handler::register_extension`.
+///
+/// The extension is built through `Default` unless a constructor is named:
+///
+/// ```ignore
+/// #[moosync_extension(constructor = SampleExtension::new)]
+/// struct SampleExtension {}
+/// ```
+///
+/// The annotated type must implement `moosync_edk::api::Extension`. Pass `async`
+/// to register an `moosync_edk::async_api::AsyncExtension` instead, whose methods
+/// are written as `async fn` but still handle one call at a time:
+///
+/// ```ignore
+/// #[moosync_extension(async)]
+/// #[derive(Default)]
+/// struct SampleExtension {}
+/// ```
+///
+/// Fields of the generated `package.json` can be given under `manifest`. They are
+/// read by `moosync-edk-build`, which passes the declared hosts back to the
+/// extension so that requests to other hosts are rejected before being sent:
+///
+/// ```ignore
+/// #[moosync_extension(manifest(
+///     display_name = "Sample extension",
+///     icon = "assets/icon.svg",
+///     hosts = ["soundcloud.com", "*.soundcloud.com"],
+///     paths = { "{HOME}/Music": "/music" },
+/// ))]
+/// struct SampleExtension {}
+/// ```
+#[proc_macro_attribute]
+pub fn moosync_extension(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = extension::ExtensionArgs::default();
+    let parser = syn::meta::parser(|meta| args.parse(meta));
+    parse_macro_input!(attr with parser);
+
+    let item = parse_macro_input!(item as Item);
+    extension::expand(args, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives the provider scopes of an extension from the methods it overrides.
+///
+/// Applied to an `impl Provider`, `impl Accounts` or `impl ContextMenu` block,
+/// or to an impl of their async counterparts.
+/// On `Provider`, generates `get_provider_scopes` unless it is written by hand.
+/// On `Accounts` and `ContextMenu`, the derived scopes are added to whatever
+/// `get_provider_scopes` returns.
+///
+/// ```ignore
+/// #[provider_scopes]
+/// impl Provider for SampleExtension {
+///     fn search(&self, term: String) -> Result<SearchResult> {
+///         // ...
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn provider_scopes(attr: TokenStream, item: TokenStream) -> TokenStream {
+    parse_macro_input!(attr as Nothing);
+
+    let item = parse_macro_input!(item as ItemImpl);
+    scopes::expand(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `moosync_edk::preferences::Preferences` for a struct with named fields.
+///
+/// Each field is stored as its own preference. The key defaults to the field name
+/// and is prefixed with `prefix` when given. Unset preferences fall back to the
+/// struct's `Default` value, which must be implemented.
+///
+/// ```ignore
+/// #[derive(Default, Preferences)]
+/// #[preferences(prefix = "player")]
+/// struct PlayerSettings {
+///     volume: f64,                        // "player.volume"
+///     #[preference(secure, key = "token")]
+///     api_token: Option<String>,          // secure "player.token"
+///     #[preference(skip)]
+///     cached: Vec<String>,                // not stored
+/// }
+/// ```
+#[proc_macro_derive(Preferences, attributes(preferences, preference))]
+pub fn derive_preferences(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    preferences::expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
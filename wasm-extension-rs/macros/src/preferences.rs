@@ -0,0 +1,192 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, Data, DeriveInput, Fields, LitStr};
+
+#[derive(Default)]
+struct FieldArgs {
+    key: Option<String>,
+    secure: bool,
+    skip: bool,
+}
+
+fn parse_prefix(input: &DeriveInput) -> syn::Result<Option<String>> {
+    let mut prefix = None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("preferences") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("prefix") {
+                prefix = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported preferences argument, expected `prefix`"))
+            }
+        })?;
+    }
+    Ok(prefix)
+}
+
+fn parse_field_args(field: &syn::Field) -> syn::Result<FieldArgs> {
+    let mut args = FieldArgs::default();
+    for attr in &field.attrs {
+        if !attr.path().is_ident("preference") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("key") {
+                args.key = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("secure") {
+                args.secure = true;
+            } else if meta.path.is_ident("skip") {
+                args.skip = true;
+            } else {
+                return Err(meta
+                    .error("unsupported preference argument, expected `key`, `secure` or `skip`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(args)
+}
+
+pub(crate) fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "#[derive(Preferences)] requires a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "#[derive(Preferences)] can only be applied to a struct",
+            ))
+        }
+    };
+
+    let prefix = parse_prefix(&input)?;
+
+    let mut load_fields = vec![];
+    let mut save_fields = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let args = parse_field_args(field)?;
+
+        if args.skip {
+            load_fields.push(quote!(#ident: default.#ident));
+            continue;
+        }
+
+        let name = args.key.unwrap_or_else(|| ident.to_string());
+        let key = match &prefix {
+            Some(prefix) => format!("{}.{}", prefix, name),
+            None => name,
+        };
+
+        let (get, set) = if args.secure {
+            (quote!(get_secure), quote!(set_secure))
+        } else {
+            (quote!(get_preference), quote!(set_preference))
+        };
+
+        load_fields.push(quote!(#ident: ::moosync_edk::preferences::#get(#key, default.#ident)?));
+        save_fields.push(quote!(::moosync_edk::preferences::#set(#key, &self.#ident)?;));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::moosync_edk::preferences::Preferences for #ident #ty_generics #where_clause {
+            fn load() -> ::moosync_edk::preferences::PreferenceResult<Self> {
+                let default = <Self as ::core::default::Default>::default();
+                ::core::result::Result::Ok(Self {
+                    #(#load_fields),*
+                })
+            }
+
+            fn save(&self) -> ::moosync_edk::preferences::PreferenceResult<()> {
+                #(#save_fields)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(item: &str) -> syn::Result<String> {
+        expand(syn::parse_str(item)?).map(|tokens| tokens.to_string())
+    }
+
+    #[test]
+    fn keys_use_the_prefix_and_renames() {
+        let expanded = expand_str(
+            r#"
+            #[preferences(prefix = "player")]
+            struct Settings {
+                volume: f64,
+                #[preference(secure, key = "token")]
+                api_token: String,
+                #[preference(skip)]
+                cache: Vec<String>,
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(expanded.contains(r#"get_preference ("player.volume" , default . volume)"#));
+        assert!(expanded.contains(r#"get_secure ("player.token" , default . api_token)"#));
+        assert!(expanded.contains(r#"set_secure ("player.token" , & self . api_token)"#));
+        assert!(expanded.contains("cache : default . cache"));
+        assert!(!expanded.contains("self . cache"));
+    }
+
+    #[test]
+    fn keys_default_to_the_field_name() {
+        let expanded = expand_str("struct Settings { volume: f64 }").unwrap();
+        assert!(expanded.contains(r#"get_preference ("volume" , default . volume)"#));
+    }
+
+    #[test]
+    fn rejects_unsupported_input() {
+        let err = expand_str("struct Settings(f64);").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("requires a struct with named fields"));
+
+        let err = expand_str("enum Settings { A }").unwrap_err();
+        assert!(err.to_string().contains("can only be applied to a struct"));
+
+        let err = expand_str(r#"#[preferences(namespace = "a")] struct Settings {}"#).unwrap_err();
+        assert!(err.to_string().contains("expected `prefix`"));
+
+        let err = expand_str("struct Settings { #[preference(hidden)] volume: f64 }").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expected `key`, `secure` or `skip`"));
+    }
+}
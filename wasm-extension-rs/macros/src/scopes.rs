@@ -0,0 +1,236 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, ImplItem, ItemImpl};
+
+/// Maps an overridden trait method to the provider scope it requires.
+/// Methods that don't need a scope of their own map to `None`.
+fn scope_for(method: &str) -> Option<&'static str> {
+    match method {
+        "get_playlists" => Some("Playlists"),
+        "get_playlist_content" => Some("PlaylistSongs"),
+        "get_playlist_from_url" => Some("PlaylistFromUrl"),
+        "get_playback_details" => Some("PlaybackDetails"),
+        "search" => Some("Search"),
+        "get_recommendations" => Some("Recommendations"),
+        "get_song_from_url" => Some("SongFromUrl"),
+        "get_artist_songs" => Some("ArtistSongs"),
+        "get_album_songs" => Some("AlbumSongs"),
+        "scrobble" => Some("Scrobbles"),
+        "get_lyrics" => Some("Lyrics"),
+        "get_accounts" | "perform_account_login" | "oauth_callback" => Some("Accounts"),
+        "get_song_context_menu" => Some("SongContextMenu"),
+        "get_playlist_context_menu" => Some("PlaylistContextMenu"),
+        _ => None,
+    }
+}
+
+pub(crate) fn expand(mut item: ItemImpl) -> syn::Result<TokenStream> {
+    let trait_name = item
+        .trait_
+        .as_ref()
+        .and_then(|(_, path, _)| path.segments.last())
+        .map(|segment| segment.ident.to_string());
+
+    let is_provider = match trait_name.as_deref() {
+        Some("Provider") | Some("AsyncProvider") => true,
+        Some("Accounts")
+        | Some("ContextMenu")
+        | Some("AsyncAccounts")
+        | Some("AsyncContextMenu") => false,
+        _ => {
+            return Err(syn::Error::new(
+                item.span(),
+                "#[provider_scopes] can only be applied to an impl of `Provider`, `Accounts` or `ContextMenu` or their async counterparts",
+            ));
+        }
+    };
+
+    let mut scopes: Vec<&'static str> = vec![];
+    let mut has_scopes_override = false;
+    for impl_item in &item.items {
+        if let ImplItem::Fn(method) = impl_item {
+            let name = method.sig.ident.to_string();
+            if name == "get_provider_scopes" {
+                has_scopes_override = true;
+            }
+            if let Some(scope) = scope_for(&name) {
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                }
+            }
+        }
+    }
+
+    let scopes = scopes.into_iter().map(|scope| {
+        let scope = syn::Ident::new(scope, proc_macro2::Span::call_site());
+        quote!(::moosync_edk::ExtensionProviderScope::#scope)
+    });
+
+    let generated: ImplItem = if is_provider {
+        if has_scopes_override {
+            return Ok(quote!(#item));
+        }
+        syn::parse_quote! {
+            fn get_provider_scopes(
+                &self,
+            ) -> ::moosync_edk::Result<::std::vec::Vec<::moosync_edk::ExtensionProviderScope>> {
+                ::core::result::Result::Ok(::std::vec![#(#scopes),*])
+            }
+        }
+    } else {
+        syn::parse_quote! {
+            fn derived_scopes(&self) -> ::std::vec::Vec<::moosync_edk::ExtensionProviderScope> {
+                ::std::vec![#(#scopes),*]
+            }
+        }
+    };
+    item.items.push(generated);
+
+    Ok(quote!(#item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(item: &str) -> syn::Result<ItemImpl> {
+        syn::parse2(expand(syn::parse_str(item)?)?)
+    }
+
+    /// Names of the functions of an expanded impl, in order.
+    fn fn_names(item: &ItemImpl) -> Vec<String> {
+        item.items
+            .iter()
+            .filter_map(|item| match item {
+                ImplItem::Fn(method) => Some(method.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn generated(item: &ItemImpl) -> String {
+        match item.items.last() {
+            Some(ImplItem::Fn(method)) => quote!(#method).to_string(),
+            _ => panic!("Expected a generated method"),
+        }
+    }
+
+    #[test]
+    fn methods_map_to_scopes() {
+        let cases = [
+            ("get_playlists", Some("Playlists")),
+            ("get_playlist_content", Some("PlaylistSongs")),
+            ("get_playlist_from_url", Some("PlaylistFromUrl")),
+            ("get_playback_details", Some("PlaybackDetails")),
+            ("search", Some("Search")),
+            ("get_recommendations", Some("Recommendations")),
+            ("get_song_from_url", Some("SongFromUrl")),
+            ("get_artist_songs", Some("ArtistSongs")),
+            ("get_album_songs", Some("AlbumSongs")),
+            ("scrobble", Some("Scrobbles")),
+            ("get_lyrics", Some("Lyrics")),
+            ("get_accounts", Some("Accounts")),
+            ("perform_account_login", Some("Accounts")),
+            ("oauth_callback", Some("Accounts")),
+            ("get_song_context_menu", Some("SongContextMenu")),
+            ("get_playlist_context_menu", Some("PlaylistContextMenu")),
+            ("get_provider_scopes", None),
+            ("on_song_context_menu_action", None),
+            ("helper", None),
+        ];
+        for (method, scope) in cases {
+            assert_eq!(scope_for(method), scope, "{}", method);
+        }
+    }
+
+    #[test]
+    fn generates_provider_scopes_from_overridden_methods() {
+        let item = expand_str(
+            "impl Provider for Sample {
+                fn search(&self, term: String) -> Result<SearchResult> { todo!() }
+                fn get_playlists(&self) -> Result<Vec<QueryablePlaylist>> { todo!() }
+                fn helper(&self) {}
+            }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            fn_names(&item),
+            ["search", "get_playlists", "helper", "get_provider_scopes"]
+        );
+        let generated = generated(&item);
+        assert!(generated.contains(
+            ":: std :: vec ! [:: moosync_edk :: ExtensionProviderScope :: Search , :: moosync_edk :: ExtensionProviderScope :: Playlists]"
+        ), "{}", generated);
+    }
+
+    #[test]
+    fn keeps_a_manual_provider_scopes() {
+        let source = "impl AsyncProvider for Sample {
+            fn search(&self, term: String) -> Result<SearchResult> { todo!() }
+            fn get_provider_scopes(&self) -> Result<Vec<ExtensionProviderScope>> { todo!() }
+        }";
+        let item = expand_str(source).unwrap();
+
+        assert_eq!(fn_names(&item), ["search", "get_provider_scopes"]);
+        let original: ItemImpl = syn::parse_str(source).unwrap();
+        assert_eq!(quote!(#item).to_string(), quote!(#original).to_string());
+    }
+
+    #[test]
+    fn generates_derived_scopes_for_accounts_and_context_menus() {
+        let item = expand_str(
+            "impl Accounts for Sample {
+                fn get_accounts(&self) -> Result<Vec<AccountDetails>> { todo!() }
+                fn perform_account_login(&self, args: AccountLoginArgs) -> Result<String> { todo!() }
+                fn oauth_callback(&self, code: String) -> Result<()> { todo!() }
+            }",
+        )
+        .unwrap();
+        assert_eq!(fn_names(&item).last().unwrap(), "derived_scopes");
+        assert!(generated(&item)
+            .contains(":: std :: vec ! [:: moosync_edk :: ExtensionProviderScope :: Accounts]"));
+
+        let item = expand_str(
+            "impl AsyncContextMenu for Sample {
+                fn get_song_context_menu(&self, songs: Vec<Song>) -> Result<Vec<ContextMenuReturnType>> { todo!() }
+            }",
+        )
+        .unwrap();
+        assert!(generated(&item).contains(
+            "fn derived_scopes (& self) -> :: std :: vec :: Vec < :: moosync_edk :: ExtensionProviderScope > { :: std :: vec ! [:: moosync_edk :: ExtensionProviderScope :: SongContextMenu] }"
+        ), "{}", generated(&item));
+    }
+
+    #[test]
+    fn rejects_other_impls() {
+        for source in [
+            "impl PlayerEvents for Sample {}",
+            "impl Sample { fn search(&self) {} }",
+        ] {
+            let err = expand(syn::parse_str(source).unwrap()).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("#[provider_scopes] can only be applied to an impl of `Provider`"),
+                "{}",
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing of the `manifest(...)` argument of `#[moosync_extension]`.
+//!
+//! Shared by `moosync-edk-macros`, which checks the argument when the
+//! attribute is expanded, and `moosync-edk-build`, which reads it from the
+//! crate sources to generate `package.json`.
+
+use std::collections::BTreeMap;
+
+use syn::{braced, bracketed, meta::ParseNestedMeta, punctuated::Punctuated, LitStr, Token};
+
+/// Manifest fields set in `#[moosync_extension(manifest(...))]`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ManifestFields {
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub icon: Option<String>,
+    pub extension_entry: Option<String>,
+    pub hosts: Option<Vec<String>>,
+    pub paths: Option<BTreeMap<String, String>>,
+}
+
+/// Parses the contents of `manifest(...)`, `meta` being the `manifest` entry itself.
+pub fn parse_manifest(meta: &ParseNestedMeta) -> syn::Result<ManifestFields> {
+    let mut fields = ManifestFields::default();
+    meta.parse_nested_meta(|meta| parse_field(meta, &mut fields))?;
+    Ok(fields)
+}
+
+fn parse_field(meta: ParseNestedMeta, fields: &mut ManifestFields) -> syn::Result<()> {
+    let string = |meta: &ParseNestedMeta| -> syn::Result<Option<String>> {
+        let value: LitStr = meta.value()?.parse()?;
+        if value.value().is_empty() {
+            return Err(syn::Error::new(
+                value.span(),
+                "manifest values cannot be empty",
+            ));
+        }
+        Ok(Some(value.value()))
+    };
+
+    if meta.path.is_ident("name") {
+        fields.name = string(&meta)?;
+    } else if meta.path.is_ident("display_name") {
+        fields.display_name = string(&meta)?;
+    } else if meta.path.is_ident("icon") {
+        fields.icon = string(&meta)?;
+    } else if meta.path.is_ident("extension_entry") {
+        fields.extension_entry = string(&meta)?;
+    } else if meta.path.is_ident("hosts") {
+        let input = meta.value()?;
+        let content;
+        bracketed!(content in input);
+        let hosts = Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?;
+        fields.hosts = Some(hosts.iter().map(LitStr::value).collect());
+    } else if meta.path.is_ident("paths") {
+        let input = meta.value()?;
+        let content;
+        braced!(content in input);
+        let paths =
+            Punctuated::<(LitStr, LitStr), Token![,]>::parse_terminated_with(&content, |input| {
+                let host: LitStr = input.parse()?;
+                input.parse::<Token![:]>()?;
+                Ok((host, input.parse()?))
+            })?;
+        fields.paths = Some(
+            paths
+                .iter()
+                .map(|(host, guest)| (host.value(), guest.value()))
+                .collect(),
+        );
+    } else {
+        return Err(meta.error(
+            "unsupported manifest field, expected one of `name`, `display_name`, `icon`, `extension_entry`, `hosts` or `paths`",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse::Parser;
+
+    use super::*;
+
+    fn parse(args: &str) -> syn::Result<ManifestFields> {
+        let mut fields = None;
+        syn::meta::parser(|meta| {
+            fields = Some(parse_manifest(&meta)?);
+            Ok(())
+        })
+        .parse_str(args)?;
+        Ok(fields.expect("manifest(...) was parsed"))
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let fields = parse(
+            r#"manifest(
+                name = "moosync.sample",
+                display_name = "Sample",
+                icon = "icon.svg",
+                extension_entry = "ext.wasm",
+                hosts = ["example.com", "*.example.com"],
+                paths = { "{HOME}/Music": "/music" },
+            )"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            fields,
+            ManifestFields {
+                name: Some("moosync.sample".into()),
+                display_name: Some("Sample".into()),
+                icon: Some("icon.svg".into()),
+                extension_entry: Some("ext.wasm".into()),
+                hosts: Some(vec!["example.com".into(), "*.example.com".into()]),
+                paths: Some(BTreeMap::from([("{HOME}/Music".into(), "/music".into())])),
+            }
+        );
+    }
+
+    #[test]
+    fn unset_fields_stay_empty() {
+        let fields = parse(r#"manifest(icon = "icon.svg")"#).unwrap();
+        assert_eq!(fields.icon.as_deref(), Some("icon.svg"));
+        assert_eq!(fields.name, None);
+        assert_eq!(fields.hosts, None);
+    }
+
+    #[test]
+    fn rejects_empty_values() {
+        let err = parse(r#"manifest(name = "")"#).unwrap_err();
+        assert_eq!(err.to_string(), "manifest values cannot be empty");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = parse(r#"manifest(version = "1.0.0")"#).unwrap_err();
+        assert!(err.to_string().starts_with("unsupported manifest field"));
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert!(parse(r#"manifest(hosts = "example.com")"#).is_err());
+        assert!(parse(r#"manifest(paths = { "/a" = "/b" })"#).is_err());
+        assert!(parse(r#"manifest(name = 1)"#).is_err());
+    }
+}
@@ -14,10 +14,16 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cell::Cell;
+
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
 use extism_pdk::host_fn;
+use serde::{Deserialize, Serialize};
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
 use serde_json::Value;
 use types::entities::{QueryableAlbum, QueryableArtist, QueryablePlaylist, SearchResult};
-use types::errors::Result as MoosyncResult;
+use types::errors::{MoosyncError, Result as MoosyncResult};
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
 use types::extensions::MainCommand;
 use types::songs::Song;
 use types::ui::extensions::{
@@ -25,22 +31,100 @@ use types::ui::extensions::{
     ExtensionProviderScope, PlaybackDetailsReturnType, PreferenceArgs,
 };
 
+use crate::events::{PlayerStateChanged, Queue, SongChanged, VolumeChanged};
+use crate::lyrics::Lyrics;
+
+/// Message of the error returned by trait methods that the extension doesn't implement.
+pub const NOT_IMPLEMENTED: &str = "Not implemented";
+
+/// Return code reported to the main app when the called method isn't implemented
+/// by the extension, as opposed to a method that failed.
+pub const NOT_IMPLEMENTED_CODE: i32 = 501;
+
+thread_local!(
+    /// Set by [`not_implemented`], so that its error can be told apart from an
+    /// error that merely carries the same message.
+    static NOT_IMPLEMENTED_RETURNED: Cell<bool> = const { Cell::new(false) };
+);
+
+/// Returns the error used by the default trait methods.
+///
+/// Return this from an overridden method to signal that a call is unsupported;
+/// the main app will then treat it the same as a method that was never implemented.
+pub fn not_implemented<T>() -> MoosyncResult<T> {
+    NOT_IMPLEMENTED_RETURNED.with(|returned| returned.set(true));
+    Err(MoosyncError::String(NOT_IMPLEMENTED.into()))
+}
+
+/// Checks whether an error was produced by [`not_implemented`] during the
+/// current call into the extension.
+///
+/// Errors built by hand with the same message are not matched.
+pub fn is_not_implemented(err: &MoosyncError) -> bool {
+    NOT_IMPLEMENTED_RETURNED.with(Cell::get)
+        && matches!(err, MoosyncError::String(msg) if msg == NOT_IMPLEMENTED)
+}
+
+/// Forgets any [`not_implemented`] error, before calling into the extension.
+pub(crate) fn reset_not_implemented() {
+    NOT_IMPLEMENTED_RETURNED.with(|returned| returned.set(false));
+}
+
+/// A single page of songs returned by a paginated provider method.
+///
+/// `next_page_token` is handed back to the main app, which passes it to the
+/// next call of the same method to fetch the following page. Leave it as `None`
+/// once there are no more songs to fetch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaginatedSongs {
+    pub songs: Vec<Song>,
+    pub next_page_token: Option<String>,
+}
+
+impl PaginatedSongs {
+    /// Creates a page of songs with an optional token pointing to the next page.
+    pub fn new(songs: Vec<Song>, next_page_token: Option<String>) -> Self {
+        Self {
+            songs,
+            next_page_token,
+        }
+    }
+}
+
+impl From<Vec<Song>> for PaginatedSongs {
+    fn from(songs: Vec<Song>) -> Self {
+        Self {
+            songs,
+            next_page_token: None,
+        }
+    }
+}
+
 #[allow(unused_variables)]
 /// Trait for handling account-related events.
 pub trait Accounts {
     /// Called when the main app requests the list of accounts.
     fn get_accounts(&self) -> MoosyncResult<Vec<ExtensionAccountDetail>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests to perform an account login.
     fn perform_account_login(&self, args: AccountLoginArgs) -> MoosyncResult<String> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app provides an OAuth callback code.
     fn oauth_callback(&self, code: String) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
+    }
+
+    /// Provider scopes required by the overridden methods of this trait.
+    ///
+    /// Generated by `#[provider_scopes]` and added to the scopes returned by
+    /// [`Provider::get_provider_scopes`].
+    #[doc(hidden)]
+    fn derived_scopes(&self) -> Vec<ExtensionProviderScope> {
+        vec![]
     }
 }
 
@@ -49,22 +133,22 @@ pub trait Accounts {
 pub trait DatabaseEvents {
     /// Called when a song is added to the database.
     fn on_song_added(&self, song: Song) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when a song is removed from the database.
     fn on_song_removed(&self, song: Song) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when a playlist is added to the database.
     fn on_playlist_added(&self, playlist: QueryablePlaylist) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when a playlist is removed from the database.
     fn on_playlist_removed(&self, playlist: QueryablePlaylist) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 }
 
@@ -73,7 +157,7 @@ pub trait DatabaseEvents {
 pub trait PreferenceEvents {
     /// Called when preferences are changed.
     fn on_preferences_changed(&self, args: PreferenceArgs) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 }
 
@@ -81,28 +165,28 @@ pub trait PreferenceEvents {
 /// Trait for handling player-related events.
 pub trait PlayerEvents {
     /// Called when the queue is changed.
-    fn on_queue_changed(&self, queue: Value) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+    fn on_queue_changed(&self, queue: Queue) -> MoosyncResult<()> {
+        not_implemented()
     }
 
     /// Called when the volume is changed.
-    fn on_volume_changed(&self) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+    fn on_volume_changed(&self, event: VolumeChanged) -> MoosyncResult<()> {
+        not_implemented()
     }
 
     /// Called when the player state is changed.
-    fn on_player_state_changed(&self) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+    fn on_player_state_changed(&self, event: PlayerStateChanged) -> MoosyncResult<()> {
+        not_implemented()
     }
 
     /// Called when the song is changed.
-    fn on_song_changed(&self) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+    fn on_song_changed(&self, event: SongChanged) -> MoosyncResult<()> {
+        not_implemented()
     }
 
     /// Called when the player is seeked to a specific time.
     fn on_seeked(&self, time: f64) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 }
 
@@ -110,11 +194,14 @@ pub trait PlayerEvents {
 /// Trait for handling provider-related events.
 pub trait Provider {
     /// Called when the main app requests the provider scopes.
+    ///
+    /// Annotate the `impl Provider` block with `#[provider_scopes]` to derive the
+    /// scopes from the overridden methods instead of listing them by hand.
     fn get_provider_scopes(&self) -> MoosyncResult<Vec<ExtensionProviderScope>>;
 
     /// Called when the main app requests the list of playlists.
     fn get_playlists(&self) -> MoosyncResult<Vec<QueryablePlaylist>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests the content of a specific playlist.
@@ -122,38 +209,38 @@ pub trait Provider {
         &self,
         id: String,
         next_page_token: Option<String>,
-    ) -> MoosyncResult<Vec<Song>> {
-        Err("Not implemented".into())
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
     }
 
     /// Called when the main app requests a playlist from a URL.
     fn get_playlist_from_url(&self, url: String) -> MoosyncResult<Option<QueryablePlaylist>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests playback details for a song.
     fn get_playback_details(&self, song: Song) -> MoosyncResult<PlaybackDetailsReturnType> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app performs a search.
     fn search(&self, term: String) -> MoosyncResult<SearchResult> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests recommendations.
     fn get_recommendations(&self) -> MoosyncResult<Vec<Song>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests a song from a URL.
     fn get_song_from_url(&self, url: String) -> MoosyncResult<Option<Song>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app handles a custom request.
     fn handle_custom_request(&self, url: String) -> MoosyncResult<CustomRequestReturnType> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests songs of a specific artist.
@@ -161,8 +248,8 @@ pub trait Provider {
         &self,
         artist: QueryableArtist,
         next_page_token: Option<String>,
-    ) -> MoosyncResult<Vec<Song>> {
-        Err("Not implemented".into())
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
     }
 
     /// Called when the main app requests songs of a specific album.
@@ -170,23 +257,23 @@ pub trait Provider {
         &self,
         album: QueryableAlbum,
         next_page_token: Option<String>,
-    ) -> MoosyncResult<Vec<Song>> {
-        Err("Not implemented".into())
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
     }
 
     /// Called when the main app requests a song from an ID.
     fn get_song_from_id(&self, id: String) -> MoosyncResult<Option<Song>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests to scrobble a song.
     fn scrobble(&self, song: Song) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests lyrics for a song.
-    fn get_lyrics(&self, song: Song) -> MoosyncResult<String> {
-        Err("Not implemented".into())
+    fn get_lyrics(&self, song: Song) -> MoosyncResult<Lyrics> {
+        not_implemented()
     }
 }
 
@@ -195,7 +282,7 @@ pub trait Provider {
 pub trait ContextMenu {
     /// Called when the main app requests the context menu for songs.
     fn get_song_context_menu(&self, songs: Vec<Song>) -> MoosyncResult<Vec<ContextMenuReturnType>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app requests the context menu for a playlist.
@@ -203,21 +290,36 @@ pub trait ContextMenu {
         &self,
         playlist: QueryablePlaylist,
     ) -> MoosyncResult<Vec<ContextMenuReturnType>> {
-        Err("Not implemented".into())
+        not_implemented()
     }
 
     /// Called when the main app performs an action from the context menu.
     fn on_context_menu_action(&self, action: String) -> MoosyncResult<()> {
-        Err("Not implemented".into())
+        not_implemented()
+    }
+
+    /// Provider scopes required by the overridden methods of this trait.
+    ///
+    /// Generated by `#[provider_scopes]` and added to the scopes returned by
+    /// [`Provider::get_provider_scopes`].
+    #[doc(hidden)]
+    fn derived_scopes(&self) -> Vec<ExtensionProviderScope> {
+        vec![]
     }
 }
 
 /// Trait that combines all other traits for the extension.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a Moosync extension",
+    label = "`{Self}` does not implement `moosync_edk::api::Extension`",
+    note = "implement `Provider`, `PlayerEvents`, `PreferenceEvents`, `DatabaseEvents`, `Accounts` and `ContextMenu`, then add `impl Extension for {Self} {{}}`"
+)]
 pub trait Extension:
     Provider + PlayerEvents + PreferenceEvents + DatabaseEvents + Accounts + ContextMenu
 {
 }
 
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
 #[host_fn]
 extern "ExtismHost" {
     fn send_main_command(command: MainCommand) -> Option<Value>;
@@ -225,10 +327,28 @@ extern "ExtismHost" {
     fn open_clientfd(path: String) -> i64;
     fn write_sock(sock_id: i64, buf: Vec<u8>) -> i64;
     fn read_sock(sock_id: i64, read_len: u64) -> Vec<u8>;
+    fn close_sock(sock_id: i64) -> i64;
     fn hash(hash_type: String, data: Vec<u8>) -> Vec<u8>;
 }
 
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
+fn config_get(key: &str) -> Result<Option<String>, extism_pdk::Error> {
+    extism_pdk::config::get(key)
+}
+
+#[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+use crate::testing::{
+    close_sock, config_get, hash, open_clientfd, read_sock, send_main_command, system_time,
+    write_sock,
+};
+
 pub mod extension_api {
+    pub mod discord;
+    mod error;
+
+    pub use error::{ApiError, ApiResult};
+
+    use serde::Deserialize;
     use serde_json::Value;
     use types::entities::{GetEntityOptions, QueryablePlaylist};
     use types::errors::{MoosyncError, Result as MoosyncResult};
@@ -238,10 +358,29 @@ pub mod extension_api {
     use types::ui::player_details::PlayerState;
 
     use super::{
-        hash, open_clientfd, read_sock as read_sock_ext, send_main_command, system_time,
-        write_sock as write_sock_ext,
+        close_sock as close_sock_ext, config_get, hash, open_clientfd, read_sock as read_sock_ext,
+        send_main_command, system_time, write_sock as write_sock_ext,
     };
 
+    /// Sends `cmd` to the main app.
+    ///
+    /// The main app reports failures through the host call itself, so any response
+    /// it sends back, including one shaped like `{ "error": ... }`, is a value.
+    /// A failed call is a [`ApiError::Transport`] error when the runtime could not
+    /// encode the command or decode the response, and the main app's error otherwise.
+    fn send_command(command: &'static str, cmd: MainCommand) -> ApiResult<Option<Value>> {
+        unsafe { send_main_command(cmd) }.map_err(|source| {
+            if source.is::<serde_json::Error>() {
+                ApiError::Transport { command, source }
+            } else {
+                ApiError::Host {
+                    command,
+                    message: source.to_string(),
+                }
+            }
+        })
+    }
+
     macro_rules! create_api_fn {
         ($(
             $(#[doc = $doc:literal])*
@@ -252,17 +391,12 @@ pub mod extension_api {
         );* $(;)?) => {
             $(
                 $(#[doc = $doc])*
-                pub fn $fn_name($( $arg_name: $arg_type ),*) -> MoosyncResult<$ret_type> {
-                    unsafe {
-                        match send_main_command(MainCommand::$variant($($arg_name),*)) {
-                            Ok(resp) => {
-                                if let Some(resp) = resp {
-                                    return Ok(serde_json::from_value(resp)?);
-                                }
-                                Err(MoosyncError::String("No response".into()))
-                            }
-                            Err(e) => Err(e.to_string().into()),
-                        }
+                pub fn $fn_name($( $arg_name: $arg_type ),*) -> ApiResult<$ret_type> {
+                    let command = stringify!($variant);
+                    match send_command(command, MainCommand::$variant($($arg_name),*))? {
+                        Some(payload) => <$ret_type>::deserialize(&payload)
+                            .map_err(|source| ApiError::Decode { command, payload, source }),
+                        None => Err(ApiError::NoResponse { command }),
                     }
                 }
             )*
@@ -279,15 +413,9 @@ pub mod extension_api {
         );* $(;)?) => {
             $(
                 $(#[doc = $doc])*
-                pub fn $fn_name($( $arg_name: $arg_type ),*) -> MoosyncResult<$ret_type> {
-                    unsafe {
-                        match send_main_command(MainCommand::$variant($($arg_name),*)) {
-                            Ok(_) => {
-                                return Ok(())
-                            }
-                            Err(e) => Err(e.to_string().into()),
-                        }
-                    }
+                pub fn $fn_name($( $arg_name: $arg_type ),*) -> ApiResult<$ret_type> {
+                    send_command(stringify!($variant), MainCommand::$variant($($arg_name),*))?;
+                    Ok(())
                 }
             )*
         };
@@ -450,6 +578,11 @@ pub mod extension_api {
         }
     }
 
+    /// Reads the config value `key` set by the main app when loading the extension.
+    pub fn get_config(key: &str) -> Option<String> {
+        config_get(key).ok().flatten()
+    }
+
     pub fn open_sock(path: String) -> MoosyncResult<i64> {
         let res = unsafe { open_clientfd(path) };
         res.map_err(|e| MoosyncError::String(e.to_string()))
@@ -465,8 +598,98 @@ pub mod extension_api {
         res.map_err(|e| MoosyncError::String(e.to_string()))
     }
 
+    pub fn close_sock(sock_id: i64) -> MoosyncResult<()> {
+        let res = unsafe { close_sock_ext(sock_id) };
+        res.map(|_| ())
+            .map_err(|e| MoosyncError::String(e.to_string()))
+    }
+
     pub fn gen_hash(hash_type: String, data: Vec<u8>) -> MoosyncResult<Vec<u8>> {
         let res = unsafe { hash(hash_type, data) };
         res.map_err(|e| MoosyncError::String(e.to_string()))
     }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+        use crate::testing::MockHost;
+
+        fn preference(key: &str) -> PreferenceData {
+            PreferenceData {
+                key: key.into(),
+                value: None,
+                default_value: None,
+            }
+        }
+
+        #[test]
+        fn error_shaped_responses_are_values() {
+            let host = MockHost::new();
+            host.queue_response("GetPreference", json!({ "error": "not an error" }));
+
+            let value = get_preference(preference("last_error")).unwrap();
+            assert_eq!(value, json!({ "error": "not an error" }));
+        }
+
+        #[test]
+        fn errors_reported_by_the_main_app_are_host_errors() {
+            let host = MockHost::new();
+            host.queue_error("GetCurrentSong", "song not found");
+            host.queue_error("AddSongs", "permission denied");
+
+            let err = get_current_song().unwrap_err();
+            assert!(
+                matches!(&err, ApiError::Host { message, .. } if message == "song not found"),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.command(), "GetCurrentSong");
+            assert_eq!(
+                err.to_string(),
+                "Main app failed to handle GetCurrentSong: song not found"
+            );
+
+            let err = add_songs(vec![]).unwrap_err();
+            assert!(matches!(
+                &err,
+                ApiError::Host { command: "AddSongs", message } if message == "permission denied"
+            ));
+        }
+
+        #[test]
+        fn runtime_failures_are_transport_errors() {
+            let host = MockHost::new();
+            host.queue_transport_error("GetCurrentSong");
+
+            let err = get_current_song().unwrap_err();
+            assert!(matches!(err, ApiError::Transport { .. }), "{:?}", err);
+            assert_eq!(err.command(), "GetCurrentSong");
+            assert!(err.to_string().starts_with("Failed to send GetCurrentSong"));
+        }
+
+        #[test]
+        fn missing_responses_are_reported() {
+            let host = MockHost::new();
+            host.queue_no_response("GetTime");
+            host.queue_no_response("SetSecure");
+
+            assert!(matches!(
+                get_time(),
+                Err(ApiError::NoResponse { command: "GetTime" })
+            ));
+            assert!(set_secure(preference("token")).is_ok());
+        }
+
+        #[test]
+        fn mismatched_responses_are_decode_errors() {
+            let host = MockHost::new();
+            host.queue_response("GetVolume", "loud");
+
+            let err = get_volume().unwrap_err();
+            assert!(matches!(&err, ApiError::Decode { payload, .. } if payload == "loud"));
+            assert!(err.to_string().contains("payload: \"loud\""));
+        }
+    }
 }
@@ -0,0 +1,832 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Client for Discord's local IPC (RPC) protocol.
+//!
+//! Discord listens on a `discord-ipc-N` socket and exchanges frames made of a
+//! little-endian opcode, a little-endian payload length and a JSON payload.
+//! [`DiscordIpcClient`] performs the handshake, sends `SET_ACTIVITY` commands and
+//! reconnects to whichever socket is available when the connection drops.
+//!
+//! ```ignore
+//! let mut client = DiscordIpcClient::new("1234567890");
+//! client.connect()?;
+//! client.set_activity(&Activity {
+//!     details: Some("Song title".into()),
+//!     state: Some("Artist".into()),
+//!     ..Default::default()
+//! })?;
+//! ```
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use types::errors::{MoosyncError, Result as MoosyncResult};
+
+use super::get_config;
+use crate::socket::UnixStream;
+
+/// Largest frame payload accepted from Discord. Its messages are a few KiB at most.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Config key the main app can set to its runtime directory, i.e. `$XDG_RUNTIME_DIR`
+/// on Linux or `$TMPDIR` on macOS, where Discord creates its sockets.
+pub const RUNTIME_DIR_CONFIG_KEY: &str = "runtime_dir";
+
+/// How long [`DiscordIpcClient::connect`] waits for Discord to answer the
+/// handshake before trying the next socket.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Opcodes of the frames exchanged with Discord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Opcode {
+    Handshake = 0,
+    Frame = 1,
+    Close = 2,
+    Ping = 3,
+    Pong = 4,
+}
+
+impl TryFrom<u32> for Opcode {
+    type Error = MoosyncError;
+
+    fn try_from(value: u32) -> MoosyncResult<Self> {
+        match value {
+            0 => Ok(Opcode::Handshake),
+            1 => Ok(Opcode::Frame),
+            2 => Ok(Opcode::Close),
+            3 => Ok(Opcode::Ping),
+            4 => Ok(Opcode::Pong),
+            _ => Err(MoosyncError::String(format!(
+                "Unknown Discord IPC opcode {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Type of a rich presence activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ActivityType {
+    Playing,
+    #[default]
+    Listening,
+    Watching,
+    Competing,
+}
+
+impl Serialize for ActivityType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value: u8 = match self {
+            ActivityType::Playing => 0,
+            ActivityType::Listening => 2,
+            ActivityType::Watching => 3,
+            ActivityType::Competing => 5,
+        };
+        serializer.serialize_u8(value)
+    }
+}
+
+/// Start and end of an activity, in milliseconds since the unix epoch.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityTimestamps {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+/// Images shown alongside an activity. Keys refer to assets uploaded to the
+/// Discord application or to image URLs.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityAssets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_text: Option<String>,
+}
+
+/// A button linking to a URL, up to two can be shown per activity.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActivityButton {
+    pub label: String,
+    pub url: String,
+}
+
+/// Rich presence activity sent through `SET_ACTIVITY`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Activity {
+    #[serde(rename = "type")]
+    pub activity_type: ActivityType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<ActivityTimestamps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assets: Option<ActivityAssets>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<ActivityButton>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    cmd: Option<String>,
+    evt: Option<String>,
+    nonce: Option<String>,
+    #[serde(default)]
+    data: Value,
+}
+
+fn io_error(err: io::Error) -> MoosyncError {
+    MoosyncError::String(format!("Discord IPC: {}", err))
+}
+
+fn decode_payload(payload: &[u8]) -> MoosyncResult<Value> {
+    serde_json::from_slice(payload)
+        .map_err(|e| MoosyncError::String(format!("Discord IPC: invalid payload: {}", e)))
+}
+
+/// Returns the socket paths Discord may be listening on, in the order they are tried.
+///
+/// The environment of the main app isn't visible to the extension, so the runtime
+/// directory is taken from the [`RUNTIME_DIR_CONFIG_KEY`] config when the main app
+/// sets it. `$XDG_RUNTIME_DIR` and `$TMPDIR`, or `/tmp`, are tried after it.
+pub fn default_socket_paths() -> Vec<String> {
+    let xdg_runtime_dir = std::env::var("XDG_RUNTIME_DIR").ok();
+    let tmp_dir = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".into());
+    let candidates = get_config(RUNTIME_DIR_CONFIG_KEY)
+        .into_iter()
+        .chain(xdg_runtime_dir)
+        .chain([tmp_dir]);
+
+    let mut seen = HashSet::new();
+    let dirs: Vec<String> = candidates
+        .map(|dir| dir.trim_end_matches('/').to_string())
+        .filter(|dir| !dir.is_empty() && seen.insert(dir.clone()))
+        .collect();
+
+    let mut paths = vec![];
+    for dir in &dirs {
+        for sub_dir in ["", "app/com.discordapp.Discord/", "snap.discord/"] {
+            for i in 0..10 {
+                paths.push(format!("{}/{}discord-ipc-{}", dir, sub_dir, i));
+            }
+        }
+    }
+    for i in 0..10 {
+        paths.push(format!(r"\\?\pipe\discord-ipc-{}", i));
+    }
+    paths
+}
+
+/// Opens a stream to the socket at the given path.
+type Connector<S> = Box<dyn FnMut(&str) -> io::Result<S>>;
+
+/// A stream whose reads can time out.
+///
+/// The timeout is shortened to [`HANDSHAKE_TIMEOUT`] during the handshake, so
+/// that a stale socket that accepts the connection but never answers is skipped.
+pub trait ReadTimeout {
+    fn read_timeout(&self) -> Option<Duration>;
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>);
+}
+
+impl ReadTimeout for UnixStream {
+    fn read_timeout(&self) -> Option<Duration> {
+        UnixStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Client for Discord's IPC protocol.
+///
+/// The transport defaults to the sockets opened through the main app. Tests can
+/// substitute any [`Read`] + [`Write`] + [`ReadTimeout`] stream through
+/// [`DiscordIpcClient::with_connector`].
+pub struct DiscordIpcClient<S = UnixStream> {
+    client_id: String,
+    pid: Option<u32>,
+    socket_paths: Vec<String>,
+    connector: Connector<S>,
+    stream: Option<S>,
+    nonce: u64,
+}
+
+impl DiscordIpcClient<UnixStream> {
+    /// Creates a client for the Discord application `client_id`.
+    pub fn new(client_id: impl Into<String>) -> Self {
+        Self::with_connector(client_id, |path| {
+            UnixStream::connect(path).map_err(io::Error::other)
+        })
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> DiscordIpcClient<S> {
+    /// Creates a client that opens its connections through `connector`, which is
+    /// called with each socket path in turn.
+    pub fn with_connector(
+        client_id: impl Into<String>,
+        connector: impl FnMut(&str) -> io::Result<S> + 'static,
+    ) -> Self {
+        Self {
+            client_id: client_id.into(),
+            pid: None,
+            socket_paths: default_socket_paths(),
+            connector: Box::new(connector),
+            stream: None,
+            nonce: 0,
+        }
+    }
+
+    /// Overrides the socket paths tried when connecting.
+    pub fn with_socket_paths(mut self, socket_paths: Vec<String>) -> Self {
+        self.socket_paths = socket_paths;
+        self
+    }
+
+    /// Sets the process id sent along with activities. Discord clears the
+    /// activity once this process exits.
+    pub fn with_pid(mut self, pid: u32) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    /// Checks whether the client holds an open connection.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Connects to the first socket that accepts a handshake.
+    pub fn connect(&mut self) -> MoosyncResult<()> {
+        self.stream = None;
+
+        let mut last_error = None;
+        for path in &self.socket_paths {
+            let mut stream = match (self.connector)(path) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    last_error = Some(io_error(e));
+                    continue;
+                }
+            };
+
+            let timeout = stream.read_timeout();
+            let handshake_timeout = timeout.map_or(HANDSHAKE_TIMEOUT, |t| t.min(HANDSHAKE_TIMEOUT));
+            stream.set_read_timeout(Some(handshake_timeout));
+            let result = Self::handshake(&mut stream, &self.client_id);
+            stream.set_read_timeout(timeout);
+
+            match result {
+                Ok(()) => {
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "Discord IPC: no socket paths to connect to".into()))
+    }
+
+    /// Drops the current connection and connects again.
+    pub fn reconnect(&mut self) -> MoosyncResult<()> {
+        self.close();
+        self.connect()
+    }
+
+    /// Sends a close frame and drops the connection.
+    pub fn close(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = write_frame(&mut stream, Opcode::Close, &json!({}));
+        }
+    }
+
+    /// Shows `activity` on the user's profile.
+    pub fn set_activity(&mut self, activity: &Activity) -> MoosyncResult<Value> {
+        let activity = serde_json::to_value(activity)?;
+        self.send_command("SET_ACTIVITY", self.activity_args(activity))
+    }
+
+    /// Removes the activity from the user's profile.
+    pub fn clear_activity(&mut self) -> MoosyncResult<Value> {
+        self.send_command("SET_ACTIVITY", self.activity_args(Value::Null))
+    }
+
+    /// Sends a command and waits for its response, reconnecting once if the
+    /// connection was lost. Returns the `data` of the response.
+    pub fn send_command(&mut self, cmd: &str, args: Value) -> MoosyncResult<Value> {
+        if self.stream.is_none() {
+            self.connect()?;
+        }
+
+        match self.try_send_command(cmd, &args) {
+            Err(CommandError::Io(_)) => {
+                self.reconnect()?;
+                self.try_send_command(cmd, &args)
+                    .map_err(MoosyncError::from)
+            }
+            res => res.map_err(MoosyncError::from),
+        }
+    }
+
+    fn activity_args(&self, activity: Value) -> Value {
+        match self.pid {
+            Some(pid) => json!({ "pid": pid, "activity": activity }),
+            None => json!({ "activity": activity }),
+        }
+    }
+
+    fn handshake(stream: &mut S, client_id: &str) -> MoosyncResult<()> {
+        write_frame(
+            stream,
+            Opcode::Handshake,
+            &json!({ "v": 1, "client_id": client_id }),
+        )
+        .map_err(io_error)?;
+
+        let (opcode, payload) = read_frame(stream).map_err(io_error)?;
+        let payload = decode_payload(&payload)?;
+        // Close frames carry the reason at the top level, as `code` and `message`.
+        if Opcode::try_from(opcode)? == Opcode::Close {
+            return Err(MoosyncError::String(format!(
+                "Discord IPC: handshake rejected: {}",
+                payload
+            )));
+        }
+
+        let response: Response = serde_json::from_value(payload)?;
+        match (Opcode::try_from(opcode)?, response.evt.as_deref()) {
+            (Opcode::Frame, Some("READY")) => Ok(()),
+            (opcode, evt) => Err(MoosyncError::String(format!(
+                "Discord IPC: unexpected handshake response {:?} {:?}",
+                opcode, evt
+            ))),
+        }
+    }
+
+    fn try_send_command(&mut self, cmd: &str, args: &Value) -> Result<Value, CommandError> {
+        self.nonce += 1;
+        let nonce = self.nonce.to_string();
+
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| CommandError::Io(io::ErrorKind::NotConnected.into()))?;
+        write_frame(
+            stream,
+            Opcode::Frame,
+            &json!({ "cmd": cmd, "args": args, "nonce": nonce }),
+        )
+        .map_err(CommandError::Io)?;
+
+        loop {
+            let (opcode, payload) = match read_frame(stream) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    self.stream = None;
+                    return Err(CommandError::Io(e));
+                }
+            };
+
+            // Invalid frames are protocol errors: the stream is still in sync, so
+            // they don't call for a reconnect.
+            match Opcode::try_from(opcode)? {
+                Opcode::Ping => {
+                    let payload = decode_payload(&payload)?;
+                    write_frame(stream, Opcode::Pong, &payload).map_err(CommandError::Io)?;
+                }
+                Opcode::Close => {
+                    self.stream = None;
+                    return Err(CommandError::Io(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        String::from_utf8_lossy(&payload).into_owned(),
+                    )));
+                }
+                Opcode::Frame => {
+                    let response: Response = serde_json::from_value(decode_payload(&payload)?)
+                        .map_err(MoosyncError::from)?;
+                    if response.nonce.as_deref() != Some(nonce.as_str()) {
+                        continue;
+                    }
+                    if response.evt.as_deref() == Some("ERROR") {
+                        return Err(CommandError::Discord(MoosyncError::String(format!(
+                            "Discord IPC: {} failed: {}",
+                            response.cmd.unwrap_or_default(),
+                            response.data
+                        ))));
+                    }
+                    return Ok(response.data);
+                }
+                Opcode::Handshake | Opcode::Pong => {}
+            }
+        }
+    }
+}
+
+enum CommandError {
+    /// The connection failed and may be recovered by reconnecting.
+    Io(io::Error),
+    /// Discord rejected the command or sent an invalid response.
+    Discord(MoosyncError),
+}
+
+impl From<MoosyncError> for CommandError {
+    fn from(err: MoosyncError) -> Self {
+        CommandError::Discord(err)
+    }
+}
+
+impl From<CommandError> for MoosyncError {
+    fn from(err: CommandError) -> Self {
+        match err {
+            CommandError::Io(e) => io_error(e),
+            CommandError::Discord(e) => e,
+        }
+    }
+}
+
+/// Writes a single frame to `stream`.
+pub fn write_frame<W: Write>(stream: &mut W, opcode: Opcode, payload: &Value) -> io::Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&(opcode as u32).to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads a single frame from `stream`, returning its raw opcode and undecoded payload.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if the payload is larger than
+/// [`MAX_FRAME_SIZE`], without reading it.
+pub fn read_frame<R: Read>(stream: &mut R) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header)?;
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Frame of {} bytes exceeds the limit of {} bytes",
+                len, MAX_FRAME_SIZE
+            ),
+        ));
+    }
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+    Ok((opcode, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::testing::MockHost;
+
+    /// How the stand-in answers the next command.
+    enum Reply {
+        Ok,
+        Close,
+        Drop,
+        Garbage,
+        Ping,
+    }
+
+    /// Plays Discord's side of the protocol.
+    #[derive(Default)]
+    struct Discord {
+        /// Frames received from the client, along with the connection they came from.
+        received: Vec<(usize, u32, Value)>,
+        replies: VecDeque<Reply>,
+        connections: usize,
+        reject_handshake: bool,
+        /// Read timeouts set by the client, along with the connection they were set on.
+        timeouts: Vec<(usize, Option<Duration>)>,
+    }
+
+    struct Connection {
+        id: usize,
+        discord: Rc<RefCell<Discord>>,
+        incoming: Vec<u8>,
+        outgoing: VecDeque<u8>,
+        dropped: bool,
+        read_timeout: Option<Duration>,
+    }
+
+    impl Connection {
+        fn send(&mut self, opcode: Opcode, payload: &Value) {
+            let mut frame = vec![];
+            write_frame(&mut frame, opcode, payload).unwrap();
+            self.outgoing.extend(frame);
+        }
+
+        fn handle(&mut self, opcode: u32, payload: Value) {
+            let mut discord = self.discord.borrow_mut();
+            discord.received.push((self.id, opcode, payload.clone()));
+            match opcode {
+                0 if discord.reject_handshake => {
+                    drop(discord);
+                    self.send(
+                        Opcode::Close,
+                        &json!({ "code": 4000, "message": "Invalid client ID" }),
+                    );
+                }
+                0 => {
+                    drop(discord);
+                    self.send(
+                        Opcode::Frame,
+                        &json!({ "cmd": "DISPATCH", "evt": "READY", "data": {} }),
+                    );
+                }
+                1 => {
+                    let reply = discord.replies.pop_front().unwrap_or(Reply::Ok);
+                    drop(discord);
+                    let response = json!({
+                        "cmd": payload["cmd"],
+                        "nonce": payload["nonce"],
+                        "data": payload["args"],
+                    });
+                    match reply {
+                        Reply::Ok => self.send(Opcode::Frame, &response),
+                        Reply::Close => self.send(Opcode::Close, &json!({ "code": 1000 })),
+                        Reply::Drop => self.dropped = true,
+                        Reply::Garbage => {
+                            self.outgoing.extend(1u32.to_le_bytes());
+                            self.outgoing.extend(3u32.to_le_bytes());
+                            self.outgoing.extend(b"{{{");
+                        }
+                        Reply::Ping => {
+                            self.send(Opcode::Ping, &json!({ "ping": 1 }));
+                            self.send(Opcode::Frame, &response);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.dropped {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            let len = buf.len().min(self.outgoing.len());
+            for (byte, out) in self.outgoing.drain(..len).zip(buf.iter_mut()) {
+                *out = byte;
+            }
+            Ok(len)
+        }
+    }
+
+    impl ReadTimeout for Connection {
+        fn read_timeout(&self) -> Option<Duration> {
+            self.read_timeout
+        }
+
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+            self.discord.borrow_mut().timeouts.push((self.id, timeout));
+            self.read_timeout = timeout;
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.dropped {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.incoming.extend_from_slice(buf);
+            while let Ok((opcode, payload)) = read_frame(&mut Cursor::new(&self.incoming)) {
+                self.incoming.drain(..8 + payload.len());
+                self.handle(opcode, serde_json::from_slice(&payload).unwrap());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client(discord: &Rc<RefCell<Discord>>) -> DiscordIpcClient<Connection> {
+        let discord = discord.clone();
+        DiscordIpcClient::with_connector("1234", move |path| {
+            if path != "/run/user/1000/discord-ipc-1" {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            let id = discord.borrow().connections;
+            discord.borrow_mut().connections += 1;
+            Ok(Connection {
+                id,
+                discord: discord.clone(),
+                incoming: vec![],
+                outgoing: VecDeque::new(),
+                dropped: false,
+                read_timeout: Some(Duration::from_secs(30)),
+            })
+        })
+        .with_socket_paths(vec![
+            "/run/user/1000/discord-ipc-0".into(),
+            "/run/user/1000/discord-ipc-1".into(),
+        ])
+    }
+
+    fn activity() -> Activity {
+        Activity {
+            details: Some("Song".into()),
+            state: Some("Artist".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn connects_with_a_handshake() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        let mut client = client(&discord);
+        client.connect().unwrap();
+
+        assert!(client.is_connected());
+        assert_eq!(
+            discord.borrow().received,
+            [(0, 0, json!({ "v": 1, "client_id": "1234" }))]
+        );
+    }
+
+    #[test]
+    fn handshakes_time_out_quickly() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        let mut client = client(&discord);
+        client.connect().unwrap();
+
+        assert_eq!(
+            discord.borrow().timeouts,
+            [
+                (0, Some(HANDSHAKE_TIMEOUT)),
+                (0, Some(Duration::from_secs(30)))
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_handshakes_fail() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord {
+            reject_handshake: true,
+            ..Default::default()
+        }));
+        let mut client = client(&discord);
+
+        let err = client.connect().unwrap_err();
+        assert!(err.to_string().contains("Invalid client ID"));
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn sets_the_activity() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        let mut client = client(&discord).with_pid(42);
+
+        let data = client.set_activity(&activity()).unwrap();
+        assert_eq!(data["pid"], 42);
+        assert_eq!(data["activity"]["details"], "Song");
+        assert_eq!(data["activity"]["type"], 2);
+
+        client.clear_activity().unwrap();
+        let discord = discord.borrow();
+        let (_, opcode, payload) = &discord.received[2];
+        assert_eq!(*opcode, 1);
+        assert_eq!(payload["cmd"], "SET_ACTIVITY");
+        assert_eq!(payload["nonce"], "2");
+        assert_eq!(payload["args"]["activity"], Value::Null);
+    }
+
+    #[test]
+    fn answers_pings() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        discord.borrow_mut().replies.push_back(Reply::Ping);
+        let mut client = client(&discord);
+
+        client.set_activity(&activity()).unwrap();
+        let discord = discord.borrow();
+        assert_eq!(discord.received[2], (0, 4, json!({ "ping": 1 })));
+    }
+
+    #[test]
+    fn reconnects_after_a_close_frame() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        discord.borrow_mut().replies.push_back(Reply::Close);
+        let mut client = client(&discord);
+
+        client.set_activity(&activity()).unwrap();
+        assert!(client.is_connected());
+        assert_eq!(discord.borrow().connections, 2);
+    }
+
+    #[test]
+    fn reconnects_when_the_stream_drops() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        discord.borrow_mut().replies.push_back(Reply::Drop);
+        let mut client = client(&discord);
+
+        client.set_activity(&activity()).unwrap();
+        let discord = discord.borrow();
+        assert_eq!(discord.connections, 2);
+        let commands: Vec<usize> = discord
+            .received
+            .iter()
+            .filter(|(_, opcode, _)| *opcode == 1)
+            .map(|(connection, _, _)| *connection)
+            .collect();
+        assert_eq!(commands, [0, 1]);
+    }
+
+    #[test]
+    fn invalid_payloads_do_not_reconnect() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        discord.borrow_mut().replies.push_back(Reply::Garbage);
+        let mut client = client(&discord);
+
+        let err = client.set_activity(&activity()).unwrap_err();
+        assert!(err.to_string().contains("invalid payload"));
+        assert!(client.is_connected());
+        assert_eq!(discord.borrow().connections, 1);
+    }
+
+    #[test]
+    fn close_sends_a_close_frame() {
+        MockHost::new();
+        let discord = Rc::new(RefCell::new(Discord::default()));
+        let mut client = client(&discord);
+        client.connect().unwrap();
+        client.close();
+
+        assert!(!client.is_connected());
+        assert_eq!(discord.borrow().received[1], (0, 2, json!({})));
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let mut frame = vec![];
+        frame.extend(1u32.to_le_bytes());
+        frame.extend(u32::MAX.to_le_bytes());
+        let err = read_frame(&mut Cursor::new(frame)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn socket_paths_include_the_runtime_dir() {
+        let host = MockHost::new();
+        host.set_config(RUNTIME_DIR_CONFIG_KEY, "/run/user/1000/");
+        let paths = default_socket_paths();
+
+        assert_eq!(paths[0], "/run/user/1000/discord-ipc-0");
+        assert!(paths.contains(&"/run/user/1000/snap.discord/discord-ipc-9".to_string()));
+        assert!(!paths.iter().any(|path| path.starts_with("/run/user/1001/")));
+        assert!(paths.contains(&r"\\?\pipe\discord-ipc-0".to_string()));
+        let unique: HashSet<&String> = paths.iter().collect();
+        assert_eq!(unique.len(), paths.len());
+        // The runtime dir, `$XDG_RUNTIME_DIR` and the temporary dir, plus the pipes.
+        assert!(paths.len() <= 3 * 30 + 10, "{} paths", paths.len());
+    }
+}
@@ -0,0 +1,115 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::{self, Display};
+
+use serde_json::Value;
+use types::errors::MoosyncError;
+
+/// Longest part of an offending payload included in the error message.
+const PAYLOAD_SNIPPET_LEN: usize = 256;
+
+/// Error returned by the functions in [`extension_api`](super) that send a
+/// command to the main app.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The runtime failed to pass the command to the main app or its response
+    /// back, for example because it could not be encoded.
+    Transport {
+        command: &'static str,
+        source: extism_pdk::Error,
+    },
+    /// The main app reported that it could not handle the command, for example
+    /// because a permission is missing or the entity doesn't exist.
+    Host {
+        command: &'static str,
+        message: String,
+    },
+    /// The main app didn't send a response to a command that expects one.
+    NoResponse { command: &'static str },
+    /// The response could not be decoded into the expected type.
+    Decode {
+        command: &'static str,
+        payload: Value,
+        source: serde_json::Error,
+    },
+}
+
+impl ApiError {
+    /// Name of the `MainCommand` variant that failed.
+    pub fn command(&self) -> &'static str {
+        match self {
+            ApiError::Transport { command, .. }
+            | ApiError::Host { command, .. }
+            | ApiError::NoResponse { command }
+            | ApiError::Decode { command, .. } => command,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport { command, source } => {
+                write!(f, "Failed to send {} to the main app: {}", command, source)
+            }
+            ApiError::Host { command, message } => {
+                write!(f, "Main app failed to handle {}: {}", command, message)
+            }
+            ApiError::NoResponse { command } => {
+                write!(f, "Main app sent no response to {}", command)
+            }
+            ApiError::Decode {
+                command,
+                payload,
+                source,
+            } => {
+                let mut payload = payload.to_string();
+                if payload.len() > PAYLOAD_SNIPPET_LEN {
+                    let mut end = PAYLOAD_SNIPPET_LEN;
+                    while !payload.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    payload.truncate(end);
+                    payload.push_str("...");
+                }
+                write!(
+                    f,
+                    "Failed to decode response to {}: {} (payload: {})",
+                    command, source, payload
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Transport { source, .. } => Some(source.as_ref()),
+            ApiError::Decode { source, .. } => Some(source),
+            ApiError::Host { .. } | ApiError::NoResponse { .. } => None,
+        }
+    }
+}
+
+impl From<ApiError> for MoosyncError {
+    fn from(err: ApiError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
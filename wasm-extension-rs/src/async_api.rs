@@ -0,0 +1,530 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Async variants of the extension traits.
+//!
+//! Implement these instead of the traits in [`crate::api`] to write an extension with
+//! `async fn` methods, and register it through [`register_async_extension`] or
+//! `#[moosync_extension(async)]`. Every call from the main app is driven to completion
+//! on a single-threaded tokio runtime that is set up in `entry`, so the methods can
+//! `.await` timers, tokio synchronization primitives and async code shared with other
+//! projects.
+//!
+//! These traits are a code style, not a way to run host calls concurrently. An
+//! extension runs on a single thread and every host call, including HTTP requests
+//! made through [`http`](crate::http), blocks until the main app answers. Requests
+//! joined with [`tokio::join!`] are therefore sent one after another.
+
+use std::cell::OnceCell;
+use std::future::Future;
+
+use extism_pdk::FnResult;
+use tokio::runtime::{Builder, Handle, Runtime};
+use types::entities::{QueryableAlbum, QueryableArtist, QueryablePlaylist, SearchResult};
+use types::errors::{MoosyncError, Result as MoosyncResult};
+use types::songs::Song;
+use types::ui::extensions::{
+    AccountLoginArgs, ContextMenuReturnType, CustomRequestReturnType, ExtensionAccountDetail,
+    ExtensionProviderScope, PlaybackDetailsReturnType, PreferenceArgs,
+};
+
+use crate::api::{
+    not_implemented, Accounts, ContextMenu, DatabaseEvents, Extension, PaginatedSongs,
+    PlayerEvents, PreferenceEvents, Provider,
+};
+use crate::events::{PlayerStateChanged, Queue, SongChanged, VolumeChanged};
+use crate::handler::register_extension;
+use crate::lyrics::Lyrics;
+
+thread_local!(
+    static RUNTIME: OnceCell<Runtime> = const { OnceCell::new() };
+);
+
+fn build_runtime() -> std::io::Result<Runtime> {
+    Builder::new_current_thread().enable_time().build()
+}
+
+/// Sets up the runtime used to drive async extensions. Called from `entry`.
+pub(crate) fn init_runtime() -> std::io::Result<()> {
+    RUNTIME.with(|rt| {
+        if rt.get().is_none() {
+            let _ = rt.set(build_runtime()?);
+        }
+        Ok(())
+    })
+}
+
+/// Runs a future to completion on the extension's runtime.
+///
+/// Fails if the runtime can't be built, or when called from a future that is
+/// already running on a runtime, like an async extension method.
+pub fn block_on<F: Future>(future: F) -> MoosyncResult<F::Output> {
+    if Handle::try_current().is_ok() {
+        return Err(MoosyncError::String(
+            "block_on cannot be called from async code, await the future instead".into(),
+        ));
+    }
+
+    RUNTIME.with(|rt| {
+        let rt = match rt.get() {
+            Some(rt) => rt,
+            None => {
+                let built = build_runtime().map_err(|e| {
+                    MoosyncError::String(format!("Failed to build the tokio runtime: {}", e))
+                })?;
+                rt.get_or_init(|| built)
+            }
+        };
+        Ok(rt.block_on(future))
+    })
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling account-related events.
+pub trait AsyncAccounts {
+    /// Called when the main app requests the list of accounts.
+    async fn get_accounts(&self) -> MoosyncResult<Vec<ExtensionAccountDetail>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests to perform an account login.
+    async fn perform_account_login(&self, args: AccountLoginArgs) -> MoosyncResult<String> {
+        not_implemented()
+    }
+
+    /// Called when the main app provides an OAuth callback code.
+    async fn oauth_callback(&self, code: String) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Provider scopes required by the overridden methods of this trait.
+    ///
+    /// Generated by `#[provider_scopes]` and added to the scopes returned by
+    /// [`AsyncProvider::get_provider_scopes`].
+    #[doc(hidden)]
+    fn derived_scopes(&self) -> Vec<ExtensionProviderScope> {
+        vec![]
+    }
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling database-related events.
+pub trait AsyncDatabaseEvents {
+    /// Called when a song is added to the database.
+    async fn on_song_added(&self, song: Song) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when a song is removed from the database.
+    async fn on_song_removed(&self, song: Song) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when a playlist is added to the database.
+    async fn on_playlist_added(&self, playlist: QueryablePlaylist) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when a playlist is removed from the database.
+    async fn on_playlist_removed(&self, playlist: QueryablePlaylist) -> MoosyncResult<()> {
+        not_implemented()
+    }
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling preference-related events.
+pub trait AsyncPreferenceEvents {
+    /// Called when preferences are changed.
+    async fn on_preferences_changed(&self, args: PreferenceArgs) -> MoosyncResult<()> {
+        not_implemented()
+    }
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling player-related events.
+pub trait AsyncPlayerEvents {
+    /// Called when the queue is changed.
+    async fn on_queue_changed(&self, queue: Queue) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when the volume is changed.
+    async fn on_volume_changed(&self, event: VolumeChanged) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when the player state is changed.
+    async fn on_player_state_changed(&self, event: PlayerStateChanged) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when the song is changed.
+    async fn on_song_changed(&self, event: SongChanged) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when the player is seeked to a specific time.
+    async fn on_seeked(&self, time: f64) -> MoosyncResult<()> {
+        not_implemented()
+    }
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling provider-related events.
+pub trait AsyncProvider {
+    /// Called when the main app requests the provider scopes.
+    ///
+    /// Annotate the `impl AsyncProvider` block with `#[provider_scopes]` to derive
+    /// the scopes from the overridden methods instead of listing them by hand.
+    fn get_provider_scopes(&self) -> MoosyncResult<Vec<ExtensionProviderScope>>;
+
+    /// Called when the main app requests the list of playlists.
+    async fn get_playlists(&self) -> MoosyncResult<Vec<QueryablePlaylist>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests the content of a specific playlist.
+    async fn get_playlist_content(
+        &self,
+        id: String,
+        next_page_token: Option<String>,
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests a playlist from a URL.
+    async fn get_playlist_from_url(&self, url: String) -> MoosyncResult<Option<QueryablePlaylist>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests playback details for a song.
+    async fn get_playback_details(&self, song: Song) -> MoosyncResult<PlaybackDetailsReturnType> {
+        not_implemented()
+    }
+
+    /// Called when the main app performs a search.
+    async fn search(&self, term: String) -> MoosyncResult<SearchResult> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests recommendations.
+    async fn get_recommendations(&self) -> MoosyncResult<Vec<Song>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests a song from a URL.
+    async fn get_song_from_url(&self, url: String) -> MoosyncResult<Option<Song>> {
+        not_implemented()
+    }
+
+    /// Called when the main app handles a custom request.
+    async fn handle_custom_request(&self, url: String) -> MoosyncResult<CustomRequestReturnType> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests songs of a specific artist.
+    async fn get_artist_songs(
+        &self,
+        artist: QueryableArtist,
+        next_page_token: Option<String>,
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests songs of a specific album.
+    async fn get_album_songs(
+        &self,
+        album: QueryableAlbum,
+        next_page_token: Option<String>,
+    ) -> MoosyncResult<PaginatedSongs> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests a song from an ID.
+    async fn get_song_from_id(&self, id: String) -> MoosyncResult<Option<Song>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests to scrobble a song.
+    async fn scrobble(&self, song: Song) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests lyrics for a song.
+    async fn get_lyrics(&self, song: Song) -> MoosyncResult<Lyrics> {
+        not_implemented()
+    }
+}
+
+#[allow(unused_variables, async_fn_in_trait)]
+/// Async counterpart of the trait for handling context menu-related events.
+pub trait AsyncContextMenu {
+    /// Called when the main app requests the context menu for songs.
+    async fn get_song_context_menu(
+        &self,
+        songs: Vec<Song>,
+    ) -> MoosyncResult<Vec<ContextMenuReturnType>> {
+        not_implemented()
+    }
+
+    /// Called when the main app requests the context menu for a playlist.
+    async fn get_playlist_context_menu(
+        &self,
+        playlist: QueryablePlaylist,
+    ) -> MoosyncResult<Vec<ContextMenuReturnType>> {
+        not_implemented()
+    }
+
+    /// Called when the main app performs an action from the context menu.
+    async fn on_context_menu_action(&self, action: String) -> MoosyncResult<()> {
+        not_implemented()
+    }
+
+    /// Provider scopes required by the overridden methods of this trait.
+    ///
+    /// Generated by `#[provider_scopes]` and added to the scopes returned by
+    /// [`AsyncProvider::get_provider_scopes`].
+    #[doc(hidden)]
+    fn derived_scopes(&self) -> Vec<ExtensionProviderScope> {
+        vec![]
+    }
+}
+
+/// Async counterpart of the trait that combines all other traits for the extension.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not an async Moosync extension",
+    label = "`{Self}` does not implement `moosync_edk::async_api::AsyncExtension`",
+    note = "implement `AsyncProvider`, `AsyncPlayerEvents`, `AsyncPreferenceEvents`, `AsyncDatabaseEvents`, `AsyncAccounts` and `AsyncContextMenu`, then add `impl AsyncExtension for {Self} {{}}`"
+)]
+pub trait AsyncExtension:
+    AsyncProvider
+    + AsyncPlayerEvents
+    + AsyncPreferenceEvents
+    + AsyncDatabaseEvents
+    + AsyncAccounts
+    + AsyncContextMenu
+{
+}
+
+/// Exposes an [`AsyncExtension`] through the blocking [`Extension`] traits.
+struct AsyncExtensionAdapter<T>(T);
+
+macro_rules! impl_blocking_trait {
+    (
+        $trait_name:ident for $async_trait:ident {
+            $(
+                $fn_name:ident (
+                    $( $arg_name:ident : $arg_type:ty ),*
+                ) -> $ret_type:ty
+            );* $(;)?
+        }
+        $( sync { $( $sync_fn:ident() -> $sync_ret:ty );* $(;)? } )?
+    ) => {
+        impl<T: AsyncExtension> $trait_name for AsyncExtensionAdapter<T> {
+            $(
+                fn $fn_name(&self, $( $arg_name: $arg_type ),*) -> MoosyncResult<$ret_type> {
+                    block_on($async_trait::$fn_name(&self.0, $( $arg_name ),*))?
+                }
+            )*
+
+            $($(
+                fn $sync_fn(&self) -> $sync_ret {
+                    $async_trait::$sync_fn(&self.0)
+                }
+            )*)?
+        }
+    };
+}
+
+impl_blocking_trait!(
+    Provider for AsyncProvider {
+        get_playlists() -> Vec<QueryablePlaylist>;
+        get_playlist_content(id: String, next_page_token: Option<String>) -> PaginatedSongs;
+        get_playlist_from_url(url: String) -> Option<QueryablePlaylist>;
+        get_playback_details(song: Song) -> PlaybackDetailsReturnType;
+        search(term: String) -> SearchResult;
+        get_recommendations() -> Vec<Song>;
+        get_song_from_url(url: String) -> Option<Song>;
+        handle_custom_request(url: String) -> CustomRequestReturnType;
+        get_artist_songs(artist: QueryableArtist, next_page_token: Option<String>) -> PaginatedSongs;
+        get_album_songs(album: QueryableAlbum, next_page_token: Option<String>) -> PaginatedSongs;
+        get_song_from_id(id: String) -> Option<Song>;
+        scrobble(song: Song) -> ();
+        get_lyrics(song: Song) -> Lyrics;
+    }
+    sync {
+        get_provider_scopes() -> MoosyncResult<Vec<ExtensionProviderScope>>;
+    }
+);
+
+impl_blocking_trait!(
+    PlayerEvents for AsyncPlayerEvents {
+        on_queue_changed(queue: Queue) -> ();
+        on_volume_changed(event: VolumeChanged) -> ();
+        on_player_state_changed(event: PlayerStateChanged) -> ();
+        on_song_changed(event: SongChanged) -> ();
+        on_seeked(time: f64) -> ();
+    }
+);
+
+impl_blocking_trait!(
+    PreferenceEvents for AsyncPreferenceEvents {
+        on_preferences_changed(args: PreferenceArgs) -> ();
+    }
+);
+
+impl_blocking_trait!(
+    DatabaseEvents for AsyncDatabaseEvents {
+        on_song_added(song: Song) -> ();
+        on_song_removed(song: Song) -> ();
+        on_playlist_added(playlist: QueryablePlaylist) -> ();
+        on_playlist_removed(playlist: QueryablePlaylist) -> ();
+    }
+);
+
+impl_blocking_trait!(
+    Accounts for AsyncAccounts {
+        get_accounts() -> Vec<ExtensionAccountDetail>;
+        perform_account_login(args: AccountLoginArgs) -> String;
+        oauth_callback(code: String) -> ();
+    }
+    sync {
+        derived_scopes() -> Vec<ExtensionProviderScope>;
+    }
+);
+
+impl_blocking_trait!(
+    ContextMenu for AsyncContextMenu {
+        get_song_context_menu(songs: Vec<Song>) -> Vec<ContextMenuReturnType>;
+        get_playlist_context_menu(playlist: QueryablePlaylist) -> Vec<ContextMenuReturnType>;
+        on_context_menu_action(action: String) -> ();
+    }
+    sync {
+        derived_scopes() -> Vec<ExtensionProviderScope>;
+    }
+);
+
+impl<T: AsyncExtension> Extension for AsyncExtensionAdapter<T> {}
+
+/// Registers an async extension. Its methods are run on the runtime set up in `entry`,
+/// one call from the main app at a time.
+#[tracing::instrument(level = "debug", skip(extension))]
+pub fn register_async_extension<T: AsyncExtension + 'static>(extension: T) -> FnResult<()> {
+    register_extension(Box::new(AsyncExtensionAdapter(extension)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+    use types::ui::extensions::ExtensionProviderScope;
+
+    use super::*;
+    use crate::api::is_not_implemented;
+    use crate::handler;
+
+    #[derive(Default)]
+    struct TestExtension;
+
+    impl AsyncProvider for TestExtension {
+        fn get_provider_scopes(&self) -> MoosyncResult<Vec<ExtensionProviderScope>> {
+            Ok(vec![ExtensionProviderScope::Recommendations])
+        }
+
+        async fn get_recommendations(&self) -> MoosyncResult<Vec<Song>> {
+            let (tx, rx) = oneshot::channel();
+            let (_, song) = tokio::join!(
+                async {
+                    tokio::task::yield_now().await;
+                    tx.send(Song::default()).unwrap();
+                },
+                async { rx.await.unwrap() },
+            );
+            Ok(vec![song])
+        }
+
+        async fn get_song_from_id(&self, _id: String) -> MoosyncResult<Option<Song>> {
+            block_on(async { None })
+        }
+    }
+
+    impl AsyncPlayerEvents for TestExtension {}
+    impl AsyncPreferenceEvents for TestExtension {}
+    impl AsyncDatabaseEvents for TestExtension {}
+    impl AsyncAccounts for TestExtension {}
+    impl AsyncContextMenu for TestExtension {
+        fn derived_scopes(&self) -> Vec<ExtensionProviderScope> {
+            vec![ExtensionProviderScope::SongContextMenu]
+        }
+    }
+    impl AsyncExtension for TestExtension {}
+
+    #[test]
+    fn adapter_drives_async_methods() {
+        let adapter = AsyncExtensionAdapter(TestExtension);
+        assert_eq!(Provider::get_recommendations(&adapter).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn adapter_keeps_defaults_and_sync_methods() {
+        let adapter = AsyncExtensionAdapter(TestExtension);
+        let err = Provider::get_playlists(&adapter).unwrap_err();
+        assert!(is_not_implemented(&err));
+
+        assert_eq!(
+            Provider::get_provider_scopes(&adapter).unwrap(),
+            [ExtensionProviderScope::Recommendations]
+        );
+        assert_eq!(
+            ContextMenu::derived_scopes(&adapter),
+            [ExtensionProviderScope::SongContextMenu]
+        );
+        assert!(Accounts::derived_scopes(&adapter).is_empty());
+    }
+
+    #[test]
+    fn registered_extension_is_called_through_the_handler() {
+        init_runtime().unwrap();
+        register_async_extension(TestExtension).unwrap();
+
+        assert_eq!(handler::get_recommendations().unwrap().len(), 1);
+        assert!(handler::on_seeked(1.0).is_ok());
+        assert_eq!(
+            handler::get_provider_scopes().unwrap(),
+            [
+                ExtensionProviderScope::Recommendations,
+                ExtensionProviderScope::SongContextMenu
+            ]
+        );
+    }
+
+    #[test]
+    fn block_on_cannot_be_reentered() {
+        let adapter = AsyncExtensionAdapter(TestExtension);
+        let err = Provider::get_song_from_id(&adapter, "id".into()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("block_on cannot be called from async code"));
+    }
+
+    #[test]
+    fn block_on_runs_futures_outside_the_runtime() {
+        let value = block_on(async {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            1
+        });
+        assert_eq!(value.unwrap(), 1);
+    }
+}
@@ -0,0 +1,460 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Opt-in cache of provider results.
+//!
+//! The main app calls methods like `search` or `get_playlists` repeatedly with the
+//! same arguments. When a TTL is configured for a method, its successful results
+//! are cached, keyed on the method name and its serialized arguments, and
+//! returned without calling the extension until they expire:
+//!
+//! ```ignore
+//! #[moosync_extension(constructor = MyExtension::new)]
+//! struct MyExtension;
+//!
+//! impl MyExtension {
+//!     fn new() -> Self {
+//!         let store = KvStore::open("/state").and_then(|store| store.namespace("cache"));
+//!         let mut config = CacheConfig::new()
+//!             .with_ttl("search", Duration::from_secs(300))
+//!             .with_ttl("get_playlists", Duration::from_secs(60));
+//!         if let Ok(store) = store {
+//!             config = config.with_persistence(store);
+//!         }
+//!         cache::configure(config);
+//!         Self
+//!     }
+//! }
+//! ```
+//!
+//! Only methods that fetch data can be cached: `get_playlists`, `get_playlist_content`,
+//! `get_playlist_from_url`, `get_playback_details`, `search`, `get_recommendations`,
+//! `get_song_from_url`, `get_artist_songs`, `get_album_songs`, `get_song_from_id`
+//! and `get_lyrics`. Errors are never cached.
+//!
+//! Expiry uses the time reported by the main app, which has a resolution of one second.
+//!
+//! A persisted cache is written at most once per flush interval, so the results
+//! cached since the last write are lost if the extension is stopped in between.
+//! Call [`flush`] to write them right away.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::extension_api::get_system_time;
+use crate::storage::KvStore;
+use crate::warn;
+
+/// Key of the cached entries in the persistence store.
+const STORE_KEY: &str = "entries";
+
+/// Which methods are cached and for how long.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    ttls: HashMap<String, Duration>,
+    max_entries: usize,
+    store: Option<KvStore>,
+    flush_interval: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttls: HashMap::new(),
+            max_entries: 256,
+            store: None,
+            flush_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches the results of `method`, named as in the `Provider` trait, for `ttl`.
+    pub fn with_ttl(mut self, method: impl Into<String>, ttl: Duration) -> Self {
+        self.ttls.insert(method.into(), ttl);
+        self
+    }
+
+    /// Sets how many results are kept. When full, the entries closest to expiring
+    /// are dropped first. Defaults to 256.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Stores the cache in `store`, so that it is shared with the next instance
+    /// of the extension. The store should be a namespace used only by the cache.
+    pub fn with_persistence(mut self, store: KvStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets how often a persisted cache is written. Changes made in between are
+    /// written together. Defaults to one minute.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    value: Value,
+    expires_at: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    config: CacheConfig,
+    entries: HashMap<String, Entry>,
+    /// Whether the entries changed since they were last persisted.
+    dirty: bool,
+    /// When the entries were last persisted.
+    flushed_at: Option<u64>,
+}
+
+thread_local!(
+    static CACHE: RefCell<Cache> = RefCell::new(Cache::default());
+);
+
+/// Enables caching with `config`, replacing any previous configuration.
+///
+/// If persistence is enabled, the entries stored by a previous instance are loaded.
+pub fn configure(config: CacheConfig) {
+    let mut entries = HashMap::new();
+    if let Some(store) = &config.store {
+        match store.get::<HashMap<String, Entry>>(STORE_KEY) {
+            Ok(stored) => entries = stored.unwrap_or_default(),
+            Err(e) => warn!("Failed to load response cache: {}", e),
+        }
+        let now = get_system_time();
+        entries.retain(|_, entry: &mut Entry| entry.expires_at > now);
+    }
+
+    CACHE.with(|cache| {
+        *cache.borrow_mut() = Cache {
+            config,
+            entries,
+            ..Default::default()
+        };
+    });
+}
+
+/// Drops every cached result of `method`.
+pub fn invalidate(method: &str) {
+    let prefix = format!("{}:", method);
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.entries.retain(|key, _| !key.starts_with(&prefix));
+        cache.dirty = true;
+    });
+    persist(get_system_time(), true);
+}
+
+/// Drops every cached result.
+pub fn clear() {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.entries.clear();
+        cache.dirty = true;
+    });
+    persist(get_system_time(), true);
+}
+
+/// Writes a persisted cache now, if it changed since it was last written.
+pub fn flush() {
+    persist(get_system_time(), true);
+}
+
+/// Returns the cache key for a call to `method`, or `None` if it isn't cached.
+pub(crate) fn key<A: Serialize>(method: &str, args: &A) -> Option<String> {
+    let cached = CACHE.with(|cache| cache.borrow().config.ttls.contains_key(method));
+    if !cached {
+        return None;
+    }
+    let args = serde_json::to_string(args).ok()?;
+    Some(format!("{}:{}", method, args))
+}
+
+/// Returns the cached result for `key`, or calls `f` and caches its result.
+pub(crate) fn cached<T, E>(
+    method: &str,
+    key: Option<String>,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let Some(key) = key else {
+        return f();
+    };
+
+    let now = get_system_time();
+    if let Some(value) = lookup(&key, now) {
+        return Ok(value);
+    }
+
+    let value = f()?;
+    if let Ok(json) = serde_json::to_value(&value) {
+        insert(method, key, json, now);
+    }
+    Ok(value)
+}
+
+fn lookup<T: DeserializeOwned>(key: &str, now: u64) -> Option<T> {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let entry = cache.entries.get(key)?;
+        if entry.expires_at <= now {
+            cache.entries.remove(key);
+            return None;
+        }
+        match serde_json::from_value(entry.value.clone()) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                // Stored by a version of the extension with a different return type.
+                cache.entries.remove(key);
+                None
+            }
+        }
+    })
+}
+
+fn insert(method: &str, key: String, value: Value, now: u64) {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let Some(ttl) = cache.config.ttls.get(method) else {
+            return;
+        };
+        let ttl = ttl.as_secs().max(1);
+
+        let max_entries = cache.config.max_entries;
+        cache.entries.retain(|_, entry| entry.expires_at > now);
+        while !cache.entries.is_empty() && cache.entries.len() >= max_entries {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.entries.remove(&oldest);
+            }
+        }
+
+        if max_entries > 0 {
+            cache.entries.insert(
+                key,
+                Entry {
+                    value,
+                    expires_at: now.saturating_add(ttl),
+                },
+            );
+        }
+        cache.dirty = true;
+    });
+    persist(now, false);
+}
+
+/// Writes the entries to the persistence store if they changed and, unless
+/// `force` is set, the flush interval has passed since the last write.
+fn persist(now: u64, force: bool) {
+    let stored = CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let store = cache.config.store.clone()?;
+        let interval = cache.config.flush_interval.as_secs();
+        let due = cache
+            .flushed_at
+            .is_none_or(|flushed_at| now >= flushed_at.saturating_add(interval));
+        if !cache.dirty || !(force || due) {
+            return None;
+        }
+        cache.dirty = false;
+        cache.flushed_at = Some(now);
+        Some((store, cache.entries.clone()))
+    });
+
+    if let Some((store, entries)) = stored {
+        if let Err(e) = store.put(STORE_KEY, &entries) {
+            warn!("Failed to persist response cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::testing::MockHost;
+
+    fn setup(config: CacheConfig) -> MockHost {
+        let host = MockHost::new();
+        host.set_system_time(1000);
+        configure(config.with_ttl("search", Duration::from_secs(10)));
+        host
+    }
+
+    fn search(query: &str, calls: &Cell<u32>) -> Result<String, String> {
+        let key = key("search", &(&query,));
+        cached("search", key, || {
+            calls.set(calls.get() + 1);
+            Ok(format!("{} #{}", query, calls.get()))
+        })
+    }
+
+    #[test]
+    fn keys_on_method_and_arguments() {
+        let _host = setup(CacheConfig::new());
+        assert_eq!(
+            key("search", &(&"daft punk", &1)).as_deref(),
+            Some(r#"search:["daft punk",1]"#)
+        );
+        assert_ne!(key("search", &(&"a",)), key("search", &(&"b",)));
+        assert_eq!(key("get_playlists", &(&"a",)), None);
+    }
+
+    #[test]
+    fn results_expire_after_ttl() {
+        let host = setup(CacheConfig::new());
+        let calls = Cell::new(0);
+
+        assert_eq!(search("a", &calls).unwrap(), "a #1");
+        host.set_system_time(1009);
+        assert_eq!(search("a", &calls).unwrap(), "a #1");
+        assert_eq!(search("b", &calls).unwrap(), "b #2");
+
+        host.set_system_time(1010);
+        assert_eq!(search("a", &calls).unwrap(), "a #3");
+    }
+
+    #[test]
+    fn errors_and_uncached_methods_call_through() {
+        let _host = setup(CacheConfig::new());
+        let calls = Cell::new(0);
+        let failing = || {
+            cached("search", key("search", &(&"x",)), || {
+                calls.set(calls.get() + 1);
+                Err::<String, _>("offline")
+            })
+        };
+        assert!(failing().is_err());
+        assert!(failing().is_err());
+        assert_eq!(calls.get(), 2);
+
+        let uncached = || cached("get_playlists", None, || Ok::<_, ()>(calls.get()));
+        calls.set(5);
+        assert_eq!(uncached(), Ok(5));
+        calls.set(6);
+        assert_eq!(uncached(), Ok(6));
+    }
+
+    #[test]
+    fn evicts_entries_closest_to_expiring() {
+        let host = setup(
+            CacheConfig::new()
+                .with_max_entries(2)
+                .with_ttl("get_playlists", Duration::from_secs(100)),
+        );
+        let calls = Cell::new(0);
+        cached("get_playlists", key("get_playlists", &()), || {
+            Ok::<_, ()>(0)
+        })
+        .unwrap();
+        search("a", &calls).unwrap();
+        host.set_system_time(1001);
+        search("b", &calls).unwrap();
+
+        // "a" expired first and was dropped to make room for "b".
+        assert_eq!(search("b", &calls).unwrap(), "b #2");
+        assert_eq!(search("a", &calls).unwrap(), "a #3");
+    }
+
+    #[test]
+    fn invalidate_drops_one_method() {
+        let _host = setup(CacheConfig::new().with_ttl("search_more", Duration::from_secs(10)));
+        let calls = Cell::new(0);
+        search("a", &calls).unwrap();
+        cached("search_more", key("search_more", &()), || Ok::<_, ()>(7)).unwrap();
+
+        invalidate("search");
+        assert_eq!(search("a", &calls).unwrap(), "a #2");
+        assert_eq!(
+            cached("search_more", key("search_more", &()), || Ok::<_, ()>(8)),
+            Ok(7)
+        );
+
+        clear();
+        assert_eq!(search("a", &calls).unwrap(), "a #3");
+    }
+
+    #[test]
+    fn persists_in_batches() {
+        let store = KvStore::in_memory();
+        let host = setup(CacheConfig::new().with_persistence(store.clone()));
+        let calls = Cell::new(0);
+        let stored = || {
+            let entries: HashMap<String, Entry> = store.get(STORE_KEY).unwrap().unwrap_or_default();
+            let mut keys: Vec<_> = entries.into_keys().collect();
+            keys.sort();
+            keys
+        };
+
+        // The first change is written right away, later ones once per interval.
+        search("a", &calls).unwrap();
+        assert_eq!(stored(), vec![r#"search:["a"]"#]);
+        search("b", &calls).unwrap();
+        assert_eq!(stored(), vec![r#"search:["a"]"#]);
+
+        flush();
+        assert_eq!(stored(), vec![r#"search:["a"]"#, r#"search:["b"]"#]);
+
+        host.set_system_time(1005);
+        search("c", &calls).unwrap();
+        assert_eq!(stored().len(), 2);
+        host.set_system_time(1060);
+        search("d", &calls).unwrap();
+        // Expired entries are dropped on insert.
+        assert_eq!(stored(), vec![r#"search:["d"]"#]);
+    }
+
+    #[test]
+    fn loads_unexpired_entries_from_the_store() {
+        let store = KvStore::in_memory();
+        let host = setup(CacheConfig::new().with_persistence(store.clone()));
+        let calls = Cell::new(0);
+        search("a", &calls).unwrap();
+        host.set_system_time(1005);
+        search("b", &calls).unwrap();
+        flush();
+
+        host.set_system_time(1012);
+        configure(
+            CacheConfig::new()
+                .with_ttl("search", Duration::from_secs(10))
+                .with_persistence(store),
+        );
+        assert_eq!(search("b", &calls).unwrap(), "b #2");
+        assert_eq!(search("a", &calls).unwrap(), "a #3");
+    }
+}
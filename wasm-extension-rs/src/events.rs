@@ -0,0 +1,383 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Payloads of the events sent by the main app.
+//!
+//! Versions of the main app that predate these payloads send the song, volume and
+//! player state events without any input. The new value is then `None`. When the
+//! main app doesn't send the previous value, it is filled in with the last value
+//! the extension received.
+
+use std::cell::RefCell;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use types::songs::Song;
+use types::ui::player_details::PlayerState;
+
+/// What changed in the queue.
+///
+/// Kinds added by newer versions of the main app are read as [`QueueChangeKind::Unknown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum QueueChangeKind {
+    /// Songs were added to the queue.
+    Added,
+    /// Songs were removed from the queue.
+    Removed,
+    /// Songs were moved within the queue.
+    Moved,
+    /// The queue was replaced with new songs.
+    Replaced,
+    /// The queue was emptied.
+    Cleared,
+    /// Only the current index changed.
+    IndexChanged,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+/// The queue of the player, sent to [`on_queue_changed`](crate::api::PlayerEvents::on_queue_changed).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Queue {
+    /// Songs in the queue, in playback order.
+    #[serde(default, alias = "songQueue")]
+    pub songs: Vec<Song>,
+    /// Position of the current song in `songs`, or `None` if nothing is playing.
+    #[serde(
+        default,
+        alias = "currentIndex",
+        deserialize_with = "deserialize_index"
+    )]
+    pub index: Option<usize>,
+    /// What changed since the previous event.
+    #[serde(default, alias = "changeKind")]
+    pub change: QueueChangeKind,
+    /// Fields not known to this version of the SDK.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Queue {
+    /// Returns the song at the current index.
+    pub fn current(&self) -> Option<&Song> {
+        self.songs.get(self.index?)
+    }
+}
+
+/// Reads an index, treating negative values, like `-1`, as no index.
+fn deserialize_index<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<usize>, D::Error> {
+    let index = Option::<i64>::deserialize(deserializer)?;
+    Ok(index.and_then(|index| usize::try_from(index).ok()))
+}
+
+/// Sent to [`on_song_changed`](crate::api::PlayerEvents::on_song_changed).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongChanged {
+    /// The song now playing, or `None` if the player was emptied.
+    #[serde(default)]
+    pub song: Option<Song>,
+    #[serde(default)]
+    pub previous: Option<Song>,
+}
+
+/// Sent to [`on_volume_changed`](crate::api::PlayerEvents::on_volume_changed).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeChanged {
+    #[serde(default)]
+    pub volume: Option<f64>,
+    #[serde(default)]
+    pub previous: Option<f64>,
+}
+
+/// Sent to [`on_player_state_changed`](crate::api::PlayerEvents::on_player_state_changed).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStateChanged {
+    #[serde(default)]
+    pub state: Option<PlayerState>,
+    #[serde(default)]
+    pub previous: Option<PlayerState>,
+}
+
+/// Last values received by the extension, used when the main app doesn't send
+/// the previous one.
+#[derive(Default)]
+struct LastValues {
+    song: Option<Song>,
+    volume: Option<f64>,
+    state: Option<PlayerState>,
+}
+
+thread_local!(
+    static LAST: RefCell<LastValues> = RefCell::new(LastValues::default());
+);
+
+/// Parses the input of an event, returning `None` if the main app sent nothing.
+fn parse_payload<T: DeserializeOwned>(input: &[u8]) -> serde_json::Result<Option<T>> {
+    if input.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    serde_json::from_slice(input)
+}
+
+fn track<T: Clone>(
+    sent: bool,
+    current: &Option<T>,
+    previous: &mut Option<T>,
+    last: &mut Option<T>,
+) {
+    if previous.is_none() {
+        previous.clone_from(last);
+    }
+    if sent {
+        last.clone_from(current);
+    }
+}
+
+pub(crate) fn song_changed(input: &[u8]) -> serde_json::Result<SongChanged> {
+    let payload = parse_payload::<SongChanged>(input)?;
+    let sent = payload.is_some();
+    let mut event = payload.unwrap_or_default();
+    LAST.with(|last| {
+        track(
+            sent,
+            &event.song,
+            &mut event.previous,
+            &mut last.borrow_mut().song,
+        )
+    });
+    Ok(event)
+}
+
+pub(crate) fn volume_changed(input: &[u8]) -> serde_json::Result<VolumeChanged> {
+    let payload = parse_payload::<VolumeChanged>(input)?;
+    let sent = payload.is_some();
+    let mut event = payload.unwrap_or_default();
+    LAST.with(|last| {
+        track(
+            sent,
+            &event.volume,
+            &mut event.previous,
+            &mut last.borrow_mut().volume,
+        )
+    });
+    Ok(event)
+}
+
+pub(crate) fn player_state_changed(input: &[u8]) -> serde_json::Result<PlayerStateChanged> {
+    let payload = parse_payload::<PlayerStateChanged>(input)?;
+    let sent = payload.is_some();
+    let mut event = payload.unwrap_or_default();
+    LAST.with(|last| {
+        track(
+            sent,
+            &event.state,
+            &mut event.previous,
+            &mut last.borrow_mut().state,
+        )
+    });
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn song(title: &str) -> Song {
+        let mut song = Song::default();
+        song.song.title = Some(title.into());
+        song
+    }
+
+    fn queue(payload: Value) -> Queue {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn queue_change_kinds_are_read() {
+        for (name, kind) in [
+            ("added", QueueChangeKind::Added),
+            ("removed", QueueChangeKind::Removed),
+            ("moved", QueueChangeKind::Moved),
+            ("replaced", QueueChangeKind::Replaced),
+            ("cleared", QueueChangeKind::Cleared),
+            ("indexChanged", QueueChangeKind::IndexChanged),
+        ] {
+            assert_eq!(queue(json!({ "changeKind": name })).change, kind);
+        }
+    }
+
+    #[test]
+    fn unknown_or_missing_change_kinds_are_unknown() {
+        assert_eq!(
+            queue(json!({ "changeKind": "shuffled" })).change,
+            QueueChangeKind::Unknown
+        );
+        assert_eq!(queue(json!({})).change, QueueChangeKind::Unknown);
+    }
+
+    #[test]
+    fn queue_is_read_from_host_names() {
+        let queue = queue(json!({
+            "songQueue": [song("a"), song("b")],
+            "currentIndex": 1,
+            "changeKind": "added",
+            "repeat": true,
+        }));
+        assert_eq!(queue.songs.len(), 2);
+        assert_eq!(queue.index, Some(1));
+        assert_eq!(queue.current(), Some(&song("b")));
+        assert_eq!(queue.extra.get("repeat"), Some(&json!(true)));
+    }
+
+    #[test]
+    fn negative_indexes_are_no_index() {
+        let negative = queue(json!({ "songQueue": [song("a")], "currentIndex": -1 }));
+        assert_eq!(negative.index, None);
+        assert_eq!(negative.current(), None);
+        assert_eq!(queue(json!({ "index": null })).index, None);
+        assert_eq!(queue(json!({})).index, None);
+    }
+
+    #[test]
+    fn indexes_past_the_end_have_no_current_song() {
+        let queue = queue(json!({ "songs": [song("a")], "index": 3 }));
+        assert_eq!(queue.index, Some(3));
+        assert_eq!(queue.current(), None);
+    }
+
+    #[test]
+    fn empty_input_is_not_sent() {
+        let event = song_changed(b"").unwrap();
+        assert_eq!(event.song, None);
+        assert_eq!(event.previous, None);
+        assert_eq!(volume_changed(b" \n").unwrap().volume, None);
+        assert_eq!(player_state_changed(b"").unwrap().state, None);
+    }
+
+    #[test]
+    fn null_input_is_not_sent() {
+        assert_eq!(song_changed(b"null").unwrap().song, None);
+        assert_eq!(volume_changed(b"null").unwrap().volume, None);
+        assert_eq!(player_state_changed(b"null").unwrap().state, None);
+    }
+
+    #[test]
+    fn payloads_are_read() {
+        let payload = serde_json::to_vec(&json!({ "song": song("a") })).unwrap();
+        assert_eq!(song_changed(&payload).unwrap().song, Some(song("a")));
+
+        let payload = br#"{"volume": 40.0, "previous": 20.0}"#;
+        let event = volume_changed(payload).unwrap();
+        assert_eq!(event.volume, Some(40.0));
+        assert_eq!(event.previous, Some(20.0));
+
+        let payload = serde_json::to_vec(&json!({ "state": PlayerState::Playing })).unwrap();
+        let event = player_state_changed(&payload).unwrap();
+        assert_eq!(event.state, Some(PlayerState::Playing));
+    }
+
+    #[test]
+    fn malformed_payloads_are_errors() {
+        assert!(volume_changed(br#"{"volume": "loud"}"#).is_err());
+        assert!(song_changed(b"{").is_err());
+    }
+
+    fn song_payload(title: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "song": song(title) })).unwrap()
+    }
+
+    fn state_payload(state: PlayerState) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "state": state })).unwrap()
+    }
+
+    #[test]
+    fn first_events_have_no_previous_value() {
+        assert_eq!(song_changed(&song_payload("a")).unwrap().previous, None);
+        assert_eq!(
+            volume_changed(br#"{"volume": 10.0}"#).unwrap().previous,
+            None
+        );
+        let event = player_state_changed(&state_payload(PlayerState::Playing)).unwrap();
+        assert_eq!(event.previous, None);
+    }
+
+    #[test]
+    fn later_events_carry_the_last_value() {
+        song_changed(&song_payload("a")).unwrap();
+        let event = song_changed(&song_payload("b")).unwrap();
+        assert_eq!(event.previous, Some(song("a")));
+        assert_eq!(
+            song_changed(&song_payload("c")).unwrap().previous,
+            Some(song("b"))
+        );
+
+        volume_changed(br#"{"volume": 10.0}"#).unwrap();
+        let event = volume_changed(br#"{"volume": 20.0}"#).unwrap();
+        assert_eq!(event.previous, Some(10.0));
+
+        player_state_changed(&state_payload(PlayerState::Playing)).unwrap();
+        let event = player_state_changed(&state_payload(PlayerState::Paused)).unwrap();
+        assert_eq!(event.previous, Some(PlayerState::Playing));
+    }
+
+    #[test]
+    fn previous_values_sent_by_the_main_app_are_kept() {
+        volume_changed(br#"{"volume": 10.0}"#).unwrap();
+        let event = volume_changed(br#"{"volume": 30.0, "previous": 20.0}"#).unwrap();
+        assert_eq!(event.previous, Some(20.0));
+        assert_eq!(
+            volume_changed(br#"{"volume": 40.0}"#).unwrap().previous,
+            Some(30.0)
+        );
+    }
+
+    #[test]
+    fn empty_events_keep_the_last_value() {
+        song_changed(&song_payload("a")).unwrap();
+        assert_eq!(song_changed(b"").unwrap().previous, Some(song("a")));
+        assert_eq!(
+            song_changed(&song_payload("b")).unwrap().previous,
+            Some(song("a"))
+        );
+    }
+
+    #[test]
+    fn kinds_are_tracked_separately() {
+        song_changed(&song_payload("a")).unwrap();
+        volume_changed(br#"{"volume": 10.0}"#).unwrap();
+        player_state_changed(&state_payload(PlayerState::Playing)).unwrap();
+
+        volume_changed(br#"{"volume": 20.0}"#).unwrap();
+        player_state_changed(b"").unwrap();
+        let event = song_changed(&song_payload("b")).unwrap();
+        assert_eq!(event.previous, Some(song("a")));
+
+        song_changed(b"").unwrap();
+        assert_eq!(volume_changed(b"").unwrap().previous, Some(20.0));
+        let event = player_state_changed(&state_payload(PlayerState::Paused)).unwrap();
+        assert_eq!(event.previous, Some(PlayerState::Playing));
+    }
+}
@@ -16,17 +16,22 @@
 
 use std::{cell::RefCell, rc::Rc};
 
-use extism_pdk::FnResult;
-use serde_json::Value;
+use extism_pdk::{Error, FnResult, WithReturnCode};
 use types::entities::{QueryableAlbum, QueryableArtist, QueryablePlaylist, SearchResult};
-use types::errors::Result as MoosyncResult;
+use types::errors::MoosyncError;
 use types::songs::Song;
 use types::ui::extensions::{
     AccountLoginArgs, ContextMenuReturnType, CustomRequestReturnType, ExtensionAccountDetail,
     ExtensionProviderScope, PlaybackDetailsReturnType, PreferenceArgs,
 };
 
-use crate::api::Extension;
+use crate::api::{
+    is_not_implemented, reset_not_implemented, Accounts, ContextMenu, Extension, PaginatedSongs,
+    NOT_IMPLEMENTED_CODE,
+};
+use crate::cache;
+use crate::events::{PlayerStateChanged, Queue, SongChanged, VolumeChanged};
+use crate::lyrics::Lyrics;
 
 macro_rules! generate_extension_methods {
     ($(
@@ -35,19 +40,64 @@ macro_rules! generate_extension_methods {
         ) -> $ret_type:ty
     );* $(;)?) => {
         $(
-            pub(crate) fn $fn_name($( $arg_name: $arg_type ),*) -> $ret_type {
-                EXTENSION.with(|ext| {
-                    if let Some(ext) = ext.borrow().as_ref() {
-                        ext.$fn_name($( $arg_name ),*)
-                    } else {
-                        panic!("No extension registered");
-                    }
+            pub(crate) fn $fn_name($( $arg_name: $arg_type ),*) -> FnResult<$ret_type> {
+                let ext = registered_extension(stringify!($fn_name))?;
+                reset_not_implemented();
+                ext.$fn_name($( $arg_name ),*).map_err(into_fn_error)
+            }
+        )*
+    };
+}
+
+/// Like `generate_extension_methods`, going through the response [`cache`](crate::cache).
+macro_rules! generate_cached_methods {
+    ($(
+        $fn_name:ident (
+            $( $arg_name:ident : $arg_type:ty ),*
+        ) -> $ret_type:ty
+    );* $(;)?) => {
+        $(
+            pub(crate) fn $fn_name($( $arg_name: $arg_type ),*) -> FnResult<$ret_type> {
+                let ext = registered_extension(stringify!($fn_name))?;
+                let key = cache::key(stringify!($fn_name), &($( &$arg_name, )*));
+                cache::cached(stringify!($fn_name), key, || {
+                    reset_not_implemented();
+                    ext.$fn_name($( $arg_name ),*).map_err(into_fn_error)
                 })
             }
         )*
     };
 }
 
+macro_rules! generate_event_methods {
+    ($(
+        $fn_name:ident (
+            $( $arg_name:ident : $arg_type:ty ),*
+        )
+    );* $(;)?) => {
+        $(
+            pub(crate) fn $fn_name($( $arg_name: $arg_type ),*) -> FnResult<()> {
+                let ext = registered_extension(stringify!($fn_name))?;
+                reset_not_implemented();
+                match ext.$fn_name($( $arg_name ),*) {
+                    Err(e) if is_not_implemented(&e) => Ok(()),
+                    res => res.map_err(into_fn_error),
+                }
+            }
+        )*
+    };
+}
+
+/// Converts an extension error into the error returned across the plugin boundary.
+/// Unimplemented methods are reported with [`NOT_IMPLEMENTED_CODE`].
+fn into_fn_error(err: MoosyncError) -> WithReturnCode<Error> {
+    if is_not_implemented(&err) {
+        WithReturnCode::new(err.into(), NOT_IMPLEMENTED_CODE)
+    } else {
+        err.into()
+    }
+}
+
 thread_local!(
     static EXTENSION: RefCell<Option<Rc<Box<dyn Extension>>>> = RefCell::new(None);
 );
@@ -60,46 +110,164 @@ pub fn register_extension(extension: Box<dyn Extension>) -> FnResult<()> {
     Ok(())
 }
 
+/// Checks whether an extension was registered through [`register_extension`].
+pub fn is_extension_registered() -> bool {
+    EXTENSION.with(|ext| ext.borrow().is_some())
+}
+
+/// Returns the registered extension, or an error naming the exported function
+/// that was called before any extension was registered.
+fn registered_extension(method: &str) -> FnResult<Rc<Box<dyn Extension>>> {
+    EXTENSION.with(|ext| ext.borrow().clone()).ok_or_else(|| {
+        Error::msg(format!(
+            "{}_wrapper was called but no extension is registered; \
+            make sure register_extension is called from init",
+            method
+        ))
+        .into()
+    })
+}
+
+/// Collects the scopes returned by `Provider::get_provider_scopes` along with
+/// the ones derived from the `Accounts` and `ContextMenu` implementations.
+pub(crate) fn get_provider_scopes() -> FnResult<Vec<ExtensionProviderScope>> {
+    let ext = registered_extension("get_provider_scopes")?;
+    let ext: &dyn Extension = ext.as_ref().as_ref();
+    reset_not_implemented();
+    let mut scopes = ext.get_provider_scopes().map_err(into_fn_error)?;
+    let derived = Accounts::derived_scopes(ext)
+        .into_iter()
+        .chain(ContextMenu::derived_scopes(ext));
+    for scope in derived {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    Ok(scopes)
+}
+
+generate_cached_methods!(
+    // Provider trait methods that only fetch data
+    get_playlists() -> Vec<QueryablePlaylist>;
+    get_playlist_content(id: String, next_page_token: Option<String>) -> PaginatedSongs;
+    get_playlist_from_url(url: String) -> Option<QueryablePlaylist>;
+    get_playback_details(song: Song) -> PlaybackDetailsReturnType;
+    search(term: String) -> SearchResult;
+    get_recommendations() -> Vec<Song>;
+    get_song_from_url(url: String) -> Option<Song>;
+    get_artist_songs(artist: QueryableArtist, next_page_token: Option<String>) -> PaginatedSongs;
+    get_album_songs(album: QueryableAlbum, next_page_token: Option<String>) -> PaginatedSongs;
+    get_song_from_id(id: String) -> Option<Song>;
+    get_lyrics(song: Song) -> Lyrics;
+);
+
 generate_extension_methods!(
     // Provider trait methods
-    get_provider_scopes() -> MoosyncResult<Vec<ExtensionProviderScope>>;
-    get_playlists() -> MoosyncResult<Vec<QueryablePlaylist>>;
-    get_playlist_content(id: String, next_page_token: Option<String>) -> MoosyncResult<Vec<Song>>;
-    get_playlist_from_url(url: String) -> MoosyncResult<Option<QueryablePlaylist>>;
-    get_playback_details(song: Song) -> MoosyncResult<PlaybackDetailsReturnType>;
-    search(term: String) -> MoosyncResult<SearchResult>;
-    get_recommendations() -> MoosyncResult<Vec<Song>>;
-    get_song_from_url(url: String) -> MoosyncResult<Option<Song>>;
-    handle_custom_request(url: String) -> MoosyncResult<CustomRequestReturnType>;
-    get_artist_songs(artist: QueryableArtist, next_page_token: Option<String>) -> MoosyncResult<Vec<Song>>;
-    get_album_songs(album: QueryableAlbum, next_page_token: Option<String>) -> MoosyncResult<Vec<Song>>;
-    get_song_from_id(id: String) -> MoosyncResult<Option<Song>>;
-    scrobble(song: Song) -> MoosyncResult<()>;
-    oauth_callback(code: String) -> MoosyncResult<()>;
-    get_lyrics(song: Song) -> MoosyncResult<String>;
+    handle_custom_request(url: String) -> CustomRequestReturnType;
+    scrobble(song: Song) -> ();
+    oauth_callback(code: String) -> ();
+
+    // Account trait methods
+    get_accounts() -> Vec<ExtensionAccountDetail>;
+    perform_account_login(args: AccountLoginArgs) -> String;
+
+    // ContextMenu trait methods
+    get_song_context_menu(songs: Vec<Song>) -> Vec<ContextMenuReturnType>;
+    get_playlist_context_menu(playlist: QueryablePlaylist) -> Vec<ContextMenuReturnType>;
+    on_context_menu_action(action: String) -> ();
+);
 
+generate_event_methods!(
     // PlayerEvents trait methods
-    on_queue_changed(queue: Value) -> MoosyncResult<()>;
-    on_volume_changed() -> MoosyncResult<()>;
-    on_player_state_changed() -> MoosyncResult<()>;
-    on_song_changed() -> MoosyncResult<()>;
-    on_seeked(time: f64) -> MoosyncResult<()>;
+    on_queue_changed(queue: Queue);
+    on_volume_changed(event: VolumeChanged);
+    on_player_state_changed(event: PlayerStateChanged);
+    on_song_changed(event: SongChanged);
+    on_seeked(time: f64);
 
     // PreferenceEvents trait methods
-    on_preferences_changed(args: PreferenceArgs) -> MoosyncResult<()>;
+    on_preferences_changed(args: PreferenceArgs);
 
     // DatabaseEvents trait methods
-    on_song_added(song: Song) -> MoosyncResult<()>;
-    on_song_removed(song: Song) -> MoosyncResult<()>;
-    on_playlist_added(playlist: QueryablePlaylist) -> MoosyncResult<()>;
-    on_playlist_removed(playlist: QueryablePlaylist) -> MoosyncResult<()>;
+    on_song_added(song: Song);
+    on_song_removed(song: Song);
+    on_playlist_added(playlist: QueryablePlaylist);
+    on_playlist_removed(playlist: QueryablePlaylist);
+);
 
-    // Account trait methods
-    get_accounts() -> MoosyncResult<Vec<ExtensionAccountDetail>>;
-    perform_account_login(args: AccountLoginArgs) -> MoosyncResult<String>;
+#[cfg(test)]
+mod tests {
+    use types::errors::Result as MoosyncResult;
 
-    // ContextMenu trait methods
-    get_song_context_menu(songs: Vec<Song>) -> MoosyncResult<Vec<ContextMenuReturnType>>;
-    get_playlist_context_menu(playlist: QueryablePlaylist) -> MoosyncResult<Vec<ContextMenuReturnType>>;
-    on_context_menu_action(action: String) -> MoosyncResult<()>;
-);
+    use super::*;
+    use crate::api::{
+        not_implemented, DatabaseEvents, PlayerEvents, PreferenceEvents, Provider, NOT_IMPLEMENTED,
+    };
+
+    struct TestExtension;
+
+    impl Provider for TestExtension {
+        fn get_provider_scopes(&self) -> MoosyncResult<Vec<ExtensionProviderScope>> {
+            Ok(vec![])
+        }
+
+        fn search(&self, _term: String) -> MoosyncResult<SearchResult> {
+            Err(MoosyncError::String(NOT_IMPLEMENTED.into()))
+        }
+
+        fn get_song_from_url(&self, _url: String) -> MoosyncResult<Option<Song>> {
+            not_implemented()
+        }
+    }
+
+    impl PlayerEvents for TestExtension {}
+    impl PreferenceEvents for TestExtension {}
+    impl DatabaseEvents for TestExtension {
+        fn on_song_added(&self, _song: Song) -> MoosyncResult<()> {
+            Err(MoosyncError::String(NOT_IMPLEMENTED.into()))
+        }
+    }
+    impl Accounts for TestExtension {}
+    impl ContextMenu for TestExtension {}
+    impl Extension for TestExtension {}
+
+    fn register() {
+        register_extension(Box::new(TestExtension)).unwrap();
+    }
+
+    #[test]
+    fn default_methods_are_reported_as_not_implemented() {
+        register();
+        let err = get_recommendations().unwrap_err();
+        assert_eq!(err.1, NOT_IMPLEMENTED_CODE);
+
+        let err = get_song_from_url("https://example.com".into()).unwrap_err();
+        assert_eq!(err.1, NOT_IMPLEMENTED_CODE);
+    }
+
+    #[test]
+    fn errors_with_the_same_message_are_failures() {
+        register();
+        let err = search("term".into()).unwrap_err();
+        assert_ne!(err.1, NOT_IMPLEMENTED_CODE);
+
+        // Even right after a method that wasn't implemented.
+        assert!(get_recommendations().is_err());
+        let err = search("term".into()).unwrap_err();
+        assert_ne!(err.1, NOT_IMPLEMENTED_CODE);
+    }
+
+    #[test]
+    fn unimplemented_events_succeed() {
+        register();
+        assert!(on_seeked(1.0).is_ok());
+        assert!(on_song_added(Song::default()).is_err());
+    }
+
+    #[test]
+    fn calls_without_an_extension_fail() {
+        EXTENSION.with(|ext| ext.borrow_mut().take());
+        let err = get_recommendations().unwrap_err();
+        assert!(err.0.to_string().contains("no extension is registered"));
+    }
+}
@@ -0,0 +1,52 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP requests from the extension.
+//!
+//! [`Client`] builds requests with query parameters, headers and JSON or form
+//! bodies, decodes responses, retries failed requests with backoff and keeps
+//! cookies in a [`CookieJar`].
+//! [`request`] is a drop-in replacement for `extism_pdk::http::request`.
+//!
+//! Both check the URL against the hosts declared in the manifest before sending,
+//! see [`permissions`](crate::permissions).
+
+use extism_pdk::{Error, ToMemory};
+
+pub use extism_pdk::{HttpRequest, HttpResponse};
+
+mod client;
+mod cookies;
+mod error;
+mod rate_limit;
+mod retry;
+
+pub use client::{Client, RequestBuilder, Response, Transport};
+pub use cookies::{Cookie, CookieJar};
+pub use error::{HttpError, HttpResult};
+pub use rate_limit::RateLimit;
+pub use retry::RetryPolicy;
+
+use crate::permissions;
+
+/// Sends `req` with an optional body.
+///
+/// Fails with a [`PermissionError`](permissions::PermissionError) if the host isn't
+/// declared in the manifest.
+pub fn request<T: ToMemory>(req: &HttpRequest, body: Option<T>) -> Result<HttpResponse, Error> {
+    permissions::check_url(&req.url)?;
+    extism_pdk::http::request(req, body)
+}
@@ -0,0 +1,712 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use extism_pdk::{Error, HttpRequest};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::cookies::CookieJar;
+use super::error::{HttpError, HttpResult};
+use super::rate_limit::{RateLimit, RateLimiter};
+use super::retry::{sleep, RetryPolicy};
+use crate::permissions;
+use crate::warn;
+
+/// HTTP client with an optional base URL and headers sent with every request.
+///
+/// Failed requests are retried according to a [`RetryPolicy`], by default up to
+/// three times for idempotent requests. Requests can also be rate limited per host.
+///
+/// ```ignore
+/// let client = Client::new()
+///     .with_base_url("https://api.example.com/v1")
+///     .with_header("Accept", "application/json");
+///
+/// let tracks: Vec<Track> = client
+///     .get("/search")
+///     .query("q", term)
+///     .query("limit", 20)
+///     .send()?
+///     .json()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    base_url: Option<String>,
+    headers: BTreeMap<String, String>,
+    retry: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    cookie_jar: Option<CookieJar>,
+    transport: Option<SharedTransport>,
+}
+
+/// Sends a request in place of the host, see [`Client::with_transport`].
+///
+/// Receives the request, including the headers and cookies the client added, and
+/// its body.
+pub type Transport = dyn Fn(&HttpRequest, Option<&[u8]>) -> Result<Response, Error>;
+
+#[derive(Clone)]
+struct SharedTransport(Rc<Transport>);
+
+impl fmt::Debug for SharedTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedTransport")
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the URL that relative request URLs are resolved against.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Adds a header sent with every request, unless the request overrides it.
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the retry policy of every request. Use [`RetryPolicy::none`] to disable retries.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Limits the rate of requests to each host.
+    ///
+    /// The limit is tracked per host and shared with clones of this client.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(limit));
+        self
+    }
+
+    /// Sends the cookies in `jar` with matching requests and stores the cookies
+    /// set by responses in it.
+    pub fn with_cookie_jar(mut self, jar: CookieJar) -> Self {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    /// Sends requests through `transport` instead of the host.
+    ///
+    /// Permissions, retries, rate limits and cookies still apply. Meant for tests of
+    /// code that talks to a web API:
+    ///
+    /// ```ignore
+    /// let client = Client::new().with_transport(|req, _body| {
+    ///     assert_eq!(req.url, "https://api.example.com/v1/search?q=song");
+    ///     Ok(Response::new(200, r#"{"tracks":[]}"#))
+    /// });
+    /// ```
+    pub fn with_transport(
+        mut self,
+        transport: impl Fn(&HttpRequest, Option<&[u8]>) -> Result<Response, Error> + 'static,
+    ) -> Self {
+        self.transport = Some(SharedTransport(Rc::new(transport)));
+        self
+    }
+
+    pub fn get(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request("POST", url)
+    }
+
+    pub fn put(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request("PUT", url)
+    }
+
+    pub fn patch(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request("PATCH", url)
+    }
+
+    pub fn delete(&self, url: impl AsRef<str>) -> RequestBuilder {
+        self.request("DELETE", url)
+    }
+
+    /// Starts a request with an arbitrary method.
+    ///
+    /// `url` is used as is if it is absolute, and appended to the base URL otherwise.
+    pub fn request(&self, method: &str, url: impl AsRef<str>) -> RequestBuilder {
+        RequestBuilder {
+            method: method.to_string(),
+            url: self.resolve(url.as_ref()),
+            query: vec![],
+            headers: self.headers.clone(),
+            body: None,
+            retry: self.retry.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            cookie_jar: self.cookie_jar.clone(),
+            transport: self.transport.clone(),
+            error: None,
+        }
+    }
+
+    fn resolve(&self, url: &str) -> String {
+        match &self.base_url {
+            Some(base) if !url.contains("://") => {
+                if url.is_empty() {
+                    base.clone()
+                } else {
+                    format!(
+                        "{}/{}",
+                        base.trim_end_matches('/'),
+                        url.trim_start_matches('/')
+                    )
+                }
+            }
+            _ => url.to_string(),
+        }
+    }
+}
+
+/// A request being built by a [`Client`]. Sent with [`send`](RequestBuilder::send).
+#[derive(Debug)]
+pub struct RequestBuilder {
+    method: String,
+    url: String,
+    query: Vec<(String, String)>,
+    headers: BTreeMap<String, String>,
+    body: Option<Vec<u8>>,
+    retry: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    cookie_jar: Option<CookieJar>,
+    transport: Option<SharedTransport>,
+    // Deferred so that the builder methods can be chained.
+    error: Option<HttpError>,
+}
+
+impl RequestBuilder {
+    /// Appends a query parameter. The key and value are percent-encoded.
+    pub fn query(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.query.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// Appends every pair in `params` as a query parameter.
+    pub fn query_pairs<I, K, V>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: ToString,
+    {
+        self.query.extend(
+            params
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.to_string())),
+        );
+        self
+    }
+
+    /// Sets a header, replacing the client's default for the same key.
+    pub fn header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the `Authorization` header to a bearer token.
+    pub fn bearer_auth(self, token: impl AsRef<str>) -> Self {
+        self.header("Authorization", format!("Bearer {}", token.as_ref()))
+    }
+
+    /// Overrides the client's retry policy for this request.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets a raw request body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Serializes `value` as the JSON request body.
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => {
+                self.body = Some(body);
+                self.default_content_type("application/json");
+            }
+            Err(source) => {
+                self.error = Some(HttpError::Encode {
+                    url: self.url.clone(),
+                    source,
+                })
+            }
+        }
+        self
+    }
+
+    /// Sets an `application/x-www-form-urlencoded` request body.
+    pub fn form<I, K, V>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: ToString,
+    {
+        let pairs: Vec<(String, String)> = params
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_string(), value.to_string()))
+            .collect();
+        self.body = Some(encode_pairs(&pairs).into_bytes());
+        self.default_content_type("application/x-www-form-urlencoded");
+        self
+    }
+
+    fn default_content_type(&mut self, content_type: &str) {
+        let is_set = self
+            .headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("Content-Type"));
+        if !is_set {
+            self.headers
+                .insert("Content-Type".into(), content_type.into());
+        }
+    }
+
+    /// The URL the request will be sent to, including the query string.
+    pub fn url(&self) -> String {
+        if self.query.is_empty() {
+            return self.url.clone();
+        }
+
+        let (url, fragment) = match self.url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (self.url.as_str(), None),
+        };
+        let separator = if !url.contains('?') {
+            "?"
+        } else if url.ends_with(['?', '&']) {
+            ""
+        } else {
+            "&"
+        };
+
+        let mut full = format!("{}{}{}", url, separator, encode_pairs(&self.query));
+        if let Some(fragment) = fragment {
+            full.push('#');
+            full.push_str(fragment);
+        }
+        full
+    }
+
+    /// Sends the request, retrying it according to the retry policy.
+    ///
+    /// Fails with [`HttpError::Status`] if the final response status is not in the
+    /// 2xx range.
+    pub fn send(mut self) -> HttpResult<Response> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let url = self.url();
+        permissions::check_url(&url)?;
+        let host = permissions::host_of(&url).unwrap_or_default().to_string();
+
+        let mut req = HttpRequest::new(&url).with_method(&self.method);
+        req.headers = self.headers.clone();
+
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire(&host);
+            }
+
+            let err = match self.send_once(&req) {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            match self.retry.delay(&self.method, &err, attempt) {
+                Some(delay) => {
+                    warn!(
+                        "{}, retrying in {}ms (attempt {} of {})",
+                        err,
+                        delay.as_millis(),
+                        attempt + 1,
+                        self.retry.max_retries()
+                    );
+                    sleep(delay);
+                    attempt += 1;
+                }
+                None => return Err(err),
+            }
+        }
+    }
+
+    fn send_once(&self, req: &HttpRequest) -> HttpResult<Response> {
+        let mut req = req.clone();
+        if let Some(cookies) = self
+            .cookie_jar
+            .as_ref()
+            .and_then(|jar| jar.cookie_header(&req.url))
+        {
+            let existing = req
+                .headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("Cookie"))
+                .map(|(key, value)| (key.clone(), value.clone()));
+            match existing {
+                Some((key, value)) => {
+                    req.headers.insert(key, format!("{}; {}", value, cookies));
+                }
+                None => {
+                    req.headers.insert("Cookie".into(), cookies);
+                }
+            }
+        }
+
+        let response = match &self.transport {
+            Some(SharedTransport(transport)) => {
+                transport(&req, self.body.as_deref()).map(|response| Response {
+                    url: req.url.clone(),
+                    ..response
+                })
+            }
+            None => extism_pdk::http::request(&req, self.body.clone()).map(|res| Response {
+                url: req.url.clone(),
+                status: res.status_code(),
+                headers: res.headers().clone(),
+                body: res.body(),
+            }),
+        }
+        .map_err(|source| HttpError::Request {
+            url: req.url.clone(),
+            source,
+        })?;
+
+        if let (Some(jar), Some(set_cookie)) = (&self.cookie_jar, response.header("Set-Cookie")) {
+            jar.store(&response.url, set_cookie);
+        }
+        response.error_for_status()
+    }
+}
+
+/// A response to a request sent by a [`Client`].
+#[derive(Debug, Clone)]
+pub struct Response {
+    url: String,
+    status: u16,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a response with no headers, for a [`Transport`].
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            url: String::new(),
+            status,
+            headers: HashMap::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Adds a header.
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Returns the value of a header, matching its name case-insensitively.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Deserializes the JSON body.
+    pub fn json<T: DeserializeOwned>(&self) -> HttpResult<T> {
+        serde_json::from_slice(&self.body).map_err(|source| HttpError::Decode {
+            url: self.url.clone(),
+            body: self.body.clone(),
+            source,
+        })
+    }
+
+    fn error_for_status(self) -> HttpResult<Self> {
+        if (200..300).contains(&self.status) {
+            Ok(self)
+        } else {
+            Err(HttpError::Status {
+                url: self.url,
+                status: self.status,
+                headers: self.headers,
+                body: self.body,
+            })
+        }
+    }
+}
+
+fn encode_pairs(pairs: &[(String, String)]) -> String {
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// Headers and body of each request sent.
+    type Sent = Rc<RefCell<Vec<(BTreeMap<String, String>, Vec<u8>)>>>;
+
+    /// A client whose requests are all answered with `status`.
+    fn client(status: u16) -> (Client, Sent) {
+        let sent = Sent::default();
+        let client = Client::new()
+            .with_retry(RetryPolicy::none())
+            .with_transport({
+                let sent = sent.clone();
+                move |req, body| {
+                    let body = body.unwrap_or_default().to_vec();
+                    sent.borrow_mut().push((req.headers.clone(), body));
+                    Ok(Response::new(status, "{}").with_header("X-Test", "1"))
+                }
+            });
+        (client, sent)
+    }
+
+    fn content_types(headers: &BTreeMap<String, String>) -> Vec<&str> {
+        headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn relative_urls_are_joined_to_the_base_url() {
+        let cases = [
+            (
+                "https://api.example.com/v1",
+                "/search",
+                "https://api.example.com/v1/search",
+            ),
+            (
+                "https://api.example.com/v1/",
+                "search",
+                "https://api.example.com/v1/search",
+            ),
+            (
+                "https://api.example.com/v1/",
+                "/search",
+                "https://api.example.com/v1/search",
+            ),
+            (
+                "https://api.example.com/v1",
+                "search",
+                "https://api.example.com/v1/search",
+            ),
+            (
+                "https://api.example.com/v1/",
+                "",
+                "https://api.example.com/v1/",
+            ),
+            (
+                "https://api.example.com/v1",
+                "https://cdn.example.com/a",
+                "https://cdn.example.com/a",
+            ),
+        ];
+        for (base, url, expected) in cases {
+            let client = Client::new().with_base_url(base);
+            assert_eq!(client.get(url).url(), expected, "{} + {}", base, url);
+        }
+
+        assert_eq!(Client::new().get("/search").url(), "/search");
+    }
+
+    #[test]
+    fn query_is_appended_to_the_url() {
+        let cases = [
+            ("https://example.com/a", "https://example.com/a?q=1"),
+            ("https://example.com/a?x=0", "https://example.com/a?x=0&q=1"),
+            ("https://example.com/a?", "https://example.com/a?q=1"),
+            (
+                "https://example.com/a?x=0&",
+                "https://example.com/a?x=0&q=1",
+            ),
+            ("https://example.com/a#top", "https://example.com/a?q=1#top"),
+            (
+                "https://example.com/a?x=0#top",
+                "https://example.com/a?x=0&q=1#top",
+            ),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(Client::new().get(url).query("q", 1).url(), expected);
+        }
+
+        assert_eq!(
+            Client::new().get("https://example.com/a#top").url(),
+            "https://example.com/a#top"
+        );
+    }
+
+    #[test]
+    fn everything_but_unreserved_characters_is_encoded() {
+        assert_eq!(encode("AZaz09-._~"), "AZaz09-._~");
+        assert_eq!(
+            encode("a b&c=d/e?f#g+h%"),
+            "a%20b%26c%3Dd%2Fe%3Ff%23g%2Bh%25"
+        );
+        assert_eq!(encode("café ♪"), "caf%C3%A9%20%E2%99%AA");
+        assert_eq!(
+            encode_pairs(&[
+                ("q".into(), "rock & roll".into()),
+                ("artist[0]".into(), "Björk".into()),
+            ]),
+            "q=rock%20%26%20roll&artist%5B0%5D=Bj%C3%B6rk"
+        );
+        assert_eq!(encode_pairs(&[]), "");
+    }
+
+    #[test]
+    fn bodies_set_a_default_content_type() {
+        let (client, sent) = client(200);
+        client
+            .post("https://example.com")
+            .form([("a", "1 2"), ("b", "é")])
+            .send()
+            .unwrap();
+        client
+            .post("https://example.com")
+            .json(&serde_json::json!({"a": 1}))
+            .send()
+            .unwrap();
+
+        let sent = sent.borrow();
+        assert_eq!(
+            content_types(&sent[0].0),
+            ["application/x-www-form-urlencoded"]
+        );
+        assert_eq!(sent[0].1, b"a=1%202&b=%C3%A9");
+        assert_eq!(content_types(&sent[1].0), ["application/json"]);
+        assert_eq!(sent[1].1, br#"{"a":1}"#);
+    }
+
+    #[test]
+    fn content_type_headers_are_kept() {
+        let (client, sent) = client(200);
+        client
+            .post("https://example.com")
+            .header("content-type", "application/vnd.api+json")
+            .json(&serde_json::json!({}))
+            .send()
+            .unwrap();
+        client
+            .clone()
+            .with_header("Content-Type", "text/plain")
+            .post("https://example.com")
+            .form([("a", 1)])
+            .send()
+            .unwrap();
+
+        let sent = sent.borrow();
+        assert_eq!(content_types(&sent[0].0), ["application/vnd.api+json"]);
+        assert_eq!(content_types(&sent[1].0), ["text/plain"]);
+    }
+
+    #[test]
+    fn statuses_outside_2xx_are_errors() {
+        for status in [200, 204, 299] {
+            assert!(Response::new(status, "").error_for_status().is_ok());
+        }
+
+        let (client, _) = client(404);
+        match client.get("https://example.com/missing").send() {
+            Err(HttpError::Status {
+                url,
+                status,
+                headers,
+                body,
+            }) => {
+                assert_eq!(url, "https://example.com/missing");
+                assert_eq!(status, 404);
+                assert_eq!(headers.get("X-Test").map(String::as_str), Some("1"));
+                assert_eq!(body, b"{}");
+            }
+            other => panic!("Expected a status error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn responses_carry_the_request_url() {
+        let (client, _) = client(200);
+        let response = client
+            .get("https://example.com/a")
+            .query("q", 1)
+            .send()
+            .unwrap();
+
+        assert_eq!(response.url(), "https://example.com/a?q=1");
+        assert_eq!(response.header("x-test"), Some("1"));
+    }
+}
@@ -0,0 +1,622 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::net::Ipv4Addr;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use super::retry::unix_time;
+use crate::api::extension_api::get_system_time;
+use crate::permissions::host_of;
+use crate::preferences::{self, PreferenceResult};
+use crate::warn;
+
+/// A cookie stored in a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Domain the cookie is sent to, without a leading dot.
+    pub domain: String,
+    /// If true, the cookie is only sent to `domain` itself and not its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// Expiry in seconds since the Unix epoch. `None` for a session cookie.
+    pub expires: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, scheme: &str, host: &str, path: &str) -> bool {
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_matches && path_match(path, &self.path) && (!self.secure || scheme == "https")
+    }
+}
+
+#[derive(Debug, Default)]
+struct JarState {
+    cookies: Vec<Cookie>,
+    persist_key: Option<String>,
+}
+
+/// Stores cookies set by responses and attaches them to matching requests.
+///
+/// Cookies are matched by domain and path following RFC 6265. There is no public
+/// suffix list, so a `Domain` attribute is only refused if it has a single label,
+/// like `com`. A jar is a handle:
+/// clones share the same cookies. Attach it to a [`Client`](super::Client) with
+/// [`with_cookie_jar`](super::Client::with_cookie_jar).
+///
+/// A jar created with [`CookieJar::persistent`] is saved to a secure preference
+/// after every change, so that sessions survive a restart of the app:
+///
+/// ```ignore
+/// let client = Client::new().with_cookie_jar(CookieJar::persistent("cookies")?);
+/// ```
+///
+/// Extism passes response headers as a map, so several `Set-Cookie` headers may
+/// arrive joined by commas. They are split again, but some hosts only keep the last one.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    state: Rc<RefCell<JarState>>,
+}
+
+impl CookieJar {
+    /// Creates an empty jar kept in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the jar stored under the secure preference `key` and saves it back
+    /// there whenever it changes.
+    pub fn persistent(key: impl Into<String>) -> PreferenceResult<Self> {
+        let key = key.into();
+        let jar = Self::load(&key)?;
+        jar.state.borrow_mut().persist_key = Some(key);
+        Ok(jar)
+    }
+
+    /// Loads the cookies stored under the secure preference `key`, dropping expired ones.
+    pub fn load(key: &str) -> PreferenceResult<Self> {
+        let mut cookies: Vec<Cookie> = preferences::get_secure(key, vec![])?;
+        let now = get_system_time();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+
+        let jar = Self::new();
+        jar.state.borrow_mut().cookies = cookies;
+        Ok(jar)
+    }
+
+    /// Stores every unexpired cookie, including session cookies, under the
+    /// secure preference `key`.
+    pub fn save(&self, key: &str) -> PreferenceResult<()> {
+        preferences::set_secure(key, &self.cookies())
+    }
+
+    /// Returns the unexpired cookies in the jar.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = get_system_time();
+        self.state
+            .borrow()
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes every cookie.
+    pub fn clear(&self) {
+        self.state.borrow_mut().cookies.clear();
+        self.persist();
+    }
+
+    /// Stores the cookies set by a `Set-Cookie` header of a response to `url`.
+    pub fn store(&self, url: &str, set_cookie: &str) {
+        let Some(request) = RequestUrl::parse(url) else {
+            return;
+        };
+        let now = get_system_time();
+
+        let mut changed = false;
+        for header in split_set_cookie(set_cookie) {
+            let Some(cookie) = parse_set_cookie(header, &request, now) else {
+                continue;
+            };
+
+            let mut state = self.state.borrow_mut();
+            state.cookies.retain(|existing| {
+                !(existing.name == cookie.name
+                    && existing.domain == cookie.domain
+                    && existing.path == cookie.path)
+            });
+            if !cookie.is_expired(now) {
+                state.cookies.push(cookie);
+            }
+            changed = true;
+        }
+
+        if changed {
+            self.persist();
+        }
+    }
+
+    /// Returns the value of the `Cookie` header for a request to `url`, if any
+    /// cookie matches.
+    pub fn cookie_header(&self, url: &str) -> Option<String> {
+        let request = RequestUrl::parse(url)?;
+        let now = get_system_time();
+
+        let state = self.state.borrow();
+        let mut cookies: Vec<&Cookie> = state
+            .cookies
+            .iter()
+            .filter(|cookie| {
+                !cookie.is_expired(now)
+                    && cookie.matches(request.scheme, &request.host, request.path)
+            })
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+
+        // More specific paths first, as recommended by RFC 6265.
+        cookies.sort_by_key(|cookie| Reverse(cookie.path.len()));
+        Some(
+            cookies
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    fn persist(&self) {
+        let key = self.state.borrow().persist_key.clone();
+        if let Some(key) = key {
+            if let Err(e) = self.save(&key) {
+                warn!("Failed to persist cookies: {}", e);
+            }
+        }
+    }
+}
+
+struct RequestUrl<'a> {
+    scheme: &'a str,
+    host: String,
+    path: &'a str,
+}
+
+impl<'a> RequestUrl<'a> {
+    fn parse(url: &'a str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let host = host_of(url)?.to_ascii_lowercase();
+        let path = match rest.find(['/', '?', '#']) {
+            Some(start) if rest[start..].starts_with('/') => {
+                rest[start..].split(['?', '#']).next().unwrap_or("/")
+            }
+            _ => "/",
+        };
+        Some(Self { scheme, host, path })
+    }
+
+    /// Path used for cookies without a `Path` attribute.
+    fn default_path(&self) -> String {
+        match self.path.rfind('/') {
+            Some(0) | None => "/".into(),
+            Some(end) => self.path[..end].into(),
+        }
+    }
+}
+
+/// Splits a header that may hold several comma-joined `Set-Cookie` values.
+///
+/// A comma only starts a new cookie if it is followed by `name=`, which keeps
+/// dates in `Expires` intact.
+fn split_set_cookie(header: &str) -> Vec<&str> {
+    let mut cookies = vec![];
+    let mut start = 0;
+    for (i, _) in header.match_indices(',') {
+        let next = &header[i + 1..];
+        let next = next.split([';', ',']).next().unwrap_or_default();
+        if next.contains('=') {
+            cookies.push(header[start..i].trim());
+            start = i + 1;
+        }
+    }
+    cookies.push(header[start..].trim());
+    cookies.retain(|cookie| !cookie.is_empty());
+    cookies
+}
+
+fn parse_set_cookie(header: &str, request: &RequestUrl, now: u64) -> Option<Cookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        name: name.into(),
+        value: value.trim().trim_matches('"').into(),
+        domain: request.host.clone(),
+        host_only: true,
+        path: request.default_path(),
+        expires: None,
+        secure: false,
+        http_only: false,
+    };
+
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+
+        match key.to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // A response can only set cookies for its own domain or a parent of it.
+                if !domain_match(&request.host, &domain) {
+                    return None;
+                }
+                if domain.contains('.') {
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                } else if domain != request.host {
+                    // A top-level domain like `com`. There is no public suffix
+                    // list to check `co.uk` against, but these are always refused.
+                    return None;
+                }
+            }
+            "path" if value.starts_with('/') => cookie.path = value.into(),
+            "expires" => {
+                if let Some(expires) = parse_cookie_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            }
+            "max-age" => {
+                if let Ok(seconds) = value.parse::<i64>() {
+                    max_age = Some(seconds);
+                }
+            }
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            _ => {}
+        }
+    }
+
+    // Max-Age takes precedence over Expires.
+    if let Some(seconds) = max_age {
+        cookie.expires = Some(if seconds <= 0 {
+            0
+        } else {
+            now.saturating_add(seconds as u64)
+        });
+    }
+
+    Some(cookie)
+}
+
+/// Parses the date of an `Expires` attribute with the lenient algorithm of
+/// RFC 6265, section 5.1.1, into seconds since the Unix epoch. Dates before the
+/// epoch give 0.
+fn parse_cookie_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    let is_delimiter = |c: char| matches!(c, '\x09' | '\x20'..='\x2f' | '\x3b'..='\x40' | '\x5b'..='\x60' | '\x7b'..='\x7e');
+
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in value.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = parse_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(parsed) = leading_digits(token, 1, 2) {
+                day = Some(parsed);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(index) = MONTHS.iter().position(|m| Some(*m) == prefix.as_deref()) {
+                month = Some(index as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(parsed) = leading_digits(token, 2, 4) {
+                year = Some(parsed);
+            }
+        }
+    }
+
+    let year = match year? {
+        year @ 70..=99 => year + 1900,
+        year @ 0..=69 => year + 2000,
+        year => year,
+    };
+    let ((hour, minute, second), day, month) = (time?, day?, month?);
+    if year < 1601 || !(1..=31).contains(&day) {
+        return None;
+    }
+    if year < 1970 {
+        return Some(0);
+    }
+    unix_time(year, month, day, hour, minute, second)
+}
+
+/// Reads `min..=max` digits at the start of `token`, which must not be followed
+/// by another digit.
+fn leading_digits(token: &str, min: usize, max: usize) -> Option<u64> {
+    let len = token.bytes().take_while(u8::is_ascii_digit).count();
+    if !(min..=max).contains(&len) {
+        return None;
+    }
+    token[..len].parse().ok()
+}
+
+/// Reads a `hh:mm:ss` time at the start of `token`, each field having one or two digits.
+fn parse_time(token: &str) -> Option<(u64, u64, u64)> {
+    let mut fields = token.splitn(3, ':');
+    let (hour, minute, second) = (fields.next()?, fields.next()?, fields.next()?);
+    let whole = |field: &str| field.len() <= 2 && field.bytes().all(|b| b.is_ascii_digit());
+    if !whole(hour) || !whole(minute) {
+        return None;
+    }
+    Some((
+        leading_digits(hour, 1, 2)?,
+        leading_digits(minute, 1, 2)?,
+        leading_digits(second, 1, 2)?,
+    ))
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    // IP addresses only match themselves.
+    let is_ip = host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok();
+    !is_ip
+        && host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || path
+            .strip_prefix(cookie_path)
+            .is_some_and(|rest| cookie_path.ends_with('/') || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn jar() -> (MockHost, CookieJar) {
+        let host = MockHost::new();
+        host.set_system_time(NOW);
+        (host, CookieJar::new())
+    }
+
+    fn request(url: &str) -> RequestUrl<'_> {
+        RequestUrl::parse(url).unwrap()
+    }
+
+    #[test]
+    fn cookie_dates() {
+        let expected = Some(1_792_567_680);
+        let cases = [
+            ("Wed, 21 Oct 2026 07:28:00 GMT", expected),
+            ("Wed, 21-Oct-2026 07:28:00 GMT", expected),
+            ("Wednesday, 21-Oct-26 07:28:00 GMT", expected),
+            ("Wed Oct 21 07:28:00 2026", expected),
+            ("21 october 2026 7:28:0", expected),
+            ("Wed, 21 Oct 2026 07:28:00", expected),
+            ("Thu, 01-Jan-70 00:00:00 GMT", Some(0)),
+            ("Tue, 01-Jan-69 00:00:00 GMT", Some(3_124_224_000)),
+            ("Sat, 01 Jan 1900 00:00:00 GMT", Some(0)),
+            ("Sat, 01 Jan 1600 00:00:00 GMT", None),
+            ("Wed, 21 Oct 2026 GMT", None),
+            ("Wed, 21 2026 07:28:00 GMT", None),
+            ("Wed, 32 Oct 2026 07:28:00 GMT", None),
+            ("Wed, 21 Oct 2026 24:00:00 GMT", None),
+            ("Wed, 31 Feb 2026 07:28:00 GMT", None),
+            ("Wed, 21 Oct 2026 07:28:00.5 GMT", expected),
+            ("Wed, 21 Oct 2026 7a:28:00 GMT", None),
+            ("", None),
+        ];
+
+        for (date, expected) in cases {
+            assert_eq!(parse_cookie_date(date), expected, "{:?}", date);
+        }
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let request = request("https://api.example.com/v1/users?id=1");
+        let cookie = parse_set_cookie(
+            "session=\"abc\"; Domain=.Example.com; Path=/v1; Secure; HttpOnly; Expires=Wed, 21-Oct-2026 07:28:00 GMT",
+            &request,
+            NOW,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cookie,
+            Cookie {
+                name: "session".into(),
+                value: "abc".into(),
+                domain: "example.com".into(),
+                host_only: false,
+                path: "/v1".into(),
+                expires: Some(1_792_567_680),
+                secure: true,
+                http_only: true,
+            }
+        );
+    }
+
+    #[test]
+    fn defaults_to_host_only_and_request_path() {
+        let cookie =
+            parse_set_cookie("a=1", &request("https://example.com/v1/users"), NOW).unwrap();
+        assert_eq!(cookie.domain, "example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/v1");
+        assert_eq!(cookie.expires, None);
+
+        let cookie =
+            parse_set_cookie("a=1; Path=relative", &request("https://example.com/x"), NOW).unwrap();
+        assert_eq!(cookie.path, "/");
+    }
+
+    #[test]
+    fn max_age_takes_precedence_over_expires() {
+        let request = request("https://example.com/");
+        let cookie = parse_set_cookie(
+            "a=1; Max-Age=60; Expires=Wed, 21 Oct 2026 07:28:00 GMT",
+            &request,
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, Some(NOW + 60));
+
+        let cookie = parse_set_cookie("a=1; Max-Age=-1", &request, NOW).unwrap();
+        assert_eq!(cookie.expires, Some(0));
+    }
+
+    #[test]
+    fn refuses_foreign_and_top_level_domains() {
+        let request = request("https://api.example.com/");
+        assert!(parse_set_cookie("a=1; Domain=other.com", &request, NOW).is_none());
+        assert!(parse_set_cookie("a=1; Domain=ample.com", &request, NOW).is_none());
+        assert!(parse_set_cookie("a=1; Domain=com", &request, NOW).is_none());
+        assert!(parse_set_cookie("a=1; Domain=.com", &request, NOW).is_none());
+        assert!(parse_set_cookie("=1", &request, NOW).is_none());
+        assert!(parse_set_cookie("no-value", &request, NOW).is_none());
+
+        // A single-label host may still set a cookie for itself, as host-only.
+        let cookie = parse_set_cookie(
+            "a=1; Domain=localhost",
+            &self::request("http://localhost/"),
+            NOW,
+        )
+        .unwrap();
+        assert!(cookie.host_only);
+    }
+
+    #[test]
+    fn domain_and_path_matching() {
+        assert!(domain_match("example.com", "example.com"));
+        assert!(domain_match("api.example.com", "example.com"));
+        assert!(!domain_match("badexample.com", "example.com"));
+        assert!(!domain_match("example.com", "api.example.com"));
+        assert!(domain_match("127.0.0.1", "127.0.0.1"));
+        assert!(!domain_match("10.0.0.1", "0.0.1"));
+
+        assert!(path_match("/", "/"));
+        assert!(path_match("/v1", "/v1"));
+        assert!(path_match("/v1/users", "/v1"));
+        assert!(path_match("/v1/users", "/v1/"));
+        assert!(!path_match("/v10", "/v1"));
+        assert!(!path_match("/", "/v1"));
+    }
+
+    #[test]
+    fn sends_matching_cookies_most_specific_first() {
+        let (_host, jar) = jar();
+        jar.store("https://example.com/", "root=1; Path=/");
+        jar.store("https://example.com/", "api=2; Path=/api");
+        jar.store("https://example.com/", "shared=3; Domain=example.com");
+        jar.store("https://example.com/", "secure=4; Secure");
+
+        assert_eq!(
+            jar.cookie_header("https://example.com/api/v1").as_deref(),
+            Some("api=2; root=1; shared=3; secure=4")
+        );
+        assert_eq!(
+            jar.cookie_header("http://example.com/").as_deref(),
+            Some("root=1; shared=3")
+        );
+        assert_eq!(
+            jar.cookie_header("https://www.example.com/").as_deref(),
+            Some("shared=3")
+        );
+        assert_eq!(jar.cookie_header("https://other.com/"), None);
+    }
+
+    #[test]
+    fn replaces_and_expires_cookies() {
+        let (host, jar) = jar();
+        jar.store("https://example.com/", "a=1, b=2; Max-Age=10");
+        jar.store("https://example.com/", "a=3");
+        assert_eq!(
+            jar.cookie_header("https://example.com/").as_deref(),
+            Some("b=2; a=3")
+        );
+
+        host.set_system_time(NOW + 10);
+        assert_eq!(
+            jar.cookie_header("https://example.com/").as_deref(),
+            Some("a=3")
+        );
+
+        // An expiry in the past deletes the cookie.
+        jar.store(
+            "https://example.com/",
+            "a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+        );
+        assert_eq!(jar.cookie_header("https://example.com/"), None);
+        assert!(jar.cookies().is_empty());
+    }
+
+    #[test]
+    fn splits_joined_headers() {
+        assert_eq!(
+            split_set_cookie("a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT, b=2; Path=/, c=3"),
+            vec![
+                "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT",
+                "b=2; Path=/",
+                "c=3"
+            ]
+        );
+        assert_eq!(split_set_cookie(""), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,128 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use types::errors::MoosyncError;
+
+use crate::permissions::PermissionError;
+
+/// Longest part of a response body included in the error message.
+const BODY_SNIPPET_LEN: usize = 256;
+
+/// Error returned by [`Client`](super::Client) requests.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The host isn't declared in the manifest.
+    Permission(PermissionError),
+    /// The request body could not be serialized.
+    Encode {
+        url: String,
+        source: serde_json::Error,
+    },
+    /// The request could not be sent or no response was received.
+    Request {
+        url: String,
+        source: extism_pdk::Error,
+    },
+    /// The server answered with a status outside of 200-299.
+    Status {
+        url: String,
+        status: u16,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    },
+    /// The response body could not be decoded into the expected type.
+    Decode {
+        url: String,
+        body: Vec<u8>,
+        source: serde_json::Error,
+    },
+}
+
+impl HttpError {
+    /// Status code of the response, if one was received.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            HttpError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+fn snippet(body: &[u8]) -> String {
+    let body = String::from_utf8_lossy(body);
+    match body.char_indices().nth(BODY_SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.into_owned(),
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Permission(e) => write!(f, "{}", e),
+            HttpError::Encode { url, source } => {
+                write!(f, "Failed to encode request body for {}: {}", url, source)
+            }
+            HttpError::Request { url, source } => {
+                write!(f, "Request to {} failed: {}", url, source)
+            }
+            HttpError::Status {
+                url, status, body, ..
+            } => write!(
+                f,
+                "Request to {} returned status {}: {}",
+                url,
+                status,
+                snippet(body)
+            ),
+            HttpError::Decode { url, body, source } => write!(
+                f,
+                "Failed to decode response from {}: {} (body: {})",
+                url,
+                source,
+                snippet(body)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Permission(e) => Some(e),
+            HttpError::Request { source, .. } => Some(source.as_ref()),
+            HttpError::Encode { source, .. } | HttpError::Decode { source, .. } => Some(source),
+            HttpError::Status { .. } => None,
+        }
+    }
+}
+
+impl From<PermissionError> for HttpError {
+    fn from(value: PermissionError) -> Self {
+        HttpError::Permission(value)
+    }
+}
+
+impl From<HttpError> for MoosyncError {
+    fn from(err: HttpError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+pub type HttpResult<T> = Result<T, HttpError>;
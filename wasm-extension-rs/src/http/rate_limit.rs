@@ -0,0 +1,207 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::retry::sleep;
+use crate::api::extension_api::get_system_time;
+
+/// Limits how many requests are sent to a single host.
+///
+/// Each host gets a token bucket holding up to `burst` tokens, refilled at
+/// `requests_per_second`. A request takes one token and waits if none is left.
+///
+/// The main app only reports time in whole seconds. Buckets are kept in
+/// milliseconds and advanced by the time spent waiting for a token, so requests
+/// are spaced at the configured rate even within a second. Time spent elsewhere
+/// is only counted once the reported second changes, so a bucket may refill up
+/// to a second late but never early.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: f64,
+}
+
+impl RateLimit {
+    /// Allows `requests_per_second` requests per second, with bursts of the same size.
+    pub fn per_second(requests_per_second: f64) -> Self {
+        let requests_per_second = requests_per_second.max(f64::MIN_POSITIVE);
+        Self {
+            requests_per_second,
+            burst: requests_per_second.max(1.0),
+        }
+    }
+
+    /// Allows `requests` requests per minute.
+    pub fn per_minute(requests: f64) -> Self {
+        Self::per_second(requests / 60.0)
+    }
+
+    /// Sets how many requests can be sent at once after the host was idle.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = f64::from(burst.max(1));
+        self
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    /// Milliseconds since the epoch, never ahead of the actual time.
+    updated_at: u64,
+}
+
+/// Per-host token buckets, shared by a client and its clones.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Rc<RefCell<HashMap<String, TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Rc::default(),
+        }
+    }
+
+    /// Takes a token for `host`, waiting until one is available.
+    pub(crate) fn acquire(&self, host: &str) {
+        loop {
+            match self.try_acquire(host, get_system_time().saturating_mul(1000)) {
+                Ok(()) => return,
+                Err(wait) => {
+                    sleep(wait);
+                    self.credit(host, wait);
+                }
+            }
+        }
+    }
+
+    /// Refills the bucket of `host` for time spent waiting, so that progress is
+    /// made even if the reported time hasn't moved yet.
+    fn credit(&self, host: &str, waited: Duration) {
+        if let Some(bucket) = self
+            .buckets
+            .borrow_mut()
+            .get_mut(&host.to_ascii_lowercase())
+        {
+            let waited = waited.as_millis() as u64;
+            bucket.tokens = (bucket.tokens
+                + waited as f64 / 1000.0 * self.limit.requests_per_second)
+                .min(self.limit.burst);
+            bucket.updated_at += waited;
+        }
+    }
+
+    /// Takes a token for `host`, or returns how long to wait for the next one.
+    /// `now` is in milliseconds.
+    fn try_acquire(&self, host: &str, now: u64) -> Result<(), Duration> {
+        let mut buckets = self.buckets.borrow_mut();
+        let bucket = buckets
+            .entry(host.to_ascii_lowercase())
+            .or_insert(TokenBucket {
+                tokens: self.limit.burst,
+                updated_at: now,
+            });
+
+        let elapsed = now.saturating_sub(bucket.updated_at) as f64 / 1000.0;
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.limit.requests_per_second).min(self.limit.burst);
+        bucket.updated_at = bucket.updated_at.max(now);
+
+        if bucket.tokens >= 1.0 - f64::EPSILON {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = (1.0 - bucket.tokens) / self.limit.requests_per_second;
+            Err(Duration::from_millis(
+                (missing * 1000.0).ceil().max(1.0) as u64
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_waits_for_refill() {
+        let limiter = RateLimiter::new(RateLimit::per_second(2.0));
+        assert_eq!(limiter.try_acquire("example.com", 10_000), Ok(()));
+        assert_eq!(limiter.try_acquire("example.com", 10_000), Ok(()));
+        assert_eq!(
+            limiter.try_acquire("example.com", 10_000),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.try_acquire("example.com", 10_250),
+            Err(Duration::from_millis(250))
+        );
+        assert_eq!(limiter.try_acquire("example.com", 10_500), Ok(()));
+    }
+
+    #[test]
+    fn waiting_refills_within_a_second() {
+        let limiter = RateLimiter::new(RateLimit::per_second(10.0).with_burst(1));
+        assert_eq!(limiter.try_acquire("example.com", 5_000), Ok(()));
+        let wait = limiter.try_acquire("example.com", 5_000).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+
+        // The reported time hasn't moved, but the time spent waiting counts.
+        limiter.credit("example.com", wait);
+        assert_eq!(limiter.try_acquire("example.com", 5_000), Ok(()));
+        assert!(limiter.try_acquire("example.com", 5_000).is_err());
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let limiter = RateLimiter::new(RateLimit::per_minute(60.0).with_burst(2));
+        assert_eq!(limiter.try_acquire("example.com", 0), Ok(()));
+        assert_eq!(limiter.try_acquire("example.com", 0), Ok(()));
+        assert!(limiter.try_acquire("example.com", 0).is_err());
+
+        assert_eq!(limiter.try_acquire("example.com", 60_000), Ok(()));
+        assert_eq!(limiter.try_acquire("example.com", 60_000), Ok(()));
+        assert_eq!(
+            limiter.try_acquire("example.com", 60_000),
+            Err(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn hosts_have_separate_buckets() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0));
+        assert_eq!(limiter.try_acquire("a.example.com", 0), Ok(()));
+        assert!(limiter.try_acquire("A.example.com", 0).is_err());
+        assert_eq!(limiter.try_acquire("b.example.com", 0), Ok(()));
+
+        // Clones share their buckets.
+        assert!(limiter.clone().try_acquire("b.example.com", 0).is_err());
+    }
+
+    #[test]
+    fn time_going_backwards_does_not_refill() {
+        let limiter = RateLimiter::new(RateLimit::per_second(1.0));
+        assert_eq!(limiter.try_acquire("example.com", 3_000), Ok(()));
+        assert!(limiter.try_acquire("example.com", 1_000).is_err());
+    }
+}
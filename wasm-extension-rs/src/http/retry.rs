@@ -0,0 +1,387 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::error::HttpError;
+use crate::api::extension_api::get_system_time;
+
+/// Methods that can be repeated without changing the result.
+const IDEMPOTENT_METHODS: [&str; 6] = ["GET", "HEAD", "OPTIONS", "TRACE", "PUT", "DELETE"];
+
+/// When and how often a failed request is sent again.
+///
+/// A request is retried if it could not be sent or the server answered with
+/// 408, 429, 500, 502, 503 or 504. Requests with a non-idempotent method, like
+/// `POST`, are only retried on 429, since the server didn't process them,
+/// unless [`with_retry_non_idempotent`](RetryPolicy::with_retry_non_idempotent) is set.
+///
+/// The delay doubles with each attempt, starting at the base delay and capped at
+/// the max delay. A `Retry-After` header on the response takes precedence. If it
+/// asks to wait longer than the max delay, the error is returned instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::default().with_max_retries(0)
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Also retries non-idempotent requests on server errors and failed sends.
+    pub fn with_retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Returns how long to wait before sending the request again, or `None`
+    /// if `err` should be returned. `attempt` starts at 0.
+    pub(crate) fn delay(&self, method: &str, err: &HttpError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+
+        let idempotent = self.retry_non_idempotent
+            || IDEMPOTENT_METHODS
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(method));
+        let retry_after = match err {
+            HttpError::Request { .. } if idempotent => None,
+            HttpError::Status {
+                status, headers, ..
+            } => match status {
+                429 => retry_after(headers),
+                503 if idempotent => retry_after(headers),
+                408 | 500 | 502 | 504 if idempotent => None,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        match retry_after {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+}
+
+/// Reads the `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HashMap<String, String>) -> Option<Duration> {
+    let value = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))?
+        .1
+        .trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = parse_http_date(value)?;
+    Some(Duration::from_secs(at.saturating_sub(get_system_time())))
+}
+
+/// Parses an IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`, into seconds
+/// since the Unix epoch.
+pub(crate) fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (_, date) = value.split_once(", ")?;
+    let mut parts = date.split_ascii_whitespace();
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    unix_time(year, month, day, hour, minute, second)
+}
+
+/// Converts a UTC date and time to seconds since the Unix epoch. Returns `None`
+/// for an invalid date or one before the epoch.
+pub(crate) fn unix_time(
+    year: u64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+) -> Option<u64> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if year < 1970 || day == 0 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Days since the epoch of a proleptic Gregorian date, counting years from March.
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Blocks the extension for `duration`.
+pub(crate) fn sleep(duration: Duration) {
+    if !duration.is_zero() {
+        std::thread::sleep(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+
+    fn request_error() -> HttpError {
+        HttpError::Request {
+            url: "https://example.com".into(),
+            source: extism_pdk::Error::msg("connection refused"),
+        }
+    }
+
+    fn status(status: u16, headers: &[(&str, &str)]) -> HttpError {
+        HttpError::Status {
+            url: "https://example.com".into(),
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_retries() {
+        let policy = RetryPolicy::default();
+        let delays: Vec<_> = (0..4)
+            .map(|attempt| policy.delay("GET", &request_error(), attempt))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(500)),
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped_at_max_delay() {
+        let policy = RetryPolicy::new()
+            .with_max_retries(10)
+            .with_base_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(5));
+        assert_eq!(
+            policy.delay("GET", &status(503, &[]), 2),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(
+            policy.delay("GET", &status(503, &[]), 3),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            policy.delay("GET", &status(503, &[]), 9),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn retried_statuses() {
+        let policy = RetryPolicy::default();
+        for code in [408, 429, 500, 502, 503, 504] {
+            assert!(
+                policy.delay("GET", &status(code, &[]), 0).is_some(),
+                "{}",
+                code
+            );
+        }
+        for code in [400, 401, 403, 404, 501] {
+            assert!(
+                policy.delay("GET", &status(code, &[]), 0).is_none(),
+                "{}",
+                code
+            );
+        }
+        assert!(RetryPolicy::none()
+            .delay("GET", &status(503, &[]), 0)
+            .is_none());
+    }
+
+    #[test]
+    fn non_idempotent_requests_are_only_retried_on_429() {
+        let policy = RetryPolicy::default();
+        assert!(policy.delay("POST", &request_error(), 0).is_none());
+        assert!(policy.delay("post", &status(503, &[]), 0).is_none());
+        assert!(policy.delay("POST", &status(429, &[]), 0).is_some());
+        assert!(policy.delay("delete", &status(503, &[]), 0).is_some());
+
+        let policy = policy.with_retry_non_idempotent(true);
+        assert!(policy.delay("POST", &request_error(), 0).is_some());
+        assert!(policy.delay("POST", &status(503, &[]), 0).is_some());
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.delay("GET", &status(429, &[("retry-after", " 7 ")]), 0),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            policy.delay("POST", &status(429, &[("Retry-After", "0")]), 0),
+            Some(Duration::ZERO)
+        );
+        // Longer than the max delay: give up instead of waiting.
+        assert_eq!(
+            policy.delay("GET", &status(503, &[("Retry-After", "31")]), 0),
+            None
+        );
+        // Unparseable: fall back to the backoff.
+        assert_eq!(
+            policy.delay("GET", &status(503, &[("Retry-After", "soon")]), 1),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn retry_after_as_a_date() {
+        let host = MockHost::new();
+        host.set_system_time(784_111_777);
+        let policy = RetryPolicy::default();
+
+        assert_eq!(
+            policy.delay(
+                "GET",
+                &status(503, &[("Retry-After", "Sun, 06 Nov 1994 08:49:47 GMT")]),
+                0
+            ),
+            Some(Duration::from_secs(10))
+        );
+        // A date in the past means retrying right away.
+        assert_eq!(
+            policy.delay(
+                "GET",
+                &status(503, &[("Retry-After", "Sun, 06 Nov 1994 08:00:00 GMT")]),
+                0
+            ),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            policy.delay(
+                "GET",
+                &status(503, &[("Retry-After", "Sun, 06 Nov 1994 09:49:37 GMT")]),
+                0
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_imf_fixdates() {
+        let cases = [
+            ("Thu, 01 Jan 1970 00:00:00 GMT", Some(0)),
+            ("Sun, 06 Nov 1994 08:49:37 GMT", Some(784_111_777)),
+            ("Thu, 29 Feb 2024 00:00:00 GMT", Some(1_709_164_800)),
+            ("Fri, 31 Dec 1999 23:59:59 GMT", Some(946_684_799)),
+            ("Wed, 21 Oct 2026 07:28:00 GMT", Some(1_792_567_680)),
+            ("Sun, 06 Nov 1994 08:49:37 UTC", None),
+            ("Sun, 06 Nov 1994 08:49:37", None),
+            ("Sun, 06 Foo 1994 08:49:37 GMT", None),
+            ("Sun, 06 Nov 1994 08:49 GMT", None),
+            ("Sun, 06 Nov 1994 24:00:00 GMT", None),
+            ("Fri, 29 Feb 2019 00:00:00 GMT", None),
+            ("Thu, 31 Apr 2025 00:00:00 GMT", None),
+            ("Wed, 31 Dec 1969 23:59:59 GMT", None),
+            ("Sunday, 06-Nov-94 08:49:37 GMT", None),
+            ("06 Nov 1994 08:49:37 GMT", None),
+            ("", None),
+        ];
+
+        for (date, expected) in cases {
+            assert_eq!(parse_http_date(date), expected, "{:?}", date);
+        }
+    }
+}
@@ -0,0 +1,961 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Last.fm API client.
+//!
+//! [`LastFm`] signs method calls with the API secret, as described in
+//! <https://www.last.fm/api/authspec>, and implements the calls needed by
+//! scrobbling extensions. The declared hosts must include `ws.audioscrobbler.com`.
+//!
+//! Logging in is wired into the [`Accounts`](crate::api::Accounts) methods:
+//!
+//! ```ignore
+//! impl Accounts for MyExtension {
+//!     fn perform_account_login(&self, args: AccountLoginArgs) -> MoosyncResult<String> {
+//!         Ok(self.lastfm.perform_account_login(&args)?)
+//!     }
+//!
+//!     fn oauth_callback(&self, code: String) -> MoosyncResult<()> {
+//!         Ok(self.lastfm.oauth_callback(&code)?)
+//!     }
+//! }
+//! ```
+//!
+//! With a callback URL set by [`LastFm::with_callback_url`], the web flow is
+//! used: Last.fm redirects to the callback with a token, which the main app
+//! passes to `oauth_callback`. Without one, the desktop flow is used: the
+//! session is fetched once the user has allowed access in the browser.
+//!
+//! Scrobbles can be submitted through a [`ScrobbleQueue`](crate::scrobble::ScrobbleQueue):
+//!
+//! ```ignore
+//! let lastfm = LastFm::new(API_KEY, API_SECRET).with_session_preference("lastfm_session");
+//! let queue = ScrobbleQueue::new({
+//!     let lastfm = lastfm.clone();
+//!     move |batch: &[Scrobble]| Ok(lastfm.scrobble(batch)?)
+//! });
+//! ```
+//!
+//! The queue's batch size must not exceed [`MAX_SCROBBLES_PER_REQUEST`], the
+//! default of 50 is the limit.
+//!
+//! The API and authorization URLs can be pointed at a local stand-in of the
+//! Last.fm API with [`LastFm::with_api_url`] and [`LastFm::with_auth_url`], or
+//! requests answered in tests by a client built with
+//! [`Client::with_transport`](crate::http::Client::with_transport).
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use types::errors::MoosyncError;
+use types::songs::Song;
+use types::ui::extensions::AccountLoginArgs;
+
+use crate::api::extension_api::{self, ApiError};
+use crate::http::{Client, HttpError};
+use crate::preferences;
+use crate::scrobble::{Scrobble, SubmitError};
+use crate::warn;
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+/// Most scrobbles Last.fm accepts in a single `track.scrobble` call, and in a
+/// single [`LastFm::scrobble`].
+pub const MAX_SCROBBLES_PER_REQUEST: usize = 50;
+
+/// Error codes returned by Last.fm for failures that may succeed later: operation
+/// failed, service offline, temporarily unavailable and rate limit exceeded.
+const TEMPORARY_ERROR_CODES: [u32; 4] = [8, 11, 16, 29];
+
+/// Error code returned for an invalid session key.
+const INVALID_SESSION: u32 = 9;
+
+/// Error codes returned for a token that is invalid or has expired.
+const INVALID_TOKEN_CODES: [u32; 2] = [4, 15];
+
+/// Error returned by [`LastFm`] calls.
+#[derive(Debug)]
+pub enum LastFmError {
+    /// The request failed.
+    Http(HttpError),
+    /// Last.fm answered with an error.
+    Api { code: u32, message: String },
+    /// A call to the main app, like hashing the signature, failed.
+    Host(MoosyncError),
+    /// The call requires a session and the user isn't logged in.
+    NotLoggedIn,
+    /// The song lacks the title or artist Last.fm requires.
+    MissingMetadata(&'static str),
+    /// More scrobbles than [`MAX_SCROBBLES_PER_REQUEST`] were passed to
+    /// [`LastFm::scrobble`].
+    TooManyScrobbles(usize),
+    /// The response lacks a field the call needs.
+    Decode(String),
+}
+
+impl Display for LastFmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LastFmError::Http(e) => write!(f, "{}", e),
+            LastFmError::Api { code, message } => {
+                write!(f, "Last.fm returned error {}: {}", code, message)
+            }
+            LastFmError::Host(e) => write!(f, "{}", e),
+            LastFmError::NotLoggedIn => write!(f, "Not logged in to Last.fm"),
+            LastFmError::MissingMetadata(field) => write!(f, "Song has no {}", field),
+            LastFmError::TooManyScrobbles(count) => write!(
+                f,
+                "Cannot scrobble {} songs at once, the limit is {}",
+                count, MAX_SCROBBLES_PER_REQUEST
+            ),
+            LastFmError::Decode(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for LastFmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LastFmError::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for LastFmError {
+    fn from(err: HttpError) -> Self {
+        LastFmError::Http(err)
+    }
+}
+
+impl From<MoosyncError> for LastFmError {
+    fn from(err: MoosyncError) -> Self {
+        LastFmError::Host(err)
+    }
+}
+
+impl From<ApiError> for LastFmError {
+    fn from(err: ApiError) -> Self {
+        LastFmError::Host(err.into())
+    }
+}
+
+impl From<LastFmError> for MoosyncError {
+    fn from(err: LastFmError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+/// Scrobbles are kept while the user isn't logged in and when Last.fm is
+/// unavailable, and dropped when Last.fm refuses them.
+///
+/// A batch that is too large is kept too, as nothing was sent: the queue's
+/// batch size needs fixing. An unexpected response is handled like an
+/// undecodable one, the batch was most likely accepted.
+impl From<LastFmError> for SubmitError {
+    fn from(err: LastFmError) -> Self {
+        match err {
+            LastFmError::Http(e) => e.into(),
+            LastFmError::Api { code, .. }
+                if TEMPORARY_ERROR_CODES.contains(&code) || code == INVALID_SESSION =>
+            {
+                SubmitError::Temporary(err.to_string())
+            }
+            LastFmError::Api { .. } | LastFmError::MissingMetadata(_) | LastFmError::Decode(_) => {
+                SubmitError::Rejected(err.to_string())
+            }
+            LastFmError::Host(_) | LastFmError::NotLoggedIn | LastFmError::TooManyScrobbles(_) => {
+                SubmitError::Temporary(err.to_string())
+            }
+        }
+    }
+}
+
+pub type LastFmResult<T> = Result<T, LastFmError>;
+
+/// An authenticated Last.fm session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// Name of the user.
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Debug, Default)]
+struct AuthState {
+    session: Option<Session>,
+    /// Token of a desktop login waiting for the user to allow access.
+    pending_token: Option<String>,
+    session_preference: Option<String>,
+}
+
+/// Client of the Last.fm API.
+///
+/// A client is a handle: clones share the same session.
+#[derive(Debug, Clone)]
+pub struct LastFm {
+    api_key: String,
+    secret: String,
+    api_url: String,
+    auth_url: String,
+    callback_url: Option<String>,
+    client: Client,
+    auth: Rc<RefCell<AuthState>>,
+}
+
+impl LastFm {
+    pub fn new(api_key: impl Into<String>, secret: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            secret: secret.into(),
+            api_url: API_URL.into(),
+            auth_url: AUTH_URL.into(),
+            callback_url: None,
+            client: Client::new(),
+            auth: Rc::default(),
+        }
+    }
+
+    /// Sets the URL API calls are sent to.
+    pub fn with_api_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = url.into();
+        self
+    }
+
+    /// Sets the URL of the page where the user allows access.
+    pub fn with_auth_url(mut self, url: impl Into<String>) -> Self {
+        self.auth_url = url.into();
+        self
+    }
+
+    /// Uses the web flow, with Last.fm redirecting to `url` once the user has
+    /// allowed access.
+    pub fn with_callback_url(mut self, url: impl Into<String>) -> Self {
+        self.callback_url = Some(url.into());
+        self
+    }
+
+    /// Sets the client used to send requests, for example to configure retries.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Loads the session stored in the secure preference `key` and saves it
+    /// there whenever the user logs in or out.
+    pub fn with_session_preference(self, key: impl Into<String>) -> Self {
+        let key = key.into();
+        {
+            let mut auth = self.auth.borrow_mut();
+            match preferences::try_get_secure::<Session>(&key) {
+                Ok(session) => auth.session = session,
+                Err(e) => warn!("Failed to load Last.fm session: {}", e),
+            }
+            auth.session_preference = Some(key);
+        }
+        self
+    }
+
+    /// Returns the current session, completing a pending desktop login if the
+    /// user has allowed access since.
+    pub fn session(&self) -> Option<Session> {
+        let (session, pending) = {
+            let auth = self.auth.borrow();
+            (auth.session.clone(), auth.pending_token.is_some())
+        };
+        if session.is_none() && pending {
+            return self.complete_login().ok();
+        }
+        session
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.session().is_some()
+    }
+
+    /// Starts logging in: opens the page where the user allows access and
+    /// returns its URL.
+    pub fn login(&self) -> LastFmResult<String> {
+        let request = self
+            .client
+            .get(&self.auth_url)
+            .query("api_key", &self.api_key);
+        let url = match &self.callback_url {
+            Some(callback) => request.query("cb", callback).url(),
+            None => {
+                let response = self.send("auth.getToken", vec![], true, false)?;
+                let token = string_field(&response, &["token"])?;
+                let url = request.query("token", &token).url();
+                self.auth.borrow_mut().pending_token = Some(token);
+                url
+            }
+        };
+
+        extension_api::open_external_url(url.clone())?;
+        Ok(url)
+    }
+
+    /// Completes a web login with the URL Last.fm redirected to, or the token it contains.
+    pub fn handle_callback(&self, callback: &str) -> LastFmResult<Session> {
+        let token = callback
+            .split(['?', '&', '#'])
+            .find_map(|param| param.strip_prefix("token="))
+            .unwrap_or(callback);
+        self.fetch_session(token)
+    }
+
+    /// Completes a desktop login, once the user has allowed access.
+    pub fn complete_login(&self) -> LastFmResult<Session> {
+        let token = self.auth.borrow().pending_token.clone();
+        let token = token.ok_or(LastFmError::NotLoggedIn)?;
+        let result = self.fetch_session(&token);
+        if let Err(LastFmError::Api { code, .. }) = &result {
+            if INVALID_TOKEN_CODES.contains(code) {
+                self.auth.borrow_mut().pending_token = None;
+            }
+        }
+        result
+    }
+
+    /// Forgets the session.
+    pub fn logout(&self) {
+        self.auth.borrow_mut().pending_token = None;
+        self.set_session(None);
+    }
+
+    /// Implements [`Accounts::perform_account_login`](crate::api::Accounts::perform_account_login):
+    /// starts logging in and returns the URL of the authorization page, or logs out.
+    pub fn perform_account_login(&self, args: &AccountLoginArgs) -> LastFmResult<String> {
+        if args.login_status {
+            self.login()
+        } else {
+            self.logout();
+            Ok(String::new())
+        }
+    }
+
+    /// Implements [`Accounts::oauth_callback`](crate::api::Accounts::oauth_callback).
+    pub fn oauth_callback(&self, code: &str) -> LastFmResult<()> {
+        self.handle_callback(code).map(|_| ())
+    }
+
+    /// Tells Last.fm that the user started listening to `song`.
+    pub fn update_now_playing(&self, song: &Song) -> LastFmResult<()> {
+        let params = track_params(song, None)?;
+        self.call_signed("track.updateNowPlaying", params)?;
+        Ok(())
+    }
+
+    /// Scrobbles `scrobbles` in a single call.
+    ///
+    /// Fails with [`LastFmError::TooManyScrobbles`], without sending anything, if
+    /// there are more than [`MAX_SCROBBLES_PER_REQUEST`]. Scrobbles of songs
+    /// without a title or artist are skipped.
+    pub fn scrobble(&self, scrobbles: &[Scrobble]) -> LastFmResult<()> {
+        if scrobbles.len() > MAX_SCROBBLES_PER_REQUEST {
+            return Err(LastFmError::TooManyScrobbles(scrobbles.len()));
+        }
+
+        let valid: Vec<_> = scrobbles
+            .iter()
+            .filter(|scrobble| match track_params(&scrobble.song, None) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Skipping scrobble: {}", e);
+                    false
+                }
+            })
+            .collect();
+
+        if valid.is_empty() {
+            return Ok(());
+        }
+
+        let mut params = vec![];
+        for (i, scrobble) in valid.iter().enumerate() {
+            params.extend(track_params(&scrobble.song, Some(i))?);
+            params.push((format!("timestamp[{}]", i), scrobble.timestamp.to_string()));
+        }
+        self.call_signed("track.scrobble", params)?;
+        Ok(())
+    }
+
+    /// Marks `song` as loved.
+    pub fn love(&self, song: &Song) -> LastFmResult<()> {
+        self.call_signed("track.love", love_params(song)?)?;
+        Ok(())
+    }
+
+    /// Removes `song` from the loved tracks.
+    pub fn unlove(&self, song: &Song) -> LastFmResult<()> {
+        self.call_signed("track.unlove", love_params(song)?)?;
+        Ok(())
+    }
+
+    /// Calls a read method of the API, which doesn't need a session.
+    pub fn call(&self, method: &str, params: Vec<(String, String)>) -> LastFmResult<Value> {
+        self.send(method, params, false, false)
+    }
+
+    /// Calls a write method of the API with the session of the user.
+    pub fn call_signed(
+        &self,
+        method: &str,
+        mut params: Vec<(String, String)>,
+    ) -> LastFmResult<Value> {
+        let session = self.session().ok_or(LastFmError::NotLoggedIn)?;
+        params.push(("sk".into(), session.key));
+        match self.send(method, params, true, true) {
+            Err(LastFmError::Api { code, message }) if code == INVALID_SESSION => {
+                // The user revoked access.
+                self.set_session(None);
+                Err(LastFmError::Api { code, message })
+            }
+            result => result,
+        }
+    }
+
+    /// Returns the `api_sig` of a call: the MD5 of the parameters sorted by name
+    /// and concatenated, followed by the secret.
+    pub fn signature(&self, params: &BTreeMap<String, String>) -> LastFmResult<String> {
+        let mut data = String::new();
+        for (key, value) in params {
+            if key != "format" && key != "callback" {
+                data.push_str(key);
+                data.push_str(value);
+            }
+        }
+        data.push_str(&self.secret);
+
+        // The main app returns the raw digest, not its hex encoding.
+        let digest = extension_api::gen_hash("md5".into(), data.into_bytes())?;
+        if digest.len() != 16 {
+            return Err(LastFmError::Host(MoosyncError::String(format!(
+                "Expected a 16 byte MD5 digest, got {} bytes",
+                digest.len()
+            ))));
+        }
+        Ok(hex(&digest))
+    }
+
+    fn fetch_session(&self, token: &str) -> LastFmResult<Session> {
+        let params = vec![("token".to_string(), token.to_string())];
+        let response = self.send("auth.getSession", params, true, false)?;
+        let session = Session {
+            name: string_field(&response, &["session", "name"])?,
+            key: string_field(&response, &["session", "key"])?,
+        };
+
+        self.auth.borrow_mut().pending_token = None;
+        self.set_session(Some(session.clone()));
+        Ok(session)
+    }
+
+    fn set_session(&self, session: Option<Session>) {
+        let key = {
+            let mut auth = self.auth.borrow_mut();
+            if auth.session == session {
+                return;
+            }
+            auth.session = session.clone();
+            auth.session_preference.clone()
+        };
+
+        if let Some(key) = key {
+            let saved = match &session {
+                Some(session) => preferences::set_secure(&key, session),
+                None => preferences::set_secure(&key, &Value::Null),
+            };
+            if let Err(e) = saved {
+                warn!("Failed to save Last.fm session: {}", e);
+            }
+        }
+        if let Err(e) = extension_api::update_accounts(None) {
+            warn!("Failed to update accounts: {}", e);
+        }
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        params: Vec<(String, String)>,
+        signed: bool,
+        post: bool,
+    ) -> LastFmResult<Value> {
+        let mut params: BTreeMap<String, String> = params.into_iter().collect();
+        params.insert("method".into(), method.into());
+        params.insert("api_key".into(), self.api_key.clone());
+        if signed {
+            let signature = self.signature(&params)?;
+            params.insert("api_sig".into(), signature);
+        }
+        params.insert("format".into(), "json".into());
+
+        let request = if post {
+            self.client.post(&self.api_url).form(params)
+        } else {
+            self.client.get(&self.api_url).query_pairs(params)
+        };
+
+        // Errors are reported in the body, with or without an error status.
+        let response: Value = match request.send() {
+            Ok(response) => response.json()?,
+            Err(err) => return Err(api_error(&err).unwrap_or(LastFmError::Http(err))),
+        };
+        match parse_api_error(&response) {
+            Some(err) => Err(err),
+            None => Ok(response),
+        }
+    }
+}
+
+fn api_error(err: &HttpError) -> Option<LastFmError> {
+    match err {
+        HttpError::Status { body, .. } => parse_api_error(&serde_json::from_slice(body).ok()?),
+        _ => None,
+    }
+}
+
+fn parse_api_error(response: &Value) -> Option<LastFmError> {
+    let code = response.get("error")?.as_u64()?;
+    let message = response
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Some(LastFmError::Api {
+        code: code as u32,
+        message: message.into(),
+    })
+}
+
+fn string_field(response: &Value, path: &[&str]) -> LastFmResult<String> {
+    path.iter()
+        .try_fold(response, |value, key| value.get(key))
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or_else(|| {
+            LastFmError::Decode(format!("Response has no {}: {}", path.join("."), response))
+        })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn artist_name(song: &Song) -> Option<&str> {
+    song.artists
+        .iter()
+        .flatten()
+        .find_map(|artist| artist.artist_name.as_deref())
+        .filter(|name| !name.is_empty())
+}
+
+fn title(song: &Song) -> Option<&str> {
+    song.song.title.as_deref().filter(|title| !title.is_empty())
+}
+
+/// Parameters describing `song` in track methods, suffixed with `[index]` for
+/// batched calls.
+fn track_params(song: &Song, index: Option<usize>) -> LastFmResult<Vec<(String, String)>> {
+    let name = |key: &str| match index {
+        Some(i) => format!("{}[{}]", key, i),
+        None => key.to_string(),
+    };
+
+    let artist = artist_name(song).ok_or(LastFmError::MissingMetadata("artist"))?;
+    let track = title(song).ok_or(LastFmError::MissingMetadata("title"))?;
+    let mut params = vec![
+        (name("artist"), artist.into()),
+        (name("track"), track.into()),
+    ];
+
+    if let Some(album) = song
+        .album
+        .as_ref()
+        .and_then(|album| album.album_name.as_deref())
+        .filter(|album| !album.is_empty())
+    {
+        params.push((name("album"), album.into()));
+    }
+    if let Some(duration) = song.song.duration.filter(|duration| *duration > 0.0) {
+        params.push((name("duration"), (duration.round() as u64).to_string()));
+    }
+    Ok(params)
+}
+
+fn love_params(song: &Song) -> LastFmResult<Vec<(String, String)>> {
+    let artist = artist_name(song).ok_or(LastFmError::MissingMetadata("artist"))?;
+    let track = title(song).ok_or(LastFmError::MissingMetadata("title"))?;
+    Ok(vec![
+        ("artist".into(), artist.into()),
+        ("track".into(), track.into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use types::entities::{QueryableAlbum, QueryableArtist};
+
+    use super::*;
+    use crate::http::{Response, RetryPolicy};
+    use crate::testing::MockHost;
+
+    const API: &str = "http://localhost:8080/2.0/";
+
+    /// URL and body of each request sent.
+    type Requests = Rc<RefCell<Vec<(String, String)>>>;
+
+    fn song(title: &str, artist: &str) -> Song {
+        let mut song = Song::default();
+        song.song.title = Some(title.into());
+        song.artists = Some(vec![QueryableArtist {
+            artist_name: Some(artist.into()),
+            ..Default::default()
+        }]);
+        song
+    }
+
+    fn fake_md5(_: &str, _: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(vec![0xab; 16])
+    }
+
+    /// A client whose requests are answered with `responses` in order.
+    fn lastfm(responses: Vec<Response>) -> (LastFm, Requests) {
+        MockHost::new().set_hash_fn(fake_md5);
+        let requests = Requests::default();
+        let responses = RefCell::new(VecDeque::from(responses));
+        let client = Client::new()
+            .with_retry(RetryPolicy::none())
+            .with_transport({
+                let requests = requests.clone();
+                move |req, body| {
+                    let body = String::from_utf8_lossy(body.unwrap_or_default()).into_owned();
+                    requests.borrow_mut().push((req.url.clone(), body));
+                    responses
+                        .borrow_mut()
+                        .pop_front()
+                        .ok_or_else(|| extism_pdk::Error::msg("No response queued"))
+                }
+            });
+        let lastfm = LastFm::new("key", "secret")
+            .with_api_url(API)
+            .with_auth_url("http://localhost:8080/auth/")
+            .with_client(client);
+        (lastfm, requests)
+    }
+
+    fn log_in(lastfm: &LastFm) {
+        lastfm.auth.borrow_mut().session = Some(Session {
+            name: "user".into(),
+            key: "session-key".into(),
+        });
+    }
+
+    fn session_response() -> Response {
+        Response::new(200, r#"{"session":{"name":"user","key":"session-key"}}"#)
+    }
+
+    fn authspec_md5(kind: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        assert_eq!(kind, "md5");
+        match data {
+            b"api_keyxxxxxxxxmethodauth.getSessiontokenxxxxxxxmysecret" => Ok((0..16).collect()),
+            _ => Err(format!(
+                "Unexpected input {}",
+                String::from_utf8_lossy(data)
+            )),
+        }
+    }
+
+    #[test]
+    fn signature_follows_the_authspec() {
+        MockHost::new().set_hash_fn(authspec_md5);
+        let lastfm = LastFm::new("xxxxxxxx", "mysecret");
+        let params = BTreeMap::from([
+            ("token".to_string(), "xxxxxxx".to_string()),
+            ("method".to_string(), "auth.getSession".to_string()),
+            ("api_key".to_string(), "xxxxxxxx".to_string()),
+            ("format".to_string(), "json".to_string()),
+            ("callback".to_string(), "cb".to_string()),
+        ]);
+
+        assert_eq!(
+            lastfm.signature(&params).unwrap(),
+            "000102030405060708090a0b0c0d0e0f"
+        );
+    }
+
+    #[test]
+    fn signature_requires_an_md5_digest() {
+        MockHost::new().set_hash_fn(|_, data| Ok(data.to_vec()));
+        let lastfm = LastFm::new("key", "secret");
+
+        let err = lastfm.signature(&BTreeMap::new()).unwrap_err();
+        assert!(matches!(err, LastFmError::Host(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn track_params_are_indexed_in_batches() {
+        let mut song = song("Title", "Artist");
+        song.album = Some(QueryableAlbum {
+            album_name: Some("Album".into()),
+            ..Default::default()
+        });
+        song.song.duration = Some(212.6);
+
+        assert_eq!(
+            track_params(&song, None).unwrap(),
+            vec![
+                ("artist".to_string(), "Artist".to_string()),
+                ("track".to_string(), "Title".to_string()),
+                ("album".to_string(), "Album".to_string()),
+                ("duration".to_string(), "213".to_string()),
+            ]
+        );
+        assert_eq!(
+            track_params(&song, Some(3)).unwrap(),
+            vec![
+                ("artist[3]".to_string(), "Artist".to_string()),
+                ("track[3]".to_string(), "Title".to_string()),
+                ("album[3]".to_string(), "Album".to_string()),
+                ("duration[3]".to_string(), "213".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn track_params_require_an_artist_and_title() {
+        let mut untitled = song("", "Artist");
+        untitled.song.title = None;
+        assert!(matches!(
+            track_params(&untitled, None),
+            Err(LastFmError::MissingMetadata("title"))
+        ));
+        assert!(matches!(
+            track_params(&song("Title", ""), None),
+            Err(LastFmError::MissingMetadata("artist"))
+        ));
+    }
+
+    #[test]
+    fn api_errors_are_read_from_the_body() {
+        let response = serde_json::json!({"error": 9, "message": "Invalid session key"});
+        match parse_api_error(&response) {
+            Some(LastFmError::Api { code, message }) => {
+                assert_eq!(code, 9);
+                assert_eq!(message, "Invalid session key");
+            }
+            other => panic!("Expected an API error, got {:?}", other),
+        }
+
+        assert!(parse_api_error(&serde_json::json!({"scrobbles": {}})).is_none());
+        assert!(parse_api_error(&serde_json::json!({"error": "nope"})).is_none());
+    }
+
+    #[test]
+    fn callback_token_is_extracted() {
+        for callback in [
+            "https://example.com/callback?token=abc&state=1",
+            "https://example.com/callback?state=1&token=abc",
+            "https://example.com/callback#token=abc",
+            "abc",
+        ] {
+            let (lastfm, requests) = lastfm(vec![session_response()]);
+            lastfm.handle_callback(callback).unwrap();
+
+            let (url, _) = requests.borrow()[0].clone();
+            assert!(url.contains("method=auth.getSession"), "{}", url);
+            assert!(
+                url.split(['?', '&']).any(|pair| pair == "token=abc"),
+                "{} from {}",
+                url,
+                callback
+            );
+        }
+    }
+
+    #[test]
+    fn errors_tell_the_queue_what_to_keep() {
+        let temporary = [
+            LastFmError::Api {
+                code: 11,
+                message: String::new(),
+            },
+            LastFmError::Api {
+                code: INVALID_SESSION,
+                message: String::new(),
+            },
+            LastFmError::Host(MoosyncError::String("failed".into())),
+            LastFmError::NotLoggedIn,
+            LastFmError::TooManyScrobbles(51),
+        ];
+        for err in temporary {
+            let message = err.to_string();
+            assert!(
+                matches!(SubmitError::from(err), SubmitError::Temporary(_)),
+                "{}",
+                message
+            );
+        }
+
+        let rejected = [
+            LastFmError::Api {
+                code: 6,
+                message: String::new(),
+            },
+            LastFmError::MissingMetadata("title"),
+            LastFmError::Decode("Response has no session".into()),
+        ];
+        for err in rejected {
+            let message = err.to_string();
+            assert!(
+                matches!(SubmitError::from(err), SubmitError::Rejected(_)),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn desktop_login_fetches_the_session_once_allowed() {
+        let (lastfm, requests) = lastfm(vec![
+            Response::new(200, r#"{"token":"tok"}"#),
+            session_response(),
+        ]);
+        let host = MockHost::new();
+        host.set_hash_fn(fake_md5);
+        host.queue_response("OpenExternalUrl", Value::Null);
+
+        let url = lastfm.login().unwrap();
+        assert_eq!(url, "http://localhost:8080/auth/?api_key=key&token=tok");
+        host.assert_sent("OpenExternalUrl");
+        assert!(requests.borrow()[0].0.starts_with(API));
+
+        assert_eq!(
+            lastfm.session(),
+            Some(Session {
+                name: "user".into(),
+                key: "session-key".into(),
+            })
+        );
+        assert!(requests.borrow()[1].0.contains("token=tok"));
+        host.assert_sent("UpdateAccounts");
+    }
+
+    #[test]
+    fn scrobbles_are_sent_in_one_signed_call() {
+        let (lastfm, requests) = lastfm(vec![Response::new(200, r#"{"scrobbles":{}}"#)]);
+        log_in(&lastfm);
+
+        let scrobbles = [
+            Scrobble {
+                song: song("One", "Artist"),
+                timestamp: 100,
+            },
+            Scrobble {
+                song: song("", "Skipped"),
+                timestamp: 200,
+            },
+            Scrobble {
+                song: song("Two", "Artist"),
+                timestamp: 300,
+            },
+        ];
+        lastfm.scrobble(&scrobbles).unwrap();
+
+        let requests = requests.borrow();
+        assert_eq!(requests.len(), 1);
+        let (url, body) = &requests[0];
+        assert_eq!(url, API);
+        for param in [
+            "method=track.scrobble",
+            "track%5B0%5D=One",
+            "timestamp%5B0%5D=100",
+            "track%5B1%5D=Two",
+            "timestamp%5B1%5D=300",
+            "sk=session-key",
+            "api_sig=abababababababababababababababab",
+            "format=json",
+        ] {
+            assert!(
+                body.split('&').any(|pair| pair == param),
+                "{} in {}",
+                param,
+                body
+            );
+        }
+        assert!(!body.contains("Skipped"));
+    }
+
+    #[test]
+    fn oversized_batches_are_refused_before_sending() {
+        let (lastfm, requests) = lastfm(vec![]);
+        log_in(&lastfm);
+
+        let scrobbles: Vec<_> = (0..=MAX_SCROBBLES_PER_REQUEST as u64)
+            .map(|timestamp| Scrobble {
+                song: song("Title", "Artist"),
+                timestamp,
+            })
+            .collect();
+        let err = lastfm.scrobble(&scrobbles).unwrap_err();
+
+        assert!(
+            matches!(err, LastFmError::TooManyScrobbles(51)),
+            "{:?}",
+            err
+        );
+        assert!(requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn an_invalid_session_logs_out() {
+        let (lastfm, _) = lastfm(vec![Response::new(
+            403,
+            r#"{"error":9,"message":"Invalid session key"}"#,
+        )]);
+        log_in(&lastfm);
+
+        let err = lastfm.love(&song("Title", "Artist")).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                LastFmError::Api {
+                    code: INVALID_SESSION,
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert!(!lastfm.is_logged_in());
+    }
+
+    #[test]
+    fn a_response_without_the_session_is_a_decode_error() {
+        let (lastfm, _) = lastfm(vec![Response::new(200, r#"{"session":{"name":"user"}}"#)]);
+
+        let err = lastfm.handle_callback("abc").unwrap_err();
+        assert!(matches!(err, LastFmError::Decode(_)), "{:?}", err);
+        assert!(!lastfm.is_logged_in());
+    }
+}
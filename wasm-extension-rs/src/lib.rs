@@ -14,7 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-pub use extism_pdk::{config, error, http, info, log, warn, HttpRequest, HttpResponse};
+use events::Queue;
+pub use extism_pdk::{config, log};
 use extism_pdk::{plugin_fn, FnResult, Json};
 use handler::{
     get_accounts, get_album_songs, get_artist_songs, get_lyrics, get_playback_details,
@@ -25,15 +26,39 @@ use handler::{
     on_queue_changed, on_seeked, on_song_added, on_song_changed, on_song_removed,
     on_volume_changed, perform_account_login, scrobble, search,
 };
+pub use http::{HttpRequest, HttpResponse};
+pub use moosync_edk_macros::{moosync_extension, provider_scopes, Preferences};
 use serde_json::Value;
 
+// Lets the derives and attribute macros, which refer to `::moosync_edk`, be used
+// in this crate's own tests.
+#[cfg(test)]
+extern crate self as moosync_edk;
+
 pub use types::{
     entities::*, errors::*, extensions::*, songs::*, ui::extensions::*,
     ui::player_details::PlayerState,
 };
 
 pub mod api;
+pub mod async_api;
+pub mod cache;
+pub mod events;
 pub mod handler;
+pub mod http;
+pub mod lastfm;
+pub mod listenbrainz;
+pub mod logging;
+pub mod lyrics;
+pub mod permissions;
+pub mod preferences;
+pub mod scrobble;
+pub mod socket;
+pub mod storage;
+#[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+pub mod testing;
+
+pub use tokio;
 
 extern "C" {
     fn init();
@@ -42,9 +67,17 @@ extern "C" {
 #[tracing::instrument(level = "debug", skip())]
 #[plugin_fn]
 pub fn entry() -> FnResult<()> {
+    async_api::init_runtime()?;
+
     unsafe {
         init();
     }
+
+    #[cfg(debug_assertions)]
+    if !handler::is_extension_registered() {
+        warn!("init returned without calling register_extension, every call to this extension will fail");
+    }
+
     Ok(())
 }
 
@@ -69,8 +102,8 @@ pub fn get_playlist_content_wrapper(
 ) -> FnResult<Json<SongsWithPageTokenReturnType>> {
     let ret = get_playlist_content(id, token)?;
     Ok(Json(SongsWithPageTokenReturnType {
-        songs: ret,
-        next_page_token: None,
+        songs: ret.songs,
+        next_page_token: ret.next_page_token.map(Value::String),
     }))
 }
 
@@ -137,8 +170,8 @@ pub fn get_artist_songs_wrapper(
 ) -> FnResult<Json<SongsWithPageTokenReturnType>> {
     let ret = get_artist_songs(artist, token)?;
     Ok(Json(SongsWithPageTokenReturnType {
-        songs: ret,
-        next_page_token: None,
+        songs: ret.songs,
+        next_page_token: ret.next_page_token.map(Value::String),
     }))
 }
 
@@ -149,8 +182,8 @@ pub fn get_album_songs_wrapper(
 ) -> FnResult<Json<SongsWithPageTokenReturnType>> {
     let ret = get_album_songs(album, token)?;
     Ok(Json(SongsWithPageTokenReturnType {
-        songs: ret,
-        next_page_token: None,
+        songs: ret.songs,
+        next_page_token: ret.next_page_token.map(Value::String),
     }))
 }
 
@@ -164,29 +197,29 @@ pub fn get_song_from_id_wrapper(Json(id): Json<String>) -> FnResult<Json<SongRet
 // PlayerEvents trait wrappers
 #[tracing::instrument(level = "debug", skip())]
 #[plugin_fn]
-pub fn on_queue_changed_wrapper(Json(queue): Json<Value>) -> FnResult<Json<()>> {
+pub fn on_queue_changed_wrapper(Json(queue): Json<Queue>) -> FnResult<Json<()>> {
     on_queue_changed(queue)?;
     Ok(Json(()))
 }
 
-#[tracing::instrument(level = "debug", skip())]
+#[tracing::instrument(level = "debug", skip(input))]
 #[plugin_fn]
-pub fn on_volume_changed_wrapper() -> FnResult<Json<()>> {
-    on_volume_changed()?;
+pub fn on_volume_changed_wrapper(input: Vec<u8>) -> FnResult<Json<()>> {
+    on_volume_changed(events::volume_changed(&input)?)?;
     Ok(Json(()))
 }
 
-#[tracing::instrument(level = "debug", skip())]
+#[tracing::instrument(level = "debug", skip(input))]
 #[plugin_fn]
-pub fn on_player_state_changed_wrapper() -> FnResult<Json<()>> {
-    on_player_state_changed()?;
+pub fn on_player_state_changed_wrapper(input: Vec<u8>) -> FnResult<Json<()>> {
+    on_player_state_changed(events::player_state_changed(&input)?)?;
     Ok(Json(()))
 }
 
-#[tracing::instrument(level = "debug", skip())]
+#[tracing::instrument(level = "debug", skip(input))]
 #[plugin_fn]
-pub fn on_song_changed_wrapper() -> FnResult<Json<()>> {
-    on_song_changed()?;
+pub fn on_song_changed_wrapper(input: Vec<u8>) -> FnResult<Json<()>> {
+    on_song_changed(events::song_changed(&input)?)?;
     Ok(Json(()))
 }
 
@@ -287,5 +320,5 @@ pub fn on_context_menu_action_wrapper(Json(action): Json<String>) -> FnResult<Js
 #[tracing::instrument(level = "debug", skip())]
 #[plugin_fn]
 pub fn get_lyrics_wrapper(Json(song): Json<Song>) -> FnResult<Json<String>> {
-    Ok(Json(get_lyrics(song)?))
+    Ok(Json(get_lyrics(song)?.into_host_format()))
 }
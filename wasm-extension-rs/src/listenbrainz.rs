@@ -0,0 +1,720 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! ListenBrainz submission client.
+//!
+//! [`ListenBrainz`] submits listens with a user token, read from a secure
+//! preference so that it can be entered in the extension's preferences. The
+//! declared hosts must include `api.listenbrainz.org`.
+//!
+//! ```ignore
+//! let listenbrainz = ListenBrainz::from_preference("listenbrainz_token");
+//!
+//! impl PlayerEvents for MyExtension {
+//!     fn on_song_changed(&self, event: SongChanged) -> MoosyncResult<()> {
+//!         Ok(self.listenbrainz.on_song_changed(&event)?)
+//!     }
+//! }
+//!
+//! impl Provider for MyExtension {
+//!     fn scrobble(&self, song: Song) -> MoosyncResult<()> {
+//!         self.queue.push(song);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! where `queue` is a [`ScrobbleQueue`](crate::scrobble::ScrobbleQueue) submitting
+//! through [`ListenBrainz::scrobble`].
+
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use types::errors::MoosyncError;
+use types::songs::Song;
+
+use crate::events::SongChanged;
+use crate::http::{Client, HttpError};
+use crate::preferences::{self, PreferenceError};
+use crate::scrobble::{Scrobble, SubmitError};
+use crate::warn;
+
+const API_URL: &str = "https://api.listenbrainz.org/1/";
+
+/// Most listens ListenBrainz accepts in a single import.
+pub const MAX_LISTENS_PER_IMPORT: usize = 1000;
+
+/// Name reported as the `submission_client` of listens.
+const SUBMISSION_CLIENT: &str = "Moosync";
+
+/// Error returned by [`ListenBrainz`] calls.
+#[derive(Debug)]
+pub enum ListenBrainzError {
+    /// The request failed.
+    Http(HttpError),
+    /// ListenBrainz refused the request.
+    Api { status: u16, message: String },
+    /// The token could not be read from the preferences.
+    Preference(PreferenceError),
+    /// No token was set.
+    NoToken,
+    /// The song lacks the title or artist ListenBrainz requires.
+    MissingMetadata(&'static str),
+}
+
+impl Display for ListenBrainzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenBrainzError::Http(e) => write!(f, "{}", e),
+            ListenBrainzError::Api { status, message } => {
+                write!(f, "ListenBrainz returned {}: {}", status, message)
+            }
+            ListenBrainzError::Preference(e) => write!(f, "{}", e),
+            ListenBrainzError::NoToken => write!(f, "No ListenBrainz token set"),
+            ListenBrainzError::MissingMetadata(field) => write!(f, "Song has no {}", field),
+        }
+    }
+}
+
+impl std::error::Error for ListenBrainzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ListenBrainzError::Http(e) => Some(e),
+            ListenBrainzError::Preference(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Error responses carry a JSON body with the reason, which is kept as the message.
+impl From<HttpError> for ListenBrainzError {
+    fn from(err: HttpError) -> Self {
+        if let HttpError::Status { status, body, .. } = &err {
+            let message = serde_json::from_slice::<Value>(body)
+                .ok()
+                .and_then(|body| body.get("error")?.as_str().map(String::from));
+            if let Some(message) = message {
+                return ListenBrainzError::Api {
+                    status: *status,
+                    message,
+                };
+            }
+        }
+        ListenBrainzError::Http(err)
+    }
+}
+
+impl From<PreferenceError> for ListenBrainzError {
+    fn from(err: PreferenceError) -> Self {
+        ListenBrainzError::Preference(err)
+    }
+}
+
+impl From<ListenBrainzError> for MoosyncError {
+    fn from(err: ListenBrainzError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+/// Listens are kept while the token is missing or invalid and when ListenBrainz
+/// is unavailable, and dropped when it refuses them.
+impl From<ListenBrainzError> for SubmitError {
+    fn from(err: ListenBrainzError) -> Self {
+        match err {
+            ListenBrainzError::Http(HttpError::Status { status: 401, .. }) => {
+                SubmitError::Temporary(err.to_string())
+            }
+            ListenBrainzError::Http(e) => e.into(),
+            ListenBrainzError::Api { status, .. }
+                if (400..500).contains(&status) && ![401, 408, 429].contains(&status) =>
+            {
+                SubmitError::Rejected(err.to_string())
+            }
+            ListenBrainzError::MissingMetadata(_) => SubmitError::Rejected(err.to_string()),
+            _ => SubmitError::Temporary(err.to_string()),
+        }
+    }
+}
+
+pub type ListenBrainzResult<T> = Result<T, ListenBrainzError>;
+
+/// Kind of a submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenType {
+    /// A single song that has been listened to.
+    Single,
+    /// The song now playing, which isn't stored as a listen.
+    PlayingNow,
+    /// Several listens at once, like a backlog.
+    Import,
+}
+
+/// Optional details of a listen.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdditionalInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artist_mbids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_mbid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording_mbid: Option<String>,
+    /// URL the song was played from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_client: Option<String>,
+    /// Any other field accepted by ListenBrainz.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Metadata of the listened track.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    pub additional_info: AdditionalInfo,
+}
+
+/// A listen, as submitted to ListenBrainz.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listen {
+    /// When the song was listened to, in seconds since the Unix epoch. Omitted
+    /// for the song now playing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listened_at: Option<u64>,
+    pub track_metadata: TrackMetadata,
+}
+
+impl Listen {
+    /// Creates the listen of `song`, with the artists, title, album, duration,
+    /// artist MBIDs and URL it has.
+    pub fn from_song(song: &Song, listened_at: Option<u64>) -> ListenBrainzResult<Self> {
+        let artists = song.artists.as_deref().unwrap_or_default();
+        let artist_name = artists
+            .iter()
+            .filter_map(|artist| artist.artist_name.as_deref())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if artist_name.is_empty() {
+            return Err(ListenBrainzError::MissingMetadata("artist"));
+        }
+        let track_name = song
+            .song
+            .title
+            .clone()
+            .filter(|title| !title.is_empty())
+            .ok_or(ListenBrainzError::MissingMetadata("title"))?;
+
+        let additional_info = AdditionalInfo {
+            duration_ms: song
+                .song
+                .duration
+                .filter(|duration| *duration > 0.0)
+                .map(|duration| (duration * 1000.0).round() as u64),
+            artist_mbids: artists
+                .iter()
+                .filter_map(|artist| artist.artist_mbid.clone())
+                .filter(|mbid| !mbid.is_empty())
+                .collect(),
+            origin_url: song
+                .song
+                .url
+                .clone()
+                .filter(|url| url.starts_with("http://") || url.starts_with("https://")),
+            submission_client: Some(SUBMISSION_CLIENT.into()),
+            ..Default::default()
+        };
+
+        Ok(Self {
+            listened_at,
+            track_metadata: TrackMetadata {
+                artist_name,
+                track_name,
+                release_name: song
+                    .album
+                    .as_ref()
+                    .and_then(|album| album.album_name.clone())
+                    .filter(|name| !name.is_empty()),
+                additional_info,
+            },
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: ListenType,
+    payload: &'a [Listen],
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Static(String),
+    /// Read from the secure preference with this key on every call.
+    Preference(String),
+}
+
+/// Client of the ListenBrainz API.
+#[derive(Debug, Clone)]
+pub struct ListenBrainz {
+    token: Token,
+    api_url: String,
+    client: Client,
+}
+
+impl ListenBrainz {
+    /// Creates a client submitting with `token`.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self::with_token(Token::Static(token.into()))
+    }
+
+    /// Creates a client submitting with the token stored in the secure preference `key`.
+    pub fn from_preference(key: impl Into<String>) -> Self {
+        Self::with_token(Token::Preference(key.into()))
+    }
+
+    fn with_token(token: Token) -> Self {
+        Self {
+            token,
+            api_url: API_URL.into(),
+            client: Client::new(),
+        }
+    }
+
+    /// Sets the root of the API, for example to use a self-hosted server.
+    pub fn with_api_url(mut self, url: impl Into<String>) -> Self {
+        let mut url = url.into();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        self.api_url = url;
+        self
+    }
+
+    /// Sets the client used to send requests, for example to configure retries.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Tells ListenBrainz that the user started listening to `song`.
+    pub fn playing_now(&self, song: &Song) -> ListenBrainzResult<()> {
+        let listen = Listen::from_song(song, None)?;
+        self.submit(ListenType::PlayingNow, &[listen])
+    }
+
+    /// Submits a listen of `song` at `listened_at`, in seconds since the Unix epoch.
+    pub fn single(&self, song: &Song, listened_at: u64) -> ListenBrainzResult<()> {
+        let listen = Listen::from_song(song, Some(listened_at))?;
+        self.submit(ListenType::Single, &[listen])
+    }
+
+    /// Imports `listens`, in requests of at most [`MAX_LISTENS_PER_IMPORT`].
+    pub fn import(&self, listens: &[Listen]) -> ListenBrainzResult<()> {
+        for batch in listens.chunks(MAX_LISTENS_PER_IMPORT) {
+            self.submit(ListenType::Import, batch)?;
+        }
+        Ok(())
+    }
+
+    /// Submits the listens of `scrobbles`, as a single listen or an import.
+    ///
+    /// Scrobbles of songs without a title or artist are skipped.
+    pub fn scrobble(&self, scrobbles: &[Scrobble]) -> ListenBrainzResult<()> {
+        let listens: Vec<_> = scrobbles
+            .iter()
+            .filter_map(|scrobble| {
+                match Listen::from_song(&scrobble.song, Some(scrobble.timestamp)) {
+                    Ok(listen) => Some(listen),
+                    Err(e) => {
+                        warn!("Skipping listen: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        match listens.len() {
+            0 => Ok(()),
+            1 => self.submit(ListenType::Single, &listens),
+            _ => self.import(&listens),
+        }
+    }
+
+    /// Implements [`PlayerEvents::on_song_changed`](crate::api::PlayerEvents::on_song_changed)
+    /// by submitting the new song as playing now.
+    pub fn on_song_changed(&self, event: &SongChanged) -> ListenBrainzResult<()> {
+        match &event.song {
+            Some(song) => self.playing_now(song),
+            None => Ok(()),
+        }
+    }
+
+    /// Submits `listens` of the given type.
+    pub fn submit(&self, listen_type: ListenType, listens: &[Listen]) -> ListenBrainzResult<()> {
+        let token = self.token()?;
+        self.client
+            .post(format!("{}submit-listens", self.api_url))
+            .header("Authorization", format!("Token {}", token))
+            .json(&Submission {
+                listen_type,
+                payload: listens,
+            })
+            .send()?;
+        Ok(())
+    }
+
+    /// Checks the token, returning the name of its user, or `None` if it is invalid.
+    pub fn validate_token(&self) -> ListenBrainzResult<Option<String>> {
+        let token = self.token()?;
+        let response: Value = self
+            .client
+            .get(format!("{}validate-token", self.api_url))
+            .header("Authorization", format!("Token {}", token))
+            .send()?
+            .json()?;
+
+        let valid = response.get("valid").and_then(Value::as_bool) == Some(true);
+        Ok(valid
+            .then(|| response.get("user_name")?.as_str().map(String::from))
+            .flatten())
+    }
+
+    fn token(&self) -> ListenBrainzResult<String> {
+        let token = match &self.token {
+            Token::Static(token) => Some(token.clone()),
+            Token::Preference(key) => preferences::try_get_secure::<String>(key)?,
+        };
+        token
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty())
+            .ok_or(ListenBrainzError::NoToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::{HashMap, VecDeque};
+    use std::rc::Rc;
+
+    use serde_json::json;
+    use types::entities::{QueryableAlbum, QueryableArtist};
+
+    use super::*;
+    use crate::http::{Response, RetryPolicy};
+    use crate::logging::Level;
+    use crate::testing::MockHost;
+
+    const API: &str = "http://localhost:8080/1/";
+
+    /// URL, Authorization header and body of each request sent.
+    type Requests = Rc<RefCell<Vec<(String, String, Value)>>>;
+
+    fn song(title: &str, artist: &str) -> Song {
+        let mut song = Song::default();
+        song.song.title = Some(title.into());
+        song.artists = Some(vec![QueryableArtist {
+            artist_name: Some(artist.into()),
+            ..Default::default()
+        }]);
+        song
+    }
+
+    fn ok() -> Response {
+        Response::new(200, r#"{"status": "ok"}"#)
+    }
+
+    /// Sends the requests of `listenbrainz` through a transport answering with
+    /// `responses` in order.
+    fn answer(listenbrainz: ListenBrainz, responses: Vec<Response>) -> (ListenBrainz, Requests) {
+        let requests = Requests::default();
+        let responses = RefCell::new(VecDeque::from(responses));
+        let client = Client::new()
+            .with_retry(RetryPolicy::none())
+            .with_transport({
+                let requests = requests.clone();
+                move |req, body| {
+                    let authorization = req
+                        .headers
+                        .get("Authorization")
+                        .cloned()
+                        .unwrap_or_default();
+                    let body =
+                        serde_json::from_slice(body.unwrap_or_default()).unwrap_or(Value::Null);
+                    requests
+                        .borrow_mut()
+                        .push((req.url.clone(), authorization, body));
+                    responses
+                        .borrow_mut()
+                        .pop_front()
+                        .ok_or_else(|| extism_pdk::Error::msg("No response queued"))
+                }
+            });
+        let listenbrainz = listenbrainz.with_api_url(API).with_client(client);
+        (listenbrainz, requests)
+    }
+
+    fn listenbrainz(responses: Vec<Response>) -> (ListenBrainz, Requests) {
+        MockHost::new();
+        answer(ListenBrainz::new("token"), responses)
+    }
+
+    #[test]
+    fn listens_carry_the_song_metadata() {
+        let mut song = song("Song", "Artist");
+        song.artists.as_mut().unwrap().push(QueryableArtist {
+            artist_name: Some("Guest".into()),
+            artist_mbid: Some("mbid-guest".into()),
+            ..Default::default()
+        });
+        song.album = Some(QueryableAlbum {
+            album_name: Some("Album".into()),
+            ..Default::default()
+        });
+        song.song.duration = Some(215.5);
+        song.song.url = Some("https://example.com/song".into());
+
+        let listen = Listen::from_song(&song, Some(100)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&listen).unwrap(),
+            json!({
+                "listened_at": 100,
+                "track_metadata": {
+                    "artist_name": "Artist, Guest",
+                    "track_name": "Song",
+                    "release_name": "Album",
+                    "additional_info": {
+                        "duration_ms": 215500,
+                        "artist_mbids": ["mbid-guest"],
+                        "origin_url": "https://example.com/song",
+                        "submission_client": "Moosync",
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn missing_details_are_left_out() {
+        let mut song = song("Song", "Artist");
+        song.artists.as_mut().unwrap()[0].artist_mbid = Some(String::new());
+        song.album = Some(QueryableAlbum::default());
+        song.song.duration = Some(0.0);
+        song.song.url = Some("/music/song.mp3".into());
+
+        let listen = Listen::from_song(&song, None).unwrap();
+        assert_eq!(
+            serde_json::to_value(&listen).unwrap(),
+            json!({
+                "track_metadata": {
+                    "artist_name": "Artist",
+                    "track_name": "Song",
+                    "additional_info": { "submission_client": "Moosync" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn listens_require_an_artist_and_title() {
+        let err = Listen::from_song(&song("Song", ""), None).unwrap_err();
+        assert!(matches!(err, ListenBrainzError::MissingMetadata("artist")));
+        let err = Listen::from_song(&song("", "Artist"), None).unwrap_err();
+        assert!(matches!(err, ListenBrainzError::MissingMetadata("title")));
+    }
+
+    #[test]
+    fn playing_now_has_no_timestamp() {
+        let (listenbrainz, requests) = listenbrainz(vec![ok()]);
+        listenbrainz.playing_now(&song("Song", "Artist")).unwrap();
+
+        let requests = requests.borrow();
+        let (url, authorization, body) = &requests[0];
+        assert_eq!(url, "http://localhost:8080/1/submit-listens");
+        assert_eq!(authorization, "Token token");
+        assert_eq!(body["listen_type"], "playing_now");
+        assert_eq!(body["payload"].as_array().unwrap().len(), 1);
+        assert!(body["payload"][0].get("listened_at").is_none());
+        assert_eq!(body["payload"][0]["track_metadata"]["track_name"], "Song");
+    }
+
+    #[test]
+    fn single_listens_have_the_timestamp() {
+        let (listenbrainz, requests) = listenbrainz(vec![ok()]);
+        listenbrainz.single(&song("Song", "Artist"), 1000).unwrap();
+
+        let body = &requests.borrow()[0].2;
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 1000);
+    }
+
+    #[test]
+    fn imports_are_split_into_batches() {
+        let (listenbrainz, requests) = listenbrainz(vec![ok(), ok()]);
+        let listen = Listen::from_song(&song("Song", "Artist"), Some(1)).unwrap();
+        let listens = vec![listen; MAX_LISTENS_PER_IMPORT + 1];
+        listenbrainz.import(&listens).unwrap();
+
+        let requests = requests.borrow();
+        let sizes: Vec<_> = requests
+            .iter()
+            .map(|(_, _, body)| {
+                assert_eq!(body["listen_type"], "import");
+                body["payload"].as_array().unwrap().len()
+            })
+            .collect();
+        assert_eq!(sizes, [MAX_LISTENS_PER_IMPORT, 1]);
+    }
+
+    #[test]
+    fn imports_stop_at_the_first_failure() {
+        let (listenbrainz, requests) = listenbrainz(vec![Response::new(500, "")]);
+        let listen = Listen::from_song(&song("Song", "Artist"), Some(1)).unwrap();
+        let listens = vec![listen; MAX_LISTENS_PER_IMPORT + 1];
+        assert!(listenbrainz.import(&listens).is_err());
+        assert_eq!(requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn scrobbles_skip_songs_without_metadata() {
+        let host = MockHost::new();
+        let (listenbrainz, requests) = answer(ListenBrainz::new("token"), vec![ok()]);
+        let scrobbles = [
+            Scrobble {
+                song: song("Song", "Artist"),
+                timestamp: 10,
+            },
+            Scrobble {
+                song: song("", "Artist"),
+                timestamp: 20,
+            },
+        ];
+        listenbrainz.scrobble(&scrobbles).unwrap();
+
+        let body = &requests.borrow()[0].2;
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(body["payload"][0]["listened_at"], 10);
+        host.assert_logged(Level::Warn, "Skipping listen");
+    }
+
+    #[test]
+    fn token_is_read_from_the_secure_preference() {
+        let host = MockHost::new();
+        host.queue_response("GetSecure", " secret \n");
+        let (listenbrainz, requests) = answer(ListenBrainz::from_preference("token"), vec![ok()]);
+        listenbrainz.playing_now(&song("Song", "Artist")).unwrap();
+
+        host.assert_sent("GetSecure");
+        assert_eq!(requests.borrow()[0].1, "Token secret");
+    }
+
+    #[test]
+    fn missing_tokens_are_not_sent() {
+        let host = MockHost::new();
+        host.queue_no_response("GetSecure");
+        host.queue_response("GetSecure", "  ");
+        let (listenbrainz, requests) = answer(ListenBrainz::from_preference("token"), vec![]);
+        for _ in 0..2 {
+            let err = listenbrainz
+                .playing_now(&song("Song", "Artist"))
+                .unwrap_err();
+            assert!(matches!(err, ListenBrainzError::NoToken));
+        }
+
+        let (listenbrainz, _) = answer(ListenBrainz::new(""), vec![]);
+        let err = listenbrainz.single(&song("Song", "Artist"), 1).unwrap_err();
+        assert!(matches!(err, ListenBrainzError::NoToken));
+        assert!(requests.borrow().is_empty());
+    }
+
+    #[test]
+    fn api_errors_are_read_from_the_body() {
+        let (listenbrainz, _) = listenbrainz(vec![Response::new(
+            400,
+            r#"{"code": 400, "error": "Invalid listen"}"#,
+        )]);
+        let err = listenbrainz.single(&song("Song", "Artist"), 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ListenBrainzError::Api { status: 400, ref message } if message == "Invalid listen"
+        ));
+    }
+
+    fn status(status: u16) -> ListenBrainzError {
+        ListenBrainzError::Http(HttpError::Status {
+            url: API.into(),
+            status,
+            headers: HashMap::new(),
+            body: Vec::new(),
+        })
+    }
+
+    fn api(status: u16) -> ListenBrainzError {
+        ListenBrainzError::Api {
+            status,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn errors_tell_the_queue_what_to_keep() {
+        MockHost::new().queue_error("GetSecure", "keychain locked");
+        let preference = preferences::try_get_secure::<String>("token").unwrap_err();
+
+        let temporary = [
+            api(401),
+            api(408),
+            api(429),
+            api(503),
+            status(401),
+            status(503),
+            ListenBrainzError::NoToken,
+            ListenBrainzError::Preference(preference),
+        ];
+        for err in temporary {
+            let message = err.to_string();
+            assert!(
+                matches!(SubmitError::from(err), SubmitError::Temporary(_)),
+                "{}",
+                message
+            );
+        }
+
+        let rejected = [
+            api(400),
+            api(413),
+            status(400),
+            ListenBrainzError::MissingMetadata("title"),
+        ];
+        for err in rejected {
+            let message = err.to_string();
+            assert!(
+                matches!(SubmitError::from(err), SubmitError::Rejected(_)),
+                "{}",
+                message
+            );
+        }
+    }
+}
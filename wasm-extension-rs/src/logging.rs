@@ -0,0 +1,70 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Logging to the main app.
+//!
+//! The [`info!`](crate::info), [`warn!`](crate::warn) and [`error!`](crate::error)
+//! macros take the same arguments as `format!` and log through Extism. Natively,
+//! with the `testing` feature, messages are recorded by the
+//! [`MockHost`](crate::testing::MockHost) instead.
+
+/// Severity of a logged message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+#[doc(hidden)]
+#[cfg(not(all(any(test, feature = "testing"), not(target_arch = "wasm32"))))]
+pub fn log(level: Level, message: String) {
+    match level {
+        Level::Info => extism_pdk::info!("{}", message),
+        Level::Warn => extism_pdk::warn!("{}", message),
+        Level::Error => extism_pdk::error!("{}", message),
+    }
+}
+
+#[doc(hidden)]
+#[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+pub fn log(level: Level, message: String) {
+    crate::testing::log(level, message)
+}
+
+/// Logs an informational message to the main app.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Info, format!($($arg)+))
+    };
+}
+
+/// Logs a warning to the main app.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Warn, format!($($arg)+))
+    };
+}
+
+/// Logs an error to the main app.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Error, format!($($arg)+))
+    };
+}
@@ -0,0 +1,540 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Plain and time-synced lyrics.
+//!
+//! [`Provider::get_lyrics`](crate::api::Provider::get_lyrics) returns [`Lyrics`],
+//! which are either plain text or an [`Lrc`] document. LRC documents can be
+//! line-synced:
+//!
+//! ```text
+//! [ti:Song title]
+//! [offset:+250]
+//! [00:12.00]First line
+//! [00:17.20]Second line
+//! ```
+//!
+//! or word-synced, using the enhanced LRC format:
+//!
+//! ```text
+//! [00:12.00]<00:12.00>First <00:12.60>line <00:13.10>
+//! ```
+//!
+//! The main app receives plain text unless it sets the `lyrics_format` config
+//! of the plugin to `lrc`, for line-synced lyrics, or `enhanced_lrc`, for
+//! word-synced lyrics.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::extension_api::get_config;
+
+/// Config key the main app sets to the lyrics format it can display.
+const FORMAT_CONFIG_KEY: &str = "lyrics_format";
+
+/// A word of a word-synced line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LrcWord {
+    pub time: Duration,
+    /// Text of the word, including the whitespace that follows it. A word with
+    /// empty text marks the end of the previous one.
+    pub text: String,
+}
+
+/// A line of an LRC document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LrcLine {
+    pub time: Duration,
+    pub text: String,
+    /// Timing of each word, empty if the line is only line-synced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<LrcWord>,
+}
+
+impl LrcLine {
+    pub fn new(time: Duration, text: impl Into<String>) -> Self {
+        Self {
+            time,
+            text: text.into(),
+            words: vec![],
+        }
+    }
+
+    /// Creates a word-synced line from its words.
+    pub fn from_words(time: Duration, words: Vec<LrcWord>) -> Self {
+        Self {
+            time,
+            text: words.iter().map(|word| word.text.as_str()).collect(),
+            words,
+        }
+    }
+}
+
+/// A parsed LRC document.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lrc {
+    /// ID tags like `ti`, `ar`, `al` or `by`, keyed by their lowercase name.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Milliseconds to subtract from every timestamp, from the `offset` tag.
+    /// Positive values make the lyrics appear sooner.
+    #[serde(default)]
+    pub offset: i64,
+    /// Lines sorted by time.
+    pub lines: Vec<LrcLine>,
+}
+
+impl Lrc {
+    /// Parses an LRC document, including enhanced LRC word timings.
+    ///
+    /// Lines with several timestamps are repeated at each of them. Parsing never
+    /// fails: text outside of a timed line, unknown or malformed tags and a
+    /// malformed offset are ignored. A bracket that isn't a timestamp after the
+    /// timestamps of a line, like `[2x]` in `[00:12.00][2x] Chorus`, is kept as
+    /// part of the text.
+    pub fn parse(input: &str) -> Self {
+        let mut lrc = Lrc::default();
+
+        for raw in input.lines() {
+            let mut rest = raw.trim();
+            let mut times = vec![];
+
+            while let Some(tag) = rest.strip_prefix('[') {
+                let Some(end) = tag.find(']') else {
+                    break;
+                };
+                let content = &tag[..end];
+
+                if let Some(time) = parse_timestamp(content) {
+                    times.push(time);
+                } else if !times.is_empty() {
+                    break;
+                } else if let Some((key, value)) = content.split_once(':') {
+                    let key = key.trim().to_ascii_lowercase();
+                    let value = value.trim();
+                    if key == "offset" {
+                        if let Ok(offset) = value.parse() {
+                            lrc.offset = offset;
+                        }
+                    } else {
+                        lrc.tags.insert(key, value.into());
+                    }
+                }
+                rest = &tag[end + 1..];
+            }
+
+            if times.is_empty() {
+                continue;
+            }
+
+            let words = parse_words(rest);
+            for time in times {
+                lrc.lines.push(if words.is_empty() {
+                    LrcLine::new(time, rest)
+                } else {
+                    LrcLine::from_words(time, words.clone())
+                });
+            }
+        }
+
+        lrc.lines.sort_by_key(|line| line.time);
+        lrc
+    }
+
+    /// Returns whether any line has word timings.
+    pub fn is_word_synced(&self) -> bool {
+        self.lines.iter().any(|line| !line.words.is_empty())
+    }
+
+    /// Returns the document with the offset applied to every timestamp and reset to 0.
+    pub fn with_offset_applied(mut self) -> Self {
+        let shift = |time: Duration| {
+            let millis = time.as_millis() as i64 - self.offset;
+            Duration::from_millis(millis.max(0) as u64)
+        };
+        for line in &mut self.lines {
+            line.time = shift(line.time);
+            for word in &mut line.words {
+                word.time = shift(word.time);
+            }
+        }
+        self.offset = 0;
+        self
+    }
+
+    /// Returns the document without word timings.
+    pub fn to_line_synced(&self) -> Self {
+        let mut lrc = self.clone();
+        for line in &mut lrc.lines {
+            line.words.clear();
+        }
+        lrc
+    }
+
+    /// Returns the text of every line, without timestamps.
+    pub fn to_plain_text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| line.text.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl FromStr for Lrc {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s))
+    }
+}
+
+/// Serializes the document as LRC, with word timings for word-synced lines.
+impl Display for Lrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.tags {
+            writeln!(f, "[{}:{}]", key, value)?;
+        }
+        if self.offset != 0 {
+            writeln!(f, "[offset:{:+}]", self.offset)?;
+        }
+        for line in &self.lines {
+            write!(f, "[{}]", format_timestamp(line.time))?;
+            if line.words.is_empty() {
+                writeln!(f, "{}", line.text)?;
+            } else {
+                for word in &line.words {
+                    write!(f, "<{}>{}", format_timestamp(word.time), word.text)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses `mm:ss`, `mm:ss.xx` or `mm:ss:xx`, with up to three fraction digits.
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let (minutes, rest) = value.trim().split_once(':')?;
+    let (seconds, fraction) = match rest.find(['.', ':']) {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 || fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u64>().ok()? * 10u64.pow(3 - fraction.len() as u32)
+    };
+
+    Some(Duration::from_millis(
+        (minutes * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+/// Formats `mm:ss.xx`, using three fraction digits when needed to keep the time exact.
+fn format_timestamp(time: Duration) -> String {
+    let millis = time.as_millis();
+    let (minutes, seconds, millis) = (millis / 60_000, millis / 1000 % 60, millis % 1000);
+    if millis % 10 == 0 {
+        format!("{:02}:{:02}.{:02}", minutes, seconds, millis / 10)
+    } else {
+        format!("{:02}:{:02}.{:03}", minutes, seconds, millis)
+    }
+}
+
+/// Splits the text of an enhanced LRC line into its timed words. Text after a
+/// malformed timestamp stays part of the last word.
+fn parse_words(text: &str) -> Vec<LrcWord> {
+    let mut words: Vec<LrcWord> = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+        let Some(time) = parse_timestamp(&rest[start + 1..end]) else {
+            break;
+        };
+
+        if let Some(last) = words.last_mut() {
+            last.text = rest[..start].into();
+        } else if !rest[..start].trim().is_empty() {
+            // Not an enhanced LRC line, `<` is part of the text.
+            return vec![];
+        }
+        words.push(LrcWord {
+            time,
+            text: String::new(),
+        });
+        rest = &rest[end + 1..];
+    }
+
+    if let Some(last) = words.last_mut() {
+        last.text = rest.into();
+    }
+    words
+}
+
+/// Lyrics of a song.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Lyrics {
+    Plain(String),
+    Synced(Lrc),
+}
+
+impl Lyrics {
+    /// Reads lyrics that may or may not be in the LRC format.
+    ///
+    /// Text without any timed line is kept as plain text.
+    pub fn parse(text: &str) -> Self {
+        let lrc = Lrc::parse(text);
+        if lrc.lines.is_empty() {
+            Lyrics::Plain(text.into())
+        } else {
+            Lyrics::Synced(lrc)
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        matches!(self, Lyrics::Synced(_))
+    }
+
+    pub fn to_plain_text(&self) -> String {
+        match self {
+            Lyrics::Plain(text) => text.clone(),
+            Lyrics::Synced(lrc) => lrc.to_plain_text(),
+        }
+    }
+
+    /// Converts the lyrics to the format set by the main app in the
+    /// `lyrics_format` config, plain text by default.
+    pub(crate) fn into_host_format(self) -> String {
+        let format = get_config(FORMAT_CONFIG_KEY).unwrap_or_default();
+        match (self, format.as_str()) {
+            (Lyrics::Synced(lrc), "enhanced_lrc") => lrc.to_string(),
+            (Lyrics::Synced(lrc), "lrc") => lrc.to_line_synced().to_string(),
+            (lyrics, _) => lyrics.to_plain_text(),
+        }
+    }
+}
+
+impl From<String> for Lyrics {
+    fn from(text: String) -> Self {
+        Lyrics::Plain(text)
+    }
+}
+
+impl From<&str> for Lyrics {
+    fn from(text: &str) -> Self {
+        Lyrics::Plain(text.into())
+    }
+}
+
+impl From<Lrc> for Lyrics {
+    fn from(lrc: Lrc) -> Self {
+        Lyrics::Synced(lrc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn word(millis: u64, text: &str) -> LrcWord {
+        LrcWord {
+            time: ms(millis),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn parses_tags_offset_and_lines() {
+        let lrc = Lrc::parse(
+            "[ti: Song title ]\n[AR:Artist]\n[offset:-250]\nintro text\n[00:17.20]Second line\n[00:12.00]First line\n[00:20.00]",
+        );
+
+        assert_eq!(lrc.tags.get("ti").map(String::as_str), Some("Song title"));
+        assert_eq!(lrc.tags.get("ar").map(String::as_str), Some("Artist"));
+        assert_eq!(lrc.offset, -250);
+        assert_eq!(
+            lrc.lines,
+            vec![
+                LrcLine::new(ms(12_000), "First line"),
+                LrcLine::new(ms(17_200), "Second line"),
+                LrcLine::new(ms(20_000), ""),
+            ]
+        );
+        assert!(!lrc.is_word_synced());
+    }
+
+    #[test]
+    fn repeats_lines_with_several_timestamps() {
+        let lrc = Lrc::parse("[00:10.00][01:10.00]Chorus\n[00:30.00]Verse");
+        let times: Vec<_> = lrc.lines.iter().map(|line| line.time).collect();
+        assert_eq!(times, vec![ms(10_000), ms(30_000), ms(70_000)]);
+        assert_eq!(lrc.lines[2].text, "Chorus");
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let cases = [
+            ("00:12", Some(12_000)),
+            ("00:12.5", Some(12_500)),
+            ("00:12.50", Some(12_500)),
+            ("00:12.345", Some(12_345)),
+            ("00:12:34", Some(12_340)),
+            ("123:04.00", Some(7_384_000)),
+            (" 01:02.03 ", Some(62_030)),
+            ("00:60.00", None),
+            ("00:12.3456", None),
+            ("00:1a", None),
+            ("2x", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_timestamp(value), expected.map(ms), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn format_timestamp_round_trips() {
+        assert_eq!(format_timestamp(ms(12_000)), "00:12.00");
+        assert_eq!(format_timestamp(ms(62_030)), "01:02.03");
+        assert_eq!(format_timestamp(ms(62_035)), "01:02.035");
+        assert_eq!(format_timestamp(ms(7_384_000)), "123:04.00");
+
+        for millis in [0, 10, 999, 1_001, 59_990, 60_000, 3_599_999] {
+            assert_eq!(
+                parse_timestamp(&format_timestamp(ms(millis))),
+                Some(ms(millis))
+            );
+        }
+    }
+
+    #[test]
+    fn parses_word_timings() {
+        let lrc = Lrc::parse("[00:12.00]<00:12.00>First <00:12.60>line <00:13.10>");
+        assert_eq!(
+            lrc.lines,
+            vec![LrcLine::from_words(
+                ms(12_000),
+                vec![
+                    word(12_000, "First "),
+                    word(12_600, "line "),
+                    word(13_100, ""),
+                ]
+            )]
+        );
+        assert_eq!(lrc.lines[0].text, "First line ");
+        assert!(lrc.is_word_synced());
+
+        // `<` in plain text doesn't start word timings.
+        let lrc = Lrc::parse("[00:01.00]a <00:02.00> b");
+        assert_eq!(lrc.lines, vec![LrcLine::new(ms(1_000), "a <00:02.00> b")]);
+    }
+
+    #[test]
+    fn skips_malformed_tags() {
+        let lrc = Lrc::parse(
+            "[2x] Chorus\n[offset:abc]\n[00:12.00][2x] Chorus\n[0a:12]bad\n[00:15.00]<00:15.00>one <1x>two",
+        );
+        assert_eq!(lrc.offset, 0);
+        assert_eq!(
+            lrc.lines,
+            vec![
+                LrcLine::new(ms(12_000), "[2x] Chorus"),
+                LrcLine::from_words(ms(15_000), vec![word(15_000, "one <1x>two")]),
+            ]
+        );
+
+        assert!(Lyrics::parse("[2x] Chorus\n[00:01.00]Line").is_synced());
+        assert_eq!("[00:01.00]a".parse::<Lrc>().unwrap().lines.len(), 1);
+    }
+
+    #[test]
+    fn serializes_and_round_trips() {
+        let source = "[ar:Artist]\n[ti:Title]\n[offset:+250]\n[00:12.00]First line\n[00:17.205]<00:17.205>Second <00:18.00>line\n";
+        let lrc = Lrc::parse(source);
+        assert_eq!(lrc.to_string(), source);
+        assert_eq!(Lrc::parse(&lrc.to_string()), lrc);
+
+        assert_eq!(
+            lrc.to_line_synced().to_string(),
+            "[ar:Artist]\n[ti:Title]\n[offset:+250]\n[00:12.00]First line\n[00:17.205]Second line\n"
+        );
+        assert_eq!(lrc.to_plain_text(), "First line\nSecond line");
+    }
+
+    #[test]
+    fn applies_the_offset() {
+        let lrc = Lrc::parse("[offset:500]\n[00:00.20]Early\n[00:01.00]<00:01.00>a <00:01.70>b");
+        let shifted = lrc.with_offset_applied();
+        assert_eq!(shifted.offset, 0);
+        assert_eq!(shifted.lines[0].time, ms(0));
+        assert_eq!(shifted.lines[1].time, ms(500));
+        assert_eq!(
+            shifted.lines[1].words,
+            vec![word(500, "a "), word(1_200, "b")]
+        );
+
+        let lrc = Lrc::parse("[offset:-500]\n[00:01.00]Late").with_offset_applied();
+        assert_eq!(lrc.lines[0].time, ms(1_500));
+    }
+
+    #[test]
+    fn plain_text_stays_plain() {
+        assert_eq!(
+            Lyrics::parse("just words\n[not a tag"),
+            Lyrics::Plain("just words\n[not a tag".into())
+        );
+        assert_eq!(
+            Lyrics::parse("[ti:Only tags]"),
+            Lyrics::Plain("[ti:Only tags]".into())
+        );
+    }
+
+    #[test]
+    fn converts_to_the_host_format() {
+        let lyrics = Lyrics::parse("[00:01.00]<00:01.00>a <00:01.50>b");
+
+        let host = MockHost::new();
+        assert_eq!(lyrics.clone().into_host_format(), "a b");
+        host.set_config("lyrics_format", "lrc");
+        assert_eq!(lyrics.clone().into_host_format(), "[00:01.00]a b\n");
+        host.set_config("lyrics_format", "enhanced_lrc");
+        assert_eq!(
+            lyrics.into_host_format(),
+            "[00:01.00]<00:01.00>a <00:01.50>b\n"
+        );
+        assert_eq!(Lyrics::from("plain").into_host_format(), "plain");
+    }
+}
@@ -0,0 +1,307 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Typed access to the extension's preferences.
+//!
+//! These helpers wrap the raw [`extension_api`](crate::api::extension_api) preference
+//! functions and (de)serialize values with serde. A whole settings struct can be bound
+//! to a key prefix with `#[derive(Preferences)]`:
+//!
+//! ```ignore
+//! #[derive(Default, Preferences)]
+//! #[preferences(prefix = "player")]
+//! struct PlayerSettings {
+//!     volume: f64,
+//!     #[preference(secure, key = "token")]
+//!     api_token: Option<String>,
+//! }
+//!
+//! let settings = PlayerSettings::load()?; // reads "player.volume" and "player.token"
+//! ```
+
+use std::fmt::{self, Display};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use types::errors::MoosyncError;
+use types::ui::extensions::PreferenceData;
+
+use crate::api::extension_api::{self, ApiError, ApiResult};
+
+/// Error returned by the typed preference helpers.
+#[derive(Debug)]
+pub enum PreferenceError {
+    /// The main app failed to read or write the preference.
+    Host { key: String, source: ApiError },
+    /// The stored value could not be deserialized into the requested type.
+    Deserialize {
+        key: String,
+        value: Value,
+        source: serde_json::Error,
+    },
+    /// The value could not be serialized before being stored.
+    Serialize {
+        key: String,
+        source: serde_json::Error,
+    },
+}
+
+impl PreferenceError {
+    /// Key of the preference that caused the error.
+    pub fn key(&self) -> &str {
+        match self {
+            PreferenceError::Host { key, .. }
+            | PreferenceError::Deserialize { key, .. }
+            | PreferenceError::Serialize { key, .. } => key,
+        }
+    }
+}
+
+impl Display for PreferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreferenceError::Host { key, source } => {
+                write!(f, "Failed to access preference {}: {}", key, source)
+            }
+            PreferenceError::Deserialize { key, value, source } => write!(
+                f,
+                "Failed to deserialize preference {} from {}: {}",
+                key, value, source
+            ),
+            PreferenceError::Serialize { key, source } => {
+                write!(f, "Failed to serialize preference {}: {}", key, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreferenceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PreferenceError::Host { source, .. } => Some(source),
+            PreferenceError::Deserialize { source, .. }
+            | PreferenceError::Serialize { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<PreferenceError> for MoosyncError {
+    fn from(err: PreferenceError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+pub type PreferenceResult<T> = Result<T, PreferenceError>;
+
+fn read<T: DeserializeOwned>(
+    key: &str,
+    fetch: fn(PreferenceData) -> ApiResult<Value>,
+) -> PreferenceResult<Option<T>> {
+    let value = match fetch(PreferenceData {
+        key: key.to_string(),
+        value: None,
+        default_value: None,
+    }) {
+        Ok(Value::Null) | Err(ApiError::NoResponse { .. }) => return Ok(None),
+        Ok(value) => value,
+        Err(source) => {
+            return Err(PreferenceError::Host {
+                key: key.to_string(),
+                source,
+            })
+        }
+    };
+
+    serde_json::from_value(value.clone())
+        .map(Some)
+        .map_err(|source| PreferenceError::Deserialize {
+            key: key.to_string(),
+            value,
+            source,
+        })
+}
+
+fn write<T: Serialize + ?Sized>(
+    key: &str,
+    value: &T,
+    store: fn(PreferenceData) -> ApiResult<()>,
+) -> PreferenceResult<()> {
+    let value = serde_json::to_value(value).map_err(|source| PreferenceError::Serialize {
+        key: key.to_string(),
+        source,
+    })?;
+
+    store(PreferenceData {
+        key: key.to_string(),
+        value: Some(value),
+        default_value: None,
+    })
+    .map_err(|source| PreferenceError::Host {
+        key: key.to_string(),
+        source,
+    })
+}
+
+/// Reads the preference `key`, returning `None` if it isn't set.
+pub fn try_get_preference<T: DeserializeOwned>(key: &str) -> PreferenceResult<Option<T>> {
+    read(key, extension_api::get_preference)
+}
+
+/// Reads the preference `key`, returning `default` if it isn't set.
+pub fn get_preference<T: DeserializeOwned>(key: &str, default: T) -> PreferenceResult<T> {
+    Ok(try_get_preference(key)?.unwrap_or(default))
+}
+
+/// Stores `value` under the preference `key`.
+pub fn set_preference<T: Serialize + ?Sized>(key: &str, value: &T) -> PreferenceResult<()> {
+    write(key, value, extension_api::set_preference)
+}
+
+/// Reads the secure preference `key`, returning `None` if it isn't set.
+pub fn try_get_secure<T: DeserializeOwned>(key: &str) -> PreferenceResult<Option<T>> {
+    read(key, extension_api::get_secure)
+}
+
+/// Reads the secure preference `key`, returning `default` if it isn't set.
+pub fn get_secure<T: DeserializeOwned>(key: &str, default: T) -> PreferenceResult<T> {
+    Ok(try_get_secure(key)?.unwrap_or(default))
+}
+
+/// Stores `value` under the secure preference `key`.
+pub fn set_secure<T: Serialize + ?Sized>(key: &str, value: &T) -> PreferenceResult<()> {
+    write(key, value, extension_api::set_secure)
+}
+
+/// A settings struct whose fields are stored as individual preferences.
+///
+/// Usually implemented through `#[derive(Preferences)]`.
+pub trait Preferences: Sized {
+    /// Reads every field, falling back to the field's value in `Default` when unset.
+    fn load() -> PreferenceResult<Self>;
+
+    /// Stores every field.
+    fn save(&self) -> PreferenceResult<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use types::extensions::MainCommand;
+
+    use super::*;
+    use crate::testing::MockHost;
+    use crate::Preferences;
+
+    #[derive(Debug, Default, PartialEq, Preferences)]
+    #[preferences(prefix = "player")]
+    struct PlayerSettings {
+        volume: f64,
+        name: String,
+        #[preference(secure, key = "token")]
+        api_token: Option<String>,
+        #[preference(skip)]
+        history: Vec<String>,
+    }
+
+    fn sent_keys(host: &MockHost) -> Vec<(String, String)> {
+        host.sent_commands()
+            .into_iter()
+            .filter_map(|command| match command {
+                MainCommand::GetPreference(data) => Some(("get".into(), data.key)),
+                MainCommand::GetSecure(data) => Some(("get_secure".into(), data.key)),
+                MainCommand::SetPreference(data) => Some(("set".into(), data.key)),
+                MainCommand::SetSecure(data) => Some(("set_secure".into(), data.key)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unset_preferences_return_the_default() {
+        let host = MockHost::new();
+        host.queue_no_response("GetPreference");
+        host.queue_response("GetPreference", Value::Null);
+        host.queue_response("GetPreference", 0.5);
+
+        assert_eq!(get_preference("volume", 1.0).unwrap(), 1.0);
+        assert_eq!(get_preference("volume", 1.0).unwrap(), 1.0);
+        assert_eq!(get_preference("volume", 1.0).unwrap(), 0.5);
+    }
+
+    #[test]
+    fn host_errors_are_not_defaults() {
+        let host = MockHost::new();
+        host.queue_error("GetSecure", "keychain locked");
+        host.queue_response("GetPreference", "loud");
+
+        let err = try_get_secure::<String>("token").unwrap_err();
+        assert!(matches!(err, PreferenceError::Host { ref key, .. } if key == "token"));
+
+        let err = get_preference("volume", 1.0).unwrap_err();
+        assert!(matches!(err, PreferenceError::Deserialize { ref key, .. } if key == "volume"));
+    }
+
+    #[test]
+    fn derive_loads_prefixed_and_secure_keys() {
+        let host = MockHost::new();
+        host.queue_response("GetPreference", 0.5);
+        host.queue_response("GetSecure", "secret");
+
+        let settings = PlayerSettings::load().unwrap();
+        assert_eq!(
+            settings,
+            PlayerSettings {
+                volume: 0.5,
+                name: String::new(),
+                api_token: Some("secret".into()),
+                history: vec![],
+            }
+        );
+        assert_eq!(
+            sent_keys(&host),
+            [
+                ("get".to_string(), "player.volume".to_string()),
+                ("get".to_string(), "player.name".to_string()),
+                ("get_secure".to_string(), "player.token".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn derive_saves_every_field_but_skipped_ones() {
+        let host = MockHost::new();
+        let settings = PlayerSettings {
+            volume: 0.25,
+            name: "Moosync".into(),
+            api_token: None,
+            history: vec!["ignored".into()],
+        };
+        settings.save().unwrap();
+
+        assert_eq!(
+            sent_keys(&host),
+            [
+                ("set".to_string(), "player.volume".to_string()),
+                ("set".to_string(), "player.name".to_string()),
+                ("set_secure".to_string(), "player.token".to_string()),
+            ]
+        );
+        assert!(matches!(
+            &host.sent_commands()[0],
+            MainCommand::SetPreference(data) if data.value == Some(json!(0.25))
+        ));
+    }
+}
@@ -0,0 +1,634 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline queue of scrobbles.
+//!
+//! [`ScrobbleQueue`] keeps the songs passed to
+//! [`Provider::scrobble`](crate::api::Provider::scrobble) until they have been
+//! submitted, so that scrobbles made while offline are sent later instead of
+//! being lost. The extension only provides the function that submits a batch:
+//!
+//! ```ignore
+//! let queue = ScrobbleQueue::new(|batch: &[Scrobble]| {
+//!     client.post("/scrobble").json(&to_payload(batch)).send()?;
+//!     Ok(())
+//! })
+//! .with_batch_size(50)
+//! .persistent(ScrobbleStorage::store(store.namespace("scrobbles")?, "pending"))?;
+//!
+//! // In Provider::scrobble
+//! queue.push(song);
+//! ```
+//!
+//! Pending scrobbles are submitted, oldest first, whenever a song is pushed or
+//! [`ScrobbleQueue::flush`] is called. After a temporary failure, submissions
+//! are paused with an exponential backoff.
+
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use types::errors::{MoosyncError, Result as MoosyncResult};
+use types::songs::Song;
+
+use crate::api::extension_api::get_system_time;
+use crate::http::HttpError;
+use crate::preferences;
+use crate::storage::KvStore;
+use crate::{info, warn};
+
+/// A song waiting to be submitted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scrobble {
+    pub song: Song,
+    /// When the song was scrobbled, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl Scrobble {
+    /// Identifies the song, to detect the same play being scrobbled twice.
+    fn identity(&self) -> String {
+        if let Some(id) = &self.song.song._id {
+            return id.clone();
+        }
+        let artists = self
+            .song
+            .artists
+            .iter()
+            .flatten()
+            .filter_map(|artist| artist.artist_name.as_deref())
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{}|{}",
+            self.song.song.title.as_deref().unwrap_or_default(),
+            artists
+        )
+    }
+}
+
+/// Error returned by a [`ScrobbleSubmitter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The batch could not be submitted now. It is kept and submitted again later.
+    Temporary(String),
+    /// The batch was refused and would be refused again. It is dropped.
+    Rejected(String),
+}
+
+impl Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Temporary(message) => write!(f, "Scrobble submission failed: {}", message),
+            SubmitError::Rejected(message) => write!(f, "Scrobbles were rejected: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+/// Client errors other than timeouts and rate limiting won't succeed on retry,
+/// and neither will a batch that can't be encoded. An undecodable response means
+/// the batch was most likely accepted, so it isn't submitted again either.
+impl From<HttpError> for SubmitError {
+    fn from(err: HttpError) -> Self {
+        let rejected = match &err {
+            HttpError::Status { status, .. } => {
+                (400..500).contains(status) && *status != 408 && *status != 429
+            }
+            HttpError::Encode { .. } | HttpError::Decode { .. } => true,
+            _ => false,
+        };
+        if rejected {
+            SubmitError::Rejected(err.to_string())
+        } else {
+            SubmitError::Temporary(err.to_string())
+        }
+    }
+}
+
+impl From<MoosyncError> for SubmitError {
+    fn from(err: MoosyncError) -> Self {
+        SubmitError::Temporary(err.to_string())
+    }
+}
+
+/// Submits a batch of scrobbles to a service.
+///
+/// Implemented for closures taking `&[Scrobble]`.
+pub trait ScrobbleSubmitter {
+    fn submit(&self, batch: &[Scrobble]) -> Result<(), SubmitError>;
+}
+
+impl<F> ScrobbleSubmitter for F
+where
+    F: Fn(&[Scrobble]) -> Result<(), SubmitError>,
+{
+    fn submit(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        self(batch)
+    }
+}
+
+/// Where pending scrobbles are kept between instances of the extension.
+///
+/// The whole queue is written after every change, so a [`KvStore`] is the
+/// better choice. A preference is visible to the user and each write is a round
+/// trip to the main app, but needs no `permissions.paths` entry.
+#[derive(Debug, Clone)]
+pub enum ScrobbleStorage {
+    /// In a preference, rewritten whenever a song is queued or a batch is submitted.
+    Preference(String),
+    /// Under a key of a [`KvStore`].
+    Store { store: KvStore, key: String },
+}
+
+impl ScrobbleStorage {
+    pub fn preference(key: impl Into<String>) -> Self {
+        ScrobbleStorage::Preference(key.into())
+    }
+
+    pub fn store(store: KvStore, key: impl Into<String>) -> Self {
+        ScrobbleStorage::Store {
+            store,
+            key: key.into(),
+        }
+    }
+
+    fn load(&self) -> MoosyncResult<Vec<Scrobble>> {
+        Ok(match self {
+            ScrobbleStorage::Preference(key) => preferences::try_get_preference(key)?,
+            ScrobbleStorage::Store { store, key } => store.get(key)?,
+        }
+        .unwrap_or_default())
+    }
+
+    fn save(&self, pending: &[Scrobble]) -> MoosyncResult<()> {
+        match self {
+            ScrobbleStorage::Preference(key) => preferences::set_preference(key, pending)?,
+            ScrobbleStorage::Store { store, key } => store.put(key, pending)?,
+        }
+        Ok(())
+    }
+}
+
+struct QueueState {
+    pending: Vec<Scrobble>,
+    storage: Option<ScrobbleStorage>,
+    batch_size: usize,
+    max_pending: usize,
+    dedupe_window: Duration,
+    base_delay: Duration,
+    max_delay: Duration,
+    failures: u32,
+    /// No submission is attempted before this time, after a temporary failure.
+    retry_at: u64,
+}
+
+/// Queue of scrobbles waiting to be submitted.
+///
+/// A queue is a handle: clones share the same pending scrobbles.
+#[derive(Clone)]
+pub struct ScrobbleQueue {
+    state: Rc<RefCell<QueueState>>,
+    submitter: Rc<dyn ScrobbleSubmitter>,
+}
+
+impl ScrobbleQueue {
+    /// Creates an empty queue kept in memory, submitting through `submitter`.
+    pub fn new(submitter: impl ScrobbleSubmitter + 'static) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(QueueState {
+                pending: vec![],
+                storage: None,
+                batch_size: 50,
+                max_pending: 1000,
+                dedupe_window: Duration::from_secs(60),
+                base_delay: Duration::from_secs(30),
+                max_delay: Duration::from_secs(3600),
+                failures: 0,
+                retry_at: 0,
+            })),
+            submitter: Rc::new(submitter),
+        }
+    }
+
+    /// Sets how many scrobbles are submitted at once. Defaults to 50, the most
+    /// Last.fm accepts in a request.
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        self.state.borrow_mut().batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets how many scrobbles are kept while they can't be submitted. When
+    /// full, the oldest are dropped. Defaults to 1000.
+    pub fn with_max_pending(self, max_pending: usize) -> Self {
+        self.state.borrow_mut().max_pending = max_pending;
+        self
+    }
+
+    /// Sets the time within which scrobbling the same song again is ignored.
+    /// Defaults to a minute.
+    pub fn with_dedupe_window(self, window: Duration) -> Self {
+        self.state.borrow_mut().dedupe_window = window;
+        self
+    }
+
+    /// Sets the delay before submitting again after a temporary failure. It
+    /// doubles with each failure, up to `max_delay`. Defaults to 30 seconds and an hour.
+    pub fn with_backoff(self, base_delay: Duration, max_delay: Duration) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.base_delay = base_delay;
+            state.max_delay = max_delay;
+        }
+        self
+    }
+
+    /// Loads the scrobbles left pending by a previous instance from `storage`
+    /// and saves the queue there whenever it changes.
+    pub fn persistent(self, storage: ScrobbleStorage) -> MoosyncResult<Self> {
+        let stored = storage.load()?;
+        {
+            let mut state = self.state.borrow_mut();
+            let current = std::mem::take(&mut state.pending);
+            state.storage = Some(storage);
+            for scrobble in stored.into_iter().chain(current) {
+                state.insert(scrobble);
+            }
+        }
+        self.save();
+        Ok(self)
+    }
+
+    /// Queues `song`, timestamped with the current time, and submits pending
+    /// scrobbles.
+    ///
+    /// Returns false if the same song was already queued within the dedupe window.
+    pub fn push(&self, song: Song) -> bool {
+        let scrobble = Scrobble {
+            song,
+            timestamp: get_system_time(),
+        };
+        if !self.state.borrow_mut().insert(scrobble) {
+            return false;
+        }
+        self.save();
+        self.flush();
+        true
+    }
+
+    /// Submits pending scrobbles in batches, unless a previous failure asked to
+    /// wait. Returns how many were submitted.
+    pub fn flush(&self) -> usize {
+        if get_system_time() < self.state.borrow().retry_at {
+            return 0;
+        }
+
+        let mut submitted = 0;
+        loop {
+            let batch = {
+                let state = self.state.borrow();
+                let len = state.pending.len().min(state.batch_size);
+                state.pending[..len].to_vec()
+            };
+            if batch.is_empty() {
+                break;
+            }
+
+            match self.submitter.submit(&batch) {
+                Ok(()) => {
+                    submitted += batch.len();
+                    self.state.borrow_mut().failures = 0;
+                }
+                Err(SubmitError::Rejected(message)) => {
+                    warn!("Dropping {} rejected scrobbles: {}", batch.len(), message);
+                }
+                Err(SubmitError::Temporary(message)) => {
+                    let mut state = self.state.borrow_mut();
+                    state.failures += 1;
+                    let delay = state.backoff();
+                    state.retry_at = get_system_time().saturating_add(delay.as_secs());
+                    info!(
+                        "Failed to submit scrobbles, retrying in {}s: {}",
+                        delay.as_secs(),
+                        message
+                    );
+                    break;
+                }
+            }
+
+            self.state.borrow_mut().remove(&batch);
+            self.save();
+        }
+        submitted
+    }
+
+    /// Returns the scrobbles waiting to be submitted, oldest first.
+    pub fn pending(&self) -> Vec<Scrobble> {
+        self.state.borrow().pending.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.borrow().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().pending.is_empty()
+    }
+
+    fn save(&self) {
+        let state = self.state.borrow();
+        if let Some(storage) = &state.storage {
+            if let Err(e) = storage.save(&state.pending) {
+                warn!("Failed to persist pending scrobbles: {}", e);
+            }
+        }
+    }
+}
+
+impl QueueState {
+    /// Adds `scrobble` in timestamp order, unless it duplicates a pending one.
+    fn insert(&mut self, scrobble: Scrobble) -> bool {
+        let identity = scrobble.identity();
+        let window = self.dedupe_window.as_secs();
+        let duplicate = self.pending.iter().any(|pending| {
+            pending.timestamp.abs_diff(scrobble.timestamp) < window
+                && pending.identity() == identity
+        });
+        if duplicate {
+            return false;
+        }
+
+        let index = self
+            .pending
+            .partition_point(|pending| pending.timestamp <= scrobble.timestamp);
+        self.pending.insert(index, scrobble);
+
+        if self.pending.len() > self.max_pending {
+            let excess = self.pending.len() - self.max_pending;
+            warn!("Too many pending scrobbles, dropping the {} oldest", excess);
+            self.pending.drain(..excess);
+        }
+        true
+    }
+
+    /// Removes the scrobbles of a batch that was submitted or rejected.
+    fn remove(&mut self, batch: &[Scrobble]) {
+        let removed: Vec<_> = batch
+            .iter()
+            .map(|scrobble| (scrobble.timestamp, scrobble.identity()))
+            .collect();
+        self.pending
+            .retain(|pending| !removed.contains(&(pending.timestamp, pending.identity())));
+    }
+
+    fn backoff(&self) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(self.failures.saturating_sub(1)))
+            .min(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+
+    use types::entities::QueryableArtist;
+
+    use super::*;
+    use crate::testing::MockHost;
+
+    /// Titles of each submitted batch.
+    type Submitted = Rc<RefCell<Vec<Vec<String>>>>;
+
+    fn song(title: &str) -> Song {
+        let mut song = Song::default();
+        song.song.title = Some(title.into());
+        song.artists = Some(vec![QueryableArtist {
+            artist_name: Some("Artist".into()),
+            ..Default::default()
+        }]);
+        song
+    }
+
+    fn titles(scrobbles: &[Scrobble]) -> Vec<String> {
+        scrobbles
+            .iter()
+            .map(|scrobble| scrobble.song.song.title.clone().unwrap_or_default())
+            .collect()
+    }
+
+    /// A queue whose submitter returns `results` in order, then temporary failures.
+    fn queue(results: Vec<Result<(), SubmitError>>) -> (ScrobbleQueue, Submitted) {
+        let results = RefCell::new(VecDeque::from(results));
+        let submitted = Submitted::default();
+        let log = submitted.clone();
+        let queue = ScrobbleQueue::new(move |batch: &[Scrobble]| {
+            log.borrow_mut().push(titles(batch));
+            results
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Err(SubmitError::Temporary("offline".into())))
+        });
+        (queue, submitted)
+    }
+
+    fn host() -> MockHost {
+        let host = MockHost::new();
+        host.set_system_time(1000);
+        host
+    }
+
+    #[test]
+    fn submits_on_push() {
+        let _host = host();
+        let (queue, submitted) = queue(vec![Ok(()), Ok(())]);
+        assert!(queue.push(song("a")));
+        assert!(queue.push(song("b")));
+        assert!(queue.is_empty());
+        assert_eq!(*submitted.borrow(), vec![vec!["a"], vec!["b"]]);
+    }
+
+    #[test]
+    fn ignores_the_same_song_within_the_dedupe_window() {
+        let host = host();
+        let (queue, _) = queue(vec![]);
+        let queue = queue.with_backoff(Duration::from_secs(3600), Duration::from_secs(3600));
+
+        assert!(queue.push(song("a")));
+        host.set_system_time(1059);
+        assert!(!queue.push(song("a")));
+        assert!(queue.push(song("b")));
+        host.set_system_time(1060);
+        assert!(queue.push(song("a")));
+
+        let mut other_artist = song("a");
+        other_artist.artists = None;
+        assert!(queue.push(other_artist));
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[test]
+    fn keeps_pending_scrobbles_in_timestamp_order() {
+        let _host = host();
+        let store = KvStore::in_memory();
+        let stored = [("c", 30), ("a", 10), ("b", 20)].map(|(title, timestamp)| Scrobble {
+            song: song(title),
+            timestamp,
+        });
+        store.put("pending", &stored).unwrap();
+
+        let (queue, _) = queue(vec![]);
+        let queue = queue
+            .with_backoff(Duration::from_secs(3600), Duration::from_secs(3600))
+            .persistent(ScrobbleStorage::store(store, "pending"))
+            .unwrap();
+        queue.push(song("d"));
+        assert_eq!(titles(&queue.pending()), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn drops_the_oldest_when_full() {
+        let host = host();
+        let (queue, _) = queue(vec![]);
+        let queue = queue
+            .with_max_pending(2)
+            .with_backoff(Duration::from_secs(3600), Duration::from_secs(3600));
+
+        for (i, title) in ["a", "b", "c"].into_iter().enumerate() {
+            host.set_system_time(1000 + i as u64);
+            queue.push(song(title));
+        }
+        assert_eq!(titles(&queue.pending()), vec!["b", "c"]);
+        host.assert_logged(
+            crate::logging::Level::Warn,
+            "Too many pending scrobbles, dropping the 1 oldest",
+        );
+    }
+
+    #[test]
+    fn backs_off_after_temporary_failures() {
+        let host = host();
+        let (queue, submitted) = queue(vec![
+            Err(SubmitError::Temporary("down".into())),
+            Err(SubmitError::Temporary("down".into())),
+            Err(SubmitError::Temporary("down".into())),
+            Ok(()),
+        ]);
+        let queue = queue.with_backoff(Duration::from_secs(30), Duration::from_secs(100));
+
+        queue.push(song("a"));
+        assert_eq!(submitted.borrow().len(), 1);
+
+        // Waits 30s, then 60s, then the capped 100s.
+        for (now, wait) in [(1000, 30), (1030, 60), (1090, 100)] {
+            host.set_system_time(now + wait - 1);
+            assert_eq!(queue.flush(), 0);
+            host.set_system_time(now + wait);
+            let attempts = submitted.borrow().len();
+            let flushed = queue.flush();
+            assert_eq!(submitted.borrow().len(), attempts + 1);
+            if now == 1090 {
+                assert_eq!(flushed, 1);
+            } else {
+                assert_eq!(flushed, 0);
+            }
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.state.borrow().failures, 0);
+    }
+
+    #[test]
+    fn drops_rejected_batches_and_stops_at_temporary_failures() {
+        let host = host();
+        let (queue, submitted) = queue(vec![
+            Err(SubmitError::Temporary("down".into())),
+            Ok(()),
+            Err(SubmitError::Rejected("bad".into())),
+            Err(SubmitError::Temporary("down".into())),
+        ]);
+        let queue = queue.with_batch_size(2).with_dedupe_window(Duration::ZERO);
+
+        for title in ["a", "b", "c", "d", "e"] {
+            queue.push(song(title));
+        }
+        assert_eq!(queue.len(), 5);
+
+        host.set_system_time(2000);
+        assert_eq!(queue.flush(), 2);
+        assert_eq!(
+            submitted.borrow()[1..],
+            [vec!["a", "b"], vec!["c", "d"], vec!["e"]]
+        );
+        assert_eq!(titles(&queue.pending()), vec!["e"]);
+        host.assert_logged(
+            crate::logging::Level::Warn,
+            "Dropping 2 rejected scrobbles: bad",
+        );
+    }
+
+    #[test]
+    fn persists_the_queue() {
+        let _host = host();
+        let store = KvStore::in_memory();
+        let storage = ScrobbleStorage::store(store.clone(), "pending");
+        let (queue, _) = queue(vec![]);
+        let queue = queue.persistent(storage.clone()).unwrap();
+        queue.push(song("a"));
+
+        let stored: Vec<Scrobble> = store.get("pending").unwrap().unwrap();
+        assert_eq!(titles(&stored), vec!["a"]);
+
+        let (restored, submitted) = self::queue(vec![Ok(())]);
+        let restored = restored.persistent(storage).unwrap();
+        assert_eq!(restored.flush(), 1);
+        assert_eq!(*submitted.borrow(), vec![vec!["a"]]);
+        let stored: Vec<Scrobble> = store.get("pending").unwrap().unwrap();
+        assert!(stored.is_empty());
+    }
+
+    #[test]
+    fn maps_http_errors() {
+        let status = |status| HttpError::Status {
+            url: "https://example.com".into(),
+            status,
+            headers: HashMap::new(),
+            body: vec![],
+        };
+        for code in [400, 401, 403, 404] {
+            assert!(matches!(
+                SubmitError::from(status(code)),
+                SubmitError::Rejected(_)
+            ));
+        }
+        for code in [408, 429, 500, 503] {
+            assert!(matches!(
+                SubmitError::from(status(code)),
+                SubmitError::Temporary(_)
+            ));
+        }
+        let request = HttpError::Request {
+            url: "https://example.com".into(),
+            source: extism_pdk::Error::msg("offline"),
+        };
+        assert!(matches!(
+            SubmitError::from(request),
+            SubmitError::Temporary(_)
+        ));
+    }
+}
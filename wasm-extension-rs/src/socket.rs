@@ -0,0 +1,235 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Stream-oriented access to the sockets exposed by the main app.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use types::errors::{MoosyncError, Result as MoosyncResult};
+
+use crate::api::extension_api::{close_sock, open_sock, read_sock, write_sock};
+use crate::warn;
+
+fn into_io_error(err: MoosyncError) -> io::Error {
+    io::Error::other(err)
+}
+
+/// How long reads wait for data unless [`UnixStream::set_read_timeout`] is called.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bounds of the interval between polls of the main app while waiting for data.
+/// The interval doubles after every empty poll.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A connected Unix domain socket, opened through the main app.
+///
+/// Implements [`Read`] and [`Write`], so it can be wrapped in a
+/// [`BufReader`](std::io::BufReader) or used with `read_exact` / `write_all` to
+/// handle short reads and writes. The socket is closed when dropped.
+///
+/// The main app answers a read with no data both when nothing has arrived yet
+/// and when the peer has closed the connection, so an empty answer is never
+/// reported as the end of the stream. Instead, reads poll the main app until data
+/// arrives and fail with [`io::ErrorKind::TimedOut`] once the read timeout
+/// elapses, or with [`io::ErrorKind::WouldBlock`] right away in nonblocking mode.
+#[derive(Debug)]
+pub struct UnixStream {
+    sock_id: i64,
+    read_timeout: Option<Duration>,
+    nonblocking: bool,
+}
+
+impl UnixStream {
+    /// Connects to the socket at `path`.
+    pub fn connect(path: impl Into<String>) -> MoosyncResult<Self> {
+        let sock_id = open_sock(path.into())?;
+        if sock_id < 0 {
+            return Err(MoosyncError::String(format!(
+                "Failed to open socket, host returned {}",
+                sock_id
+            )));
+        }
+        Ok(Self {
+            sock_id,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            nonblocking: false,
+        })
+    }
+
+    /// Returns the id of the socket on the host side.
+    pub fn sock_id(&self) -> i64 {
+        self.sock_id
+    }
+
+    /// Sets how long reads wait for data. `None` waits indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Makes reads fail with [`io::ErrorKind::WouldBlock`] instead of waiting
+    /// when no data is available.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Closes the socket, returning any error reported by the main app.
+    pub fn close(self) -> MoosyncResult<()> {
+        let sock_id = self.sock_id;
+        std::mem::forget(self);
+        close_sock(sock_id)
+    }
+}
+
+impl Read for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut waited = Duration::ZERO;
+        let mut interval = MIN_POLL_INTERVAL;
+        loop {
+            let data = read_sock(self.sock_id, buf.len() as u64).map_err(into_io_error)?;
+            if data.len() > buf.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Host returned {} bytes for a read of {} bytes",
+                        data.len(),
+                        buf.len()
+                    ),
+                ));
+            }
+            if !data.is_empty() {
+                buf[..data.len()].copy_from_slice(&data);
+                return Ok(data.len());
+            }
+
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if let Some(timeout) = self.read_timeout {
+                if waited >= timeout {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("No data received from socket in {}ms", timeout.as_millis()),
+                    ));
+                }
+                interval = interval.min(timeout - waited);
+            }
+            std::thread::sleep(interval);
+            waited += interval;
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+}
+
+impl Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let written = write_sock(self.sock_id, buf.to_vec()).map_err(into_io_error)?;
+        match usize::try_from(written) {
+            Ok(written) if written <= buf.len() => Ok(written),
+            _ => Err(io::Error::other(format!(
+                "Failed to write {} bytes to socket, host returned {}",
+                buf.len(),
+                written
+            ))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        if let Err(e) = close_sock(self.sock_id) {
+            warn!("Failed to close socket {}: {:?}", self.sock_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+
+    const PATH: &str = "/tmp/test.sock";
+
+    #[test]
+    fn empty_reads_time_out_instead_of_ending_the_stream() {
+        let host = MockHost::new();
+        host.add_socket(PATH, vec![]);
+        let mut stream = UnixStream::connect(PATH).unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(20)));
+
+        let mut buf = [0; 4];
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        host.push_socket_data(PATH, b"data");
+        assert_eq!(stream.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"data");
+    }
+
+    #[test]
+    fn nonblocking_reads_would_block() {
+        let host = MockHost::new();
+        host.add_socket(PATH, b"ab".to_vec());
+        let mut stream = UnixStream::connect(PATH).unwrap();
+        stream.set_nonblocking(true);
+
+        let mut buf = [0; 4];
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn read_exact_fails_on_a_partial_message() {
+        let host = MockHost::new();
+        host.add_socket(PATH, b"abc".to_vec());
+        let mut stream = UnixStream::connect(PATH).unwrap();
+        stream.set_read_timeout(Some(Duration::ZERO));
+
+        let mut buf = [0; 4];
+        let err = stream.read_exact(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn close_closes_the_host_socket() {
+        let host = MockHost::new();
+        host.add_socket(PATH, vec![]);
+        let mut stream = UnixStream::connect(PATH).unwrap();
+        stream.write_all(b"bye").unwrap();
+        stream.close().unwrap();
+
+        assert_eq!(host.socket_written(PATH), b"bye");
+        assert!(host.socket_closed(PATH));
+    }
+}
@@ -0,0 +1,595 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Key-value storage for internal extension state, like sync cursors, ETags or
+//! ID mappings, kept apart from the user-visible preferences.
+//!
+//! The store lives in a directory the extension is allowed to access through the
+//! `permissions.paths` field of its manifest. With a manifest containing
+//!
+//! ```json
+//! "paths": {
+//!   "{HOME}/.local/share/moosync/my-extension": "/state"
+//! }
+//! ```
+//!
+//! the store is opened from the path it is mapped to:
+//!
+//! ```ignore
+//! let store = KvStore::open("/state")?;
+//! let sync = store.namespace("sync")?;
+//!
+//! sync.put("cursor", &cursor)?;
+//! let cursor: Option<String> = sync.get("cursor")?;
+//! for key in sync.list("playlist:")? {
+//!     // ...
+//! }
+//! ```
+//!
+//! [`KvStore::in_memory`] provides the same API without touching the filesystem,
+//! for tests.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::{de::DeserializeOwned, Serialize};
+use types::errors::MoosyncError;
+
+/// Separates nested namespaces and the key in the full name of an entry.
+const NAMESPACE_SEPARATOR: char = '/';
+
+/// Longest encoded name used as a file name. Longer names are hashed, keeping
+/// file names well within the 255 bytes most filesystems allow.
+const MAX_ENCODED_LEN: usize = 160;
+
+/// Error returned by [`KvStore`] operations.
+#[derive(Debug)]
+pub enum StorageError {
+    /// Reading or writing the store failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The value could not be serialized before being stored.
+    Serialize {
+        key: String,
+        source: serde_json::Error,
+    },
+    /// The stored value could not be deserialized into the requested type.
+    Deserialize {
+        key: String,
+        source: serde_json::Error,
+    },
+    /// Keys and namespace names cannot be empty or contain `/`.
+    InvalidKey(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io { path, source } => {
+                write!(
+                    f,
+                    "Failed to access storage at {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            StorageError::Serialize { key, source } => {
+                write!(f, "Failed to serialize stored value {}: {}", key, source)
+            }
+            StorageError::Deserialize { key, source } => {
+                write!(f, "Failed to deserialize stored value {}: {}", key, source)
+            }
+            StorageError::InvalidKey(key) => write!(
+                f,
+                "Invalid storage key {:?}: keys cannot be empty or contain '{}'",
+                key, NAMESPACE_SEPARATOR
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Io { source, .. } => Some(source),
+            StorageError::Serialize { source, .. } | StorageError::Deserialize { source, .. } => {
+                Some(source)
+            }
+            StorageError::InvalidKey(_) => None,
+        }
+    }
+}
+
+impl From<StorageError> for MoosyncError {
+    fn from(err: StorageError) -> Self {
+        MoosyncError::String(err.to_string())
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug)]
+enum Backend {
+    /// One file per entry in a directory, named after the encoded full name of
+    /// the entry. Entries with long names are stored in a file named after a
+    /// hash of the name, starting with the name itself on its own line.
+    Files(PathBuf),
+    Memory(RefCell<BTreeMap<String, Vec<u8>>>),
+}
+
+impl Backend {
+    fn read(&self, name: &str) -> StorageResult<Option<Vec<u8>>> {
+        match self {
+            Backend::Files(root) => {
+                let (path, hashed) = entry_path(root, name);
+                match fs::read(&path) {
+                    Ok(data) if hashed => Ok(split_hashed(&data)
+                        .filter(|(stored, _)| *stored == name)
+                        .map(|(_, value)| value.to_vec())),
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(source) => Err(StorageError::Io { path, source }),
+                }
+            }
+            Backend::Memory(entries) => Ok(entries.borrow().get(name).cloned()),
+        }
+    }
+
+    fn write(&self, name: &str, data: Vec<u8>) -> StorageResult<()> {
+        match self {
+            Backend::Files(root) => {
+                // Write then rename, so that a crash never leaves a partial value behind.
+                let (path, hashed) = entry_path(root, name);
+                let data = if hashed {
+                    [name.as_bytes(), b"\n", &data].concat()
+                } else {
+                    data
+                };
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, data).map_err(|source| StorageError::Io {
+                    path: tmp.clone(),
+                    source,
+                })?;
+                fs::rename(&tmp, &path).map_err(|source| StorageError::Io { path, source })
+            }
+            Backend::Memory(entries) => {
+                entries.borrow_mut().insert(name.to_string(), data);
+                Ok(())
+            }
+        }
+    }
+
+    fn delete(&self, name: &str) -> StorageResult<bool> {
+        match self {
+            Backend::Files(root) => {
+                let (path, _) = entry_path(root, name);
+                match fs::remove_file(&path) {
+                    Ok(()) => Ok(true),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                    Err(source) => Err(StorageError::Io { path, source }),
+                }
+            }
+            Backend::Memory(entries) => Ok(entries.borrow_mut().remove(name).is_some()),
+        }
+    }
+
+    /// Full names of every entry starting with `prefix`, sorted.
+    fn names(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        match self {
+            Backend::Files(root) => {
+                let entries = fs::read_dir(root).map_err(|source| StorageError::Io {
+                    path: root.clone(),
+                    source,
+                })?;
+
+                let mut names = vec![];
+                for entry in entries {
+                    let entry = entry.map_err(|source| StorageError::Io {
+                        path: root.clone(),
+                        source,
+                    })?;
+                    let file_name = entry.file_name();
+                    let Some(file_name) = file_name.to_str() else {
+                        continue;
+                    };
+                    let name = if let Some(encoded) = file_name.strip_suffix(".json") {
+                        decode(encoded)
+                    } else if file_name.ends_with(".long") {
+                        let path = entry.path();
+                        let data =
+                            fs::read(&path).map_err(|source| StorageError::Io { path, source })?;
+                        split_hashed(&data).map(|(name, _)| name.to_string())
+                    } else {
+                        None
+                    };
+                    let Some(name) = name else {
+                        continue;
+                    };
+                    if name.starts_with(prefix) {
+                        names.push(name);
+                    }
+                }
+                names.sort();
+                Ok(names)
+            }
+            Backend::Memory(entries) => Ok(entries
+                .borrow()
+                .keys()
+                .filter(|name| name.starts_with(prefix))
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+/// Returns the path of the file storing the entry `name`, and whether the file
+/// name is a hash.
+fn entry_path(root: &Path, name: &str) -> (PathBuf, bool) {
+    let encoded = encode(name);
+    if encoded.len() <= MAX_ENCODED_LEN {
+        (root.join(format!("{}.json", encoded)), false)
+    } else {
+        (root.join(format!("{:016x}.long", fnv1a(name))), true)
+    }
+}
+
+/// Percent-encodes everything but lowercase ASCII letters, digits, `-` and `_`,
+/// so that any name maps to a valid file name. Uppercase letters are encoded too,
+/// so that names differing only in case don't collide on case-insensitive
+/// filesystems.
+fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is stable across
+/// Rust versions.
+fn fnv1a(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Splits the contents of a hashed entry file into the entry name and its value.
+fn split_hashed(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|&byte| byte == b'\n')?;
+    let name = std::str::from_utf8(&data[..end]).ok()?;
+    Some((name, &data[end + 1..]))
+}
+
+fn decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn validate(key: &str) -> StorageResult<()> {
+    if key.is_empty() || key.contains(NAMESPACE_SEPARATOR) {
+        Err(StorageError::InvalidKey(key.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// A namespaced key-value store. Values are stored as JSON.
+///
+/// Clones and namespaces share the same underlying storage.
+#[derive(Debug, Clone)]
+pub struct KvStore {
+    backend: Rc<Backend>,
+    /// Full name prefix of this namespace, ending with the separator unless empty.
+    namespace: String,
+}
+
+impl KvStore {
+    /// Opens the store in the directory at `path`, creating it if needed.
+    ///
+    /// `path` must be accessible to the extension, i.e. a path mapped in the
+    /// manifest's `permissions.paths`, or inside one.
+    pub fn open(path: impl Into<PathBuf>) -> StorageResult<Self> {
+        let root = path.into();
+        fs::create_dir_all(&root).map_err(|source| StorageError::Io {
+            path: root.clone(),
+            source,
+        })?;
+        Ok(Self {
+            backend: Rc::new(Backend::Files(root)),
+            namespace: String::new(),
+        })
+    }
+
+    /// Creates an empty store kept in memory.
+    pub fn in_memory() -> Self {
+        Self {
+            backend: Rc::new(Backend::Memory(RefCell::default())),
+            namespace: String::new(),
+        }
+    }
+
+    /// Returns the store for the namespace `name` nested in this one.
+    ///
+    /// Keys in different namespaces never collide.
+    pub fn namespace(&self, name: &str) -> StorageResult<KvStore> {
+        validate(name)?;
+        Ok(Self {
+            backend: self.backend.clone(),
+            namespace: format!("{}{}{}", self.namespace, name, NAMESPACE_SEPARATOR),
+        })
+    }
+
+    fn full_name(&self, key: &str) -> StorageResult<String> {
+        validate(key)?;
+        Ok(format!("{}{}", self.namespace, key))
+    }
+
+    /// Reads the value stored under `key`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> StorageResult<Option<T>> {
+        let Some(data) = self.backend.read(&self.full_name(key)?)? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|source| StorageError::Deserialize {
+                key: key.to_string(),
+                source,
+            })
+    }
+
+    /// Stores `value` under `key`, replacing any previous value.
+    pub fn put<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> StorageResult<()> {
+        let name = self.full_name(key)?;
+        let data = serde_json::to_vec(value).map_err(|source| StorageError::Serialize {
+            key: key.to_string(),
+            source,
+        })?;
+        self.backend.write(&name, data)
+    }
+
+    /// Removes `key`, returning whether it was present.
+    pub fn delete(&self, key: &str) -> StorageResult<bool> {
+        self.backend.delete(&self.full_name(key)?)
+    }
+
+    pub fn contains(&self, key: &str) -> StorageResult<bool> {
+        Ok(self.backend.read(&self.full_name(key)?)?.is_some())
+    }
+
+    /// Returns the keys in this namespace starting with `prefix`, sorted.
+    ///
+    /// Keys of nested namespaces are not included.
+    pub fn list(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        let names = self
+            .backend
+            .names(&format!("{}{}", self.namespace, prefix))?;
+        Ok(names
+            .into_iter()
+            .filter_map(|name| {
+                let key = name.strip_prefix(&self.namespace)?;
+                (!key.contains(NAMESPACE_SEPARATOR)).then(|| key.to_string())
+            })
+            .collect())
+    }
+
+    /// Removes every key in this namespace starting with `prefix`, including
+    /// nested namespaces, and returns how many entries were removed.
+    pub fn delete_prefix(&self, prefix: &str) -> StorageResult<usize> {
+        let names = self
+            .backend
+            .names(&format!("{}{}", self.namespace, prefix))?;
+        let mut removed = 0;
+        for name in names {
+            if self.backend.delete(&name)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "moosync-edk-storage-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn check_store(store: &KvStore) {
+        assert_eq!(store.get::<String>("missing").unwrap(), None);
+        store.put("cursor", "abc").unwrap();
+        store.put("count", &3).unwrap();
+        assert_eq!(
+            store.get::<String>("cursor").unwrap().as_deref(),
+            Some("abc")
+        );
+        assert_eq!(store.get::<u32>("count").unwrap(), Some(3));
+        assert!(matches!(
+            store.get::<u32>("cursor"),
+            Err(StorageError::Deserialize { .. })
+        ));
+
+        store.put("cursor", "def").unwrap();
+        assert_eq!(
+            store.get::<String>("cursor").unwrap().as_deref(),
+            Some("def")
+        );
+        assert!(store.contains("cursor").unwrap());
+        assert!(store.delete("cursor").unwrap());
+        assert!(!store.delete("cursor").unwrap());
+        assert!(!store.contains("cursor").unwrap());
+    }
+
+    fn check_namespaces(store: &KvStore) {
+        let playlists = store.namespace("playlists").unwrap();
+        let nested = playlists.namespace("etags").unwrap();
+        store.put("a", &0).unwrap();
+        playlists.put("a", &1).unwrap();
+        playlists.put("b", &2).unwrap();
+        nested.put("a", &3).unwrap();
+        store.namespace("playlists2").unwrap().put("a", &4).unwrap();
+
+        assert_eq!(store.get::<u32>("a").unwrap(), Some(0));
+        assert_eq!(playlists.get::<u32>("a").unwrap(), Some(1));
+        assert_eq!(nested.get::<u32>("a").unwrap(), Some(3));
+
+        assert_eq!(store.list("").unwrap(), vec!["a"]);
+        assert_eq!(playlists.list("").unwrap(), vec!["a", "b"]);
+        assert_eq!(playlists.list("b").unwrap(), vec!["b"]);
+        assert_eq!(nested.list("").unwrap(), vec!["a"]);
+
+        assert_eq!(playlists.delete_prefix("").unwrap(), 3);
+        assert_eq!(nested.get::<u32>("a").unwrap(), None);
+        assert_eq!(store.get::<u32>("a").unwrap(), Some(0));
+        assert_eq!(
+            store
+                .namespace("playlists2")
+                .unwrap()
+                .get::<u32>("a")
+                .unwrap(),
+            Some(4)
+        );
+    }
+
+    fn check_key_names(store: &KvStore) {
+        // Percent-encoded, this is longer than any file name allows.
+        let long_key = format!("etag:https:%%example.com%{}", "Path%".repeat(60));
+        let keys = ["Foo", "foo", "FOO", "a.b:c d", "ünïcode", long_key.as_str()];
+        let urls = store.namespace("urls").unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            urls.put(key, &i).unwrap();
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(urls.get::<usize>(key).unwrap(), Some(i));
+        }
+
+        let mut expected = keys.to_vec();
+        expected.sort();
+        assert_eq!(urls.list("").unwrap(), expected);
+        assert_eq!(urls.list("etag:").unwrap(), vec![long_key.clone()]);
+
+        assert!(urls.delete(&long_key).unwrap());
+        assert_eq!(urls.list("etag:").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn memory_backend() {
+        check_store(&KvStore::in_memory());
+        check_namespaces(&KvStore::in_memory());
+        check_key_names(&KvStore::in_memory());
+    }
+
+    #[test]
+    fn file_backend() {
+        let dir = TempDir::new();
+        let store = KvStore::open(&dir.0).unwrap();
+        check_store(&store);
+        check_namespaces(&store.namespace("ns").unwrap());
+        check_key_names(&store);
+
+        // The entries outlive the handle.
+        store.put("kept", &true).unwrap();
+        assert_eq!(
+            KvStore::open(&dir.0).unwrap().get::<bool>("kept").unwrap(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn file_names_are_short_and_case_insensitive() {
+        let dir = TempDir::new();
+        let store = KvStore::open(&dir.0).unwrap();
+        store.put("Foo", &1).unwrap();
+        store.put("foo", &2).unwrap();
+        store.put(&"K".repeat(300), &3).unwrap();
+
+        let mut names: Vec<String> = fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| name.len() <= 255));
+        let lowercase: std::collections::HashSet<_> =
+            names.iter().map(|name| name.to_lowercase()).collect();
+        assert_eq!(lowercase.len(), 3);
+        assert!(names.contains(&"%46oo.json".to_string()));
+        assert!(names.contains(&"foo.json".to_string()));
+        assert!(names.iter().any(|name| name.ends_with(".long")));
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        for name in ["", "plain", "Mixed Case", "a/b:c", "日本", "%41"] {
+            assert_eq!(decode(&encode(name)).as_deref(), Some(name));
+        }
+        assert_eq!(decode("%4"), None);
+        assert_eq!(decode("%zz"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        let store = KvStore::in_memory();
+        assert!(matches!(
+            store.put("", &1),
+            Err(StorageError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            store.get::<u32>("a/b"),
+            Err(StorageError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            store.namespace("a/b"),
+            Err(StorageError::InvalidKey(_))
+        ));
+    }
+}
@@ -0,0 +1,499 @@
+// Moosync
+// Copyright (C) 2024, 2025  Moosync <support@moosync.app>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-process mock of the main app for testing extensions natively.
+//!
+//! Enabled by the `testing` feature on non-wasm targets. The host functions used
+//! by [`extension_api`](crate::api::extension_api) and [`socket`](crate::socket)
+//! are then served by [`MockHost`] instead of the Extism runtime, and messages
+//! logged with [`info!`](crate::info), [`warn!`](crate::warn) and
+//! [`error!`](crate::error) are recorded by it. Extension code can be exercised
+//! with a plain `cargo test`:
+//!
+//! ```ignore
+//! #[test]
+//! fn scrobbles_current_song() {
+//!     let host = MockHost::new();
+//!     host.queue_response("GetCurrentSong", Some(song.clone()));
+//!
+//!     extension.on_song_changed(SongChanged::default()).unwrap();
+//!
+//!     host.assert_sent("GetCurrentSong");
+//! }
+//! ```
+//!
+//! State is kept per thread, so tests running in parallel don't interfere.
+//! Calls that go through Extism directly, like `extism_pdk::http`,
+//! `extism_pdk::config` or `log!`, still require the wasm runtime; use
+//! [`get_config`](crate::api::extension_api::get_config) to read the config.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use extism_pdk::Error;
+use serde::Serialize;
+use serde_json::Value;
+use types::extensions::MainCommand;
+
+use crate::logging::Level;
+
+type HashFn = fn(&str, &[u8]) -> Result<Vec<u8>, String>;
+
+#[derive(Default)]
+struct SocketState {
+    path: String,
+    incoming: VecDeque<u8>,
+    written: Vec<u8>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct HostState {
+    responses: HashMap<String, VecDeque<Result<Option<Value>, Error>>>,
+    sent: Vec<MainCommand>,
+    system_time: u64,
+    hash: Option<HashFn>,
+    socket_paths: HashMap<String, Vec<u8>>,
+    sockets: Vec<SocketState>,
+    logs: Vec<(Level, String)>,
+    config: HashMap<String, String>,
+}
+
+thread_local!(
+    static HOST: RefCell<HostState> = RefCell::new(HostState::default());
+);
+
+/// Returns the name of the `MainCommand` variant, as used to queue responses.
+pub fn command_name(command: &MainCommand) -> String {
+    match serde_json::to_value(command) {
+        Ok(Value::Object(obj)) => obj.keys().next().cloned().unwrap_or_default(),
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Handle to the mock main app of the current thread.
+pub struct MockHost {
+    _private: (),
+}
+
+impl MockHost {
+    /// Resets the mock host of the current thread and returns a handle to it.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        HOST.with(|host| *host.borrow_mut() = HostState::default());
+        Self { _private: () }
+    }
+
+    /// Queues the response to the next `command`, e.g. `"GetCurrentSong"`.
+    /// Responses to the same command are returned in the order they were queued.
+    /// Commands without a queued response receive no response.
+    pub fn queue_response<T: Serialize>(&self, command: &str, response: T) {
+        let response = serde_json::to_value(response).expect("Failed to serialize mock response");
+        self.queue(command, Ok(Some(response)));
+    }
+
+    /// Queues an empty response to the next `command`.
+    pub fn queue_no_response(&self, command: &str) {
+        self.queue(command, Ok(None));
+    }
+
+    /// Makes the next `command` fail with an error reported by the main app.
+    pub fn queue_error(&self, command: &str, message: impl Into<String>) {
+        self.queue(command, Err(Error::msg(message.into())));
+    }
+
+    /// Makes the next `command` fail in the runtime, as if its response could
+    /// not be decoded.
+    pub fn queue_transport_error(&self, command: &str) {
+        let source = serde_json::from_str::<Value>("{").unwrap_err();
+        self.queue(command, Err(source.into()));
+    }
+
+    fn queue(&self, command: &str, response: Result<Option<Value>, Error>) {
+        HOST.with(|host| {
+            host.borrow_mut()
+                .responses
+                .entry(command.to_string())
+                .or_default()
+                .push_back(response)
+        });
+    }
+
+    /// Commands sent to the main app so far, in order.
+    pub fn sent_commands(&self) -> Vec<MainCommand> {
+        HOST.with(|host| host.borrow().sent.clone())
+    }
+
+    /// Names of the commands sent to the main app so far, in order.
+    pub fn sent_command_names(&self) -> Vec<String> {
+        HOST.with(|host| host.borrow().sent.iter().map(command_name).collect())
+    }
+
+    /// Panics unless `command` was sent to the main app.
+    #[track_caller]
+    pub fn assert_sent(&self, command: &str) {
+        let sent = self.sent_command_names();
+        assert!(
+            sent.iter().any(|name| name == command),
+            "Expected {} to be sent to the main app, sent commands: {:?}",
+            command,
+            sent
+        );
+    }
+
+    /// Panics unless `command` was never sent to the main app.
+    #[track_caller]
+    pub fn assert_not_sent(&self, command: &str) {
+        let sent = self.sent_command_names();
+        assert!(
+            sent.iter().all(|name| name != command),
+            "Expected {} not to be sent to the main app, sent commands: {:?}",
+            command,
+            sent
+        );
+    }
+
+    /// Clears the recorded commands.
+    pub fn clear_sent(&self) {
+        HOST.with(|host| host.borrow_mut().sent.clear());
+    }
+
+    /// Sets the time returned by `get_system_time`.
+    pub fn set_system_time(&self, time: u64) {
+        HOST.with(|host| host.borrow_mut().system_time = time);
+    }
+
+    /// Sets the config value returned by `get_config` for `key`.
+    pub fn set_config(&self, key: impl Into<String>, value: impl Into<String>) {
+        HOST.with(|host| {
+            host.borrow_mut().config.insert(key.into(), value.into());
+        });
+    }
+
+    /// Sets the function used to answer `gen_hash`.
+    pub fn set_hash_fn(&self, hash: HashFn) {
+        HOST.with(|host| host.borrow_mut().hash = Some(hash));
+    }
+
+    /// Messages logged so far, in order.
+    pub fn logs(&self) -> Vec<(Level, String)> {
+        HOST.with(|host| host.borrow().logs.clone())
+    }
+
+    /// Panics unless a message containing `text` was logged at `level`.
+    #[track_caller]
+    pub fn assert_logged(&self, level: Level, text: &str) {
+        let logs = self.logs();
+        assert!(
+            logs.iter()
+                .any(|(logged, message)| *logged == level && message.contains(text)),
+            "Expected a {:?} message containing {:?}, logged messages: {:?}",
+            level,
+            text,
+            logs
+        );
+    }
+
+    /// Makes `path` available to `open_sock`. Every connection to it first
+    /// reads `incoming`.
+    pub fn add_socket(&self, path: impl Into<String>, incoming: Vec<u8>) {
+        HOST.with(|host| {
+            host.borrow_mut().socket_paths.insert(path.into(), incoming);
+        });
+    }
+
+    /// Appends data to be read from the most recent connection to `path`.
+    pub fn push_socket_data(&self, path: &str, data: &[u8]) {
+        HOST.with(|host| {
+            if let Some(socket) = host
+                .borrow_mut()
+                .sockets
+                .iter_mut()
+                .rev()
+                .find(|socket| socket.path == path)
+            {
+                socket.incoming.extend(data);
+            }
+        });
+    }
+
+    /// Data written to every connection to `path`, in order.
+    pub fn socket_written(&self, path: &str) -> Vec<u8> {
+        HOST.with(|host| {
+            host.borrow()
+                .sockets
+                .iter()
+                .filter(|socket| socket.path == path)
+                .flat_map(|socket| socket.written.clone())
+                .collect()
+        })
+    }
+
+    /// Checks whether every connection to `path` was closed.
+    pub fn socket_closed(&self, path: &str) -> bool {
+        HOST.with(|host| {
+            host.borrow()
+                .sockets
+                .iter()
+                .filter(|socket| socket.path == path)
+                .all(|socket| socket.closed)
+        })
+    }
+}
+
+fn with_socket<T>(sock_id: i64, f: impl FnOnce(&mut SocketState) -> T) -> Result<T, Error> {
+    HOST.with(|host| {
+        let mut host = host.borrow_mut();
+        let socket = usize::try_from(sock_id)
+            .ok()
+            .and_then(|idx| host.sockets.get_mut(idx))
+            .filter(|socket| !socket.closed)
+            .ok_or_else(|| Error::msg(format!("Socket {} is not open", sock_id)))?;
+        Ok(f(socket))
+    })
+}
+
+pub(crate) fn log(level: Level, message: String) {
+    HOST.with(|host| host.borrow_mut().logs.push((level, message)));
+}
+
+// Replacements for the functions imported from "ExtismHost", with the same
+// signatures as the ones generated by `#[host_fn]`.
+
+pub(crate) unsafe fn send_main_command(command: MainCommand) -> Result<Option<Value>, Error> {
+    let name = command_name(&command);
+    HOST.with(|host| {
+        let mut host = host.borrow_mut();
+        host.sent.push(command);
+        match host
+            .responses
+            .get_mut(&name)
+            .and_then(|queue| queue.pop_front())
+        {
+            Some(response) => response,
+            None => Ok(None),
+        }
+    })
+}
+
+pub(crate) fn config_get(key: &str) -> Result<Option<String>, Error> {
+    Ok(HOST.with(|host| host.borrow().config.get(key).cloned()))
+}
+
+pub(crate) unsafe fn system_time() -> Result<u64, Error> {
+    Ok(HOST.with(|host| host.borrow().system_time))
+}
+
+pub(crate) unsafe fn hash(hash_type: String, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let hash = HOST.with(|host| host.borrow().hash);
+    match hash {
+        Some(hash) => hash(&hash_type, &data).map_err(Error::msg),
+        None => Err(Error::msg("No hash function set on the mock host")),
+    }
+}
+
+pub(crate) unsafe fn open_clientfd(path: String) -> Result<i64, Error> {
+    HOST.with(|host| {
+        let mut host = host.borrow_mut();
+        let incoming = host
+            .socket_paths
+            .get(&path)
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("No socket at {}", path)))?;
+        host.sockets.push(SocketState {
+            path,
+            incoming: incoming.into(),
+            ..Default::default()
+        });
+        Ok(host.sockets.len() as i64 - 1)
+    })
+}
+
+pub(crate) unsafe fn write_sock(sock_id: i64, buf: Vec<u8>) -> Result<i64, Error> {
+    with_socket(sock_id, |socket| {
+        socket.written.extend_from_slice(&buf);
+        buf.len() as i64
+    })
+}
+
+pub(crate) unsafe fn read_sock(sock_id: i64, read_len: u64) -> Result<Vec<u8>, Error> {
+    with_socket(sock_id, |socket| {
+        let len = (read_len as usize).min(socket.incoming.len());
+        socket.incoming.drain(..len).collect()
+    })
+}
+
+pub(crate) unsafe fn close_sock(sock_id: i64) -> Result<i64, Error> {
+    with_socket(sock_id, |socket| {
+        socket.closed = true;
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use serde_json::json;
+    use types::extensions::MainCommand;
+    use types::ui::extensions::PreferenceData;
+
+    use super::*;
+    use crate::api::extension_api::{self, ApiError};
+    use crate::socket::UnixStream;
+
+    fn preference(key: &str) -> PreferenceData {
+        PreferenceData {
+            key: key.into(),
+            value: None,
+            default_value: None,
+        }
+    }
+
+    #[test]
+    fn responses_are_returned_in_order() {
+        let host = MockHost::new();
+        host.queue_response("GetVolume", 10.0);
+        host.queue_response("GetVolume", 20.0);
+
+        assert_eq!(extension_api::get_volume().unwrap(), 10.0);
+        assert_eq!(extension_api::get_volume().unwrap(), 20.0);
+        assert!(matches!(
+            extension_api::get_volume(),
+            Err(ApiError::NoResponse {
+                command: "GetVolume"
+            })
+        ));
+        assert_eq!(host.sent_command_names(), ["GetVolume"; 3]);
+    }
+
+    #[test]
+    fn queued_errors_fail_the_call() {
+        let host = MockHost::new();
+        host.queue_error("GetPreference", "permission denied");
+        host.queue_transport_error("GetPreference");
+
+        let err = extension_api::get_preference(preference("volume")).unwrap_err();
+        assert!(matches!(
+            &err,
+            ApiError::Host {
+                command: "GetPreference",
+                message,
+            } if message == "permission denied"
+        ));
+        let err = extension_api::get_preference(preference("volume")).unwrap_err();
+        assert!(matches!(
+            err,
+            ApiError::Transport {
+                command: "GetPreference",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn records_sent_commands() {
+        let host = MockHost::new();
+        host.queue_no_response("SetPreference");
+        extension_api::set_preference(PreferenceData {
+            value: Some(json!(1)),
+            ..preference("volume")
+        })
+        .unwrap();
+
+        host.assert_sent("SetPreference");
+        host.assert_not_sent("GetPreference");
+        assert!(matches!(
+            &host.sent_commands()[..],
+            [MainCommand::SetPreference(data)] if data.key == "volume"
+        ));
+
+        host.clear_sent();
+        assert!(host.sent_commands().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected GetTime to be sent")]
+    fn assert_sent_panics_for_missing_commands() {
+        MockHost::new().assert_sent("GetTime");
+    }
+
+    #[test]
+    fn new_resets_the_host() {
+        let host = MockHost::new();
+        host.queue_response("GetVolume", 10.0);
+        host.set_system_time(42);
+
+        let host = MockHost::new();
+        assert_eq!(extension_api::get_system_time(), 0);
+        assert!(extension_api::get_volume().is_err());
+        assert_eq!(host.sent_command_names(), ["GetVolume"]);
+    }
+
+    #[test]
+    fn serves_system_time_and_hashes() {
+        let host = MockHost::new();
+        host.set_system_time(1_700_000_000);
+        assert_eq!(extension_api::get_system_time(), 1_700_000_000);
+
+        assert!(extension_api::gen_hash("md5".into(), b"data".to_vec()).is_err());
+        host.set_hash_fn(|hash_type, data| Ok([hash_type.as_bytes(), b":", data].concat()));
+        assert_eq!(
+            extension_api::gen_hash("md5".into(), b"data".to_vec()).unwrap(),
+            b"md5:data"
+        );
+    }
+
+    #[test]
+    fn serves_sockets() {
+        let host = MockHost::new();
+        assert!(UnixStream::connect("/tmp/missing").is_err());
+
+        host.add_socket("/tmp/test.sock", b"hello".to_vec());
+        let mut stream = UnixStream::connect("/tmp/test.sock").unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        host.push_socket_data("/tmp/test.sock", b"again");
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"again");
+
+        stream.write_all(b"ping").unwrap();
+        assert_eq!(host.socket_written("/tmp/test.sock"), b"ping");
+        assert!(!host.socket_closed("/tmp/test.sock"));
+
+        drop(stream);
+        assert!(host.socket_closed("/tmp/test.sock"));
+    }
+
+    #[test]
+    fn records_logs() {
+        let host = MockHost::new();
+        crate::info!("connected to {}", "host");
+        crate::warn!("retrying");
+
+        assert_eq!(
+            host.logs(),
+            [
+                (Level::Info, "connected to host".to_string()),
+                (Level::Warn, "retrying".to_string())
+            ]
+        );
+        host.assert_logged(Level::Warn, "retry");
+    }
+}